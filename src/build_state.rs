@@ -0,0 +1,319 @@
+//! This module contains the build state recorded between builds.
+//!
+//! The state is a single versioned file under `ruxos_bld/`, rewritten atomically (temp file and rename)
+//! each time a target finishes, so an interrupted build never leaves it half written.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use crate::hasher;
+use crate::utils::{log, LogLevel};
+use crate::error::{Result, RuxgoError};
+
+/// Version of the layout of the state file, a state with another version is discarded
+const STATE_VERSION: u32 = 3;
+#[cfg(target_os = "windows")]
+static STATE_PATH: &str = "ruxos_bld/build_state.win32.json";
+#[cfg(target_os = "linux")]
+static STATE_PATH: &str = "ruxos_bld/build_state.linux.json";
+
+/// The content hash of a file along with the metadata it was computed from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub hash: String,
+    /// Modification time in nanoseconds since the unix epoch
+    pub mtime: u64,
+    pub size: u64,
+}
+
+/// What an object file was built from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectState {
+    /// The compile command, along with the identity of the compiler
+    pub command: String,
+    /// The source file and the headers it depends on, with their content hashes at compile time
+    pub inputs: BTreeMap<String, String>,
+}
+
+/// What a target binary was linked from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkState {
+    /// The link command, along with the identity of the linker
+    pub command: String,
+    /// The files read by the linker, with their content hashes at link time
+    pub inputs: BTreeMap<String, String>,
+}
+
+/// Represents everything ruxgo knows about the previous builds
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildState {
+    version: u32,
+    /// Content hashes of the files seen by the previous builds
+    files: HashMap<String, FileStamp>,
+    /// Objects, by path
+    objects: HashMap<String, ObjectState>,
+    /// Target binaries, by path
+    links: HashMap<String, LinkState>,
+}
+
+impl Default for BuildState {
+    fn default() -> Self {
+        BuildState {
+            version: STATE_VERSION,
+            files: HashMap::new(),
+            objects: HashMap::new(),
+            links: HashMap::new(),
+        }
+    }
+}
+
+/// Locks the build state of the local project, loading it on first use
+///
+/// The lock is only meant to read or record entries, files are hashed with `hash` without holding it
+pub fn lock() -> MutexGuard<'static, BuildState> {
+    static STATE: OnceLock<Mutex<BuildState>> = OnceLock::new();
+    STATE
+        .get_or_init(|| Mutex::new(BuildState::load(STATE_PATH)))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Returns the current content hash of a file, or None if it can't be read
+///
+/// Each file is checked at most once per invocation, and only rehashed if its mtime or size changed.
+/// Concurrent jobs wait for the job already hashing a file, the build state is not locked meanwhile.
+/// # Arguments
+/// * `path` - The path of the file
+pub fn hash(path: &str) -> Option<String> {
+    let cell = checked().lock().unwrap().entry(path.to_string()).or_default().clone();
+    cell.get_or_init(|| stamp(path)).clone()
+}
+
+/// Returns the current content hash of a file, like `hash` but even if it was already checked,
+/// for the files this invocation writes, eg: the objects and binaries linked by a later target
+/// # Arguments
+/// * `path` - The path of the file
+pub fn rehash(path: &str) -> Option<String> {
+    checked().lock().unwrap().remove(path);
+    hash(path)
+}
+
+/// The hash of a file checked by this invocation, set once hashed, None if the file can't be read
+type Checked = Arc<OnceLock<Option<String>>>;
+
+/// Returns the hashes already checked by this invocation
+fn checked() -> &'static Mutex<HashMap<String, Checked>> {
+    static CHECKED: OnceLock<Mutex<HashMap<String, Checked>>> = OnceLock::new();
+    CHECKED.get_or_init(Default::default)
+}
+
+/// Stats a file and returns its hash, reusing the recorded one if the file looks untouched
+fn stamp(path: &str) -> Option<String> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            lock().files.remove(path);
+            return None;
+        }
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64);
+    let size = metadata.len();
+    if let Some(stamp) = lock().files.get(path) {
+        if stamp.mtime == mtime && stamp.size == size && mtime != 0 {
+            return Some(stamp.hash.clone());
+        }
+    }
+    let hash = hasher::hash_file(path)?;
+    lock().files.insert(path.to_string(), FileStamp { hash: hash.clone(), mtime, size });
+    Some(hash)
+}
+
+impl BuildState {
+    /// Loads the state from a file, a missing, corrupted or outdated state gives an empty one
+    /// # Arguments
+    /// * `path` - The path of the state file
+    fn load(path: &str) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return BuildState::default(),
+        };
+        match serde_json::from_str::<BuildState>(&contents) {
+            Ok(state) if state.version == STATE_VERSION => state,
+            Ok(state) => {
+                log(LogLevel::Warn, &format!(
+                    "Build state {} has version {}, expected {}, rebuilding everything", path, state.version, STATE_VERSION
+                ));
+                BuildState::default()
+            }
+            Err(why) => {
+                log(LogLevel::Warn, &format!("Build state {} is corrupted ({}), rebuilding everything", path, why));
+                BuildState::default()
+            }
+        }
+    }
+
+    /// Writes the state of the local project
+    pub fn save(&self) -> Result<()> {
+        self.write(STATE_PATH)
+    }
+
+    /// Writes the state atomically: to a temp file first, which then replaces the state file
+    /// # Arguments
+    /// * `path` - The path of the state file
+    fn write(&self, path: &str) -> Result<()> {
+        let parent = Path::new(path).parent().unwrap_or(Path::new("."));
+        if !parent.exists() {
+            fs::create_dir_all(parent)
+                .map_err(|why| RuxgoError::io(format!("Failed to create {}", parent.display()), why))?;
+        }
+        let contents = serde_json::to_string(self)
+            .map_err(|why| RuxgoError::Config(format!("Failed to serialize build state: {}", why)))?;
+        let tmp_path = format!("{}.tmp", path);
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)
+            .map_err(|why| RuxgoError::io(format!("Failed to open file: {}", tmp_path), why))?;
+        file.write_all(contents.as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|why| RuxgoError::io(format!("Failed to write file: {}", tmp_path), why))?;
+        fs::rename(&tmp_path, path)
+            .map_err(|why| RuxgoError::io(format!("Failed to replace build state: {}", path), why))
+    }
+
+    /// Returns what an object was built from by the previous builds
+    pub fn object(&self, obj_path: &str) -> Option<&ObjectState> {
+        self.objects.get(obj_path)
+    }
+
+    /// Records what an object was built from
+    pub fn record_object(&mut self, obj_path: &str, object: ObjectState) {
+        self.objects.insert(obj_path.to_string(), object);
+    }
+
+    /// Returns what a target binary was linked from by the previous builds
+    pub fn link(&self, bin_path: &str) -> Option<&LinkState> {
+        self.links.get(bin_path)
+    }
+
+    /// Records what a target binary was linked from
+    pub fn record_link(&mut self, bin_path: &str, link: LinkState) {
+        self.links.insert(bin_path.to_string(), link);
+    }
+
+    /// Forgets a target, so that it is fully rebuilt next time
+    /// # Arguments
+    /// * `obj_dir` - The directory holding the objects of the target
+    /// * `bin_path` - The binary of the target
+    pub fn forget_target(&mut self, obj_dir: &str, bin_path: &str) {
+        let obj_dir = format!("{}/", obj_dir.trim_end_matches('/'));
+        self.objects.retain(|obj_path, _| !obj_path.starts_with(&obj_dir));
+        self.links.remove(bin_path);
+    }
+
+    /// Forgets every object and binary, keeping only the file hashes
+    pub fn forget_all(&mut self) {
+        self.objects.clear();
+        self.links.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("ruxgo-{}-state-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("build_state.json").to_str().unwrap().to_string()
+    }
+
+    fn object(command: &str) -> ObjectState {
+        ObjectState { command: command.to_string(), inputs: BTreeMap::new() }
+    }
+
+    fn link(command: &str) -> LinkState {
+        LinkState { command: command.to_string(), inputs: BTreeMap::new() }
+    }
+
+    #[test]
+    fn saved_state_loads_back_and_leaves_no_temp_file() {
+        let path = state_path("save");
+        let mut state = BuildState::default();
+        state.record_object("obj/main/main.c.o", object("gcc -c main.c"));
+        state.record_link("bin/main", link("gcc -o bin/main"));
+        state.write(&path).unwrap();
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let state = BuildState::load(&path);
+        assert_eq!(state.object("obj/main/main.c.o").unwrap().command, "gcc -c main.c");
+        assert_eq!(state.link("bin/main").unwrap().command, "gcc -o bin/main");
+    }
+
+    #[test]
+    fn truncated_or_garbage_state_loads_empty() {
+        let path = state_path("corrupted");
+        let mut state = BuildState::default();
+        state.record_object("obj/main/main.c.o", object("gcc -c main.c"));
+        state.write(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+        assert!(BuildState::load(&path).objects.is_empty());
+
+        fs::write(&path, "\0\0garbage").unwrap();
+        assert!(BuildState::load(&path).objects.is_empty());
+        assert!(BuildState::load(&format!("{}.missing", path)).objects.is_empty());
+    }
+
+    #[test]
+    fn state_of_another_version_is_discarded() {
+        let path = state_path("version");
+        let mut state = BuildState { version: STATE_VERSION + 1, ..BuildState::default() };
+        state.record_object("obj/main/main.c.o", object("gcc -c main.c"));
+        state.write(&path).unwrap();
+        let state = BuildState::load(&path);
+        assert_eq!(state.version, STATE_VERSION);
+        assert!(state.objects.is_empty());
+    }
+
+    #[test]
+    fn forget_target_removes_only_its_own_entries() {
+        let mut state = BuildState::default();
+        state.record_object("obj/liba/a.c.o", object("gcc -c a.c"));
+        state.record_object("obj/liba/net/a.c.o", object("gcc -c net/a.c"));
+        // shares the prefix of the obj dir of liba, but not the dir itself
+        state.record_object("obj/libab/b.c.o", object("gcc -c b.c"));
+        state.record_link("bin/liba.a", link("ar rcs bin/liba.a"));
+        state.record_link("bin/libab.a", link("ar rcs bin/libab.a"));
+
+        state.forget_target("obj/liba", "bin/liba.a");
+        assert_eq!(state.objects.keys().collect::<Vec<_>>(), ["obj/libab/b.c.o"]);
+        assert_eq!(state.links.keys().collect::<Vec<_>>(), ["bin/libab.a"]);
+    }
+
+    #[test]
+    fn untouched_files_reuse_their_recorded_hash() {
+        let path = state_path("stamps");
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        let src = format!("{}.c", path);
+        fs::write(&src, "int main(void) { return 0; }\n").unwrap();
+        assert_eq!(hash(&src), hasher::hash_file(&src));
+
+        // same mtime and size, the file is not read again
+        lock().files.get_mut(&src).unwrap().hash = String::from("recorded");
+        assert_eq!(rehash(&src).unwrap(), "recorded");
+
+        fs::write(&src, "int main(void) { return 1 + 1; }\n").unwrap();
+        // checked once per invocation
+        assert_eq!(hash(&src).unwrap(), "recorded");
+        assert_eq!(rehash(&src), hasher::hash_file(&src));
+
+        fs::remove_file(&src).unwrap();
+        assert_eq!(rehash(&src), None);
+        assert!(!lock().files.contains_key(&src));
+    }
+}
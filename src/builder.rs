@@ -1,16 +1,25 @@
 //! This module contains the build related functions
 
 use crate::features::cfg_feat;
-use crate::utils::{BuildConfig, TargetConfig, Package, log, LogLevel, OSConfig};
-use std::path::{Path, PathBuf};
+use crate::utils::{BuildConfig, TargetConfig, Package, log, LogLevel, OSConfig, default_src_extensions};
+use std::path::{Component, Path, PathBuf};
 use std::io::{Read, Write};
 use std::fs;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::process::Command;
-use crate::hasher;
+use crate::process::{self, Cmd};
+use crate::build_state::{self, LinkState, ObjectState};
+use crate::cache::{self, ObjectCache};
+use crate::remote_cache;
+use crate::depfile;
+use crate::diagnostics;
+use crate::jobs;
+use crate::explain::{self, Reason};
+use crate::timings;
+use crate::error::{Result, RuxgoError};
 use rayon::prelude::*;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use indicatif::{ProgressBar, ProgressStyle};
 use colored::Colorize;
 
@@ -38,20 +47,110 @@ pub struct Target<'a> {
     dependant_includes: HashMap<String, Vec<String>>,
     pub bin_path: String,
     pub elf_path: String,
-    hash_file_path: String,
-    path_hash: HashMap<String, String>,
     dependant_libs: Vec<Target<'a>>,
+    /// The flags of the os added to the command of every source file, see `os_cflags`
+    os_cflags: Vec<String>,
+    /// The precompiled header of the target, if any
+    pch: Option<Src>,
     packages: &'a Vec<Package>,
 }
 
+/// Returns the identity of a tool, the first line it prints for `--version`
+///
+/// The identity is computed once per tool and invocation, concurrent jobs wait for the first one
+fn toolchain_identity(tool: &str) -> String {
+    static IDENTITIES: OnceLock<Mutex<HashMap<String, Arc<OnceLock<String>>>>> = OnceLock::new();
+    let identity = IDENTITIES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap()
+        .entry(tool.to_string())
+        .or_default()
+        .clone();
+    identity.get_or_init(|| match Command::new(tool).arg("--version").output() {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            stdout.lines().chain(stderr.lines()).next().unwrap_or_default().trim().to_string()
+        }
+        Err(_) => String::from("unknown"),
+    }).clone()
+}
+
+/// Returns a command as recorded in the build state: the identity of the tool running it, then the command
+fn command_record(cmd: &Cmd) -> String {
+    format!("{}\t{}", toolchain_identity(&cmd.program().to_string_lossy()), cmd)
+}
+
+/// Returns the flags of the os added to the command of every source file, none if not running on the os
+/// # Arguments
+/// * `os_config` - The os configuration
+fn os_cflags(os_config: &OSConfig) -> Result<Vec<String>> {
+    let mut cflags = Vec::new();
+    if os_config.name.is_empty() {
+        return Ok(cflags);
+    }
+    if os_config.ulib == "ruxlibc" {
+        let (_, lib_feats) = cfg_feat(os_config)?;
+        // generate the preprocessing macro definition
+        for lib_feat in lib_feats {
+            let processed_lib_feat = lib_feat.to_uppercase().replace("-", "_");
+            cflags.push(format!("-DRUX_CONFIG_{}", &processed_lib_feat));
+        }
+        cflags.push(format!("-DRUX_CONFIG_{}", os_config.platform.log.to_uppercase()));
+        cflags.extend(["-nostdinc", "-fno-builtin", "-ffreestanding", "-Wall"].map(String::from));
+        cflags.push(format!("-I{}", RUXLIBC_INC));
+    } else if os_config.ulib == "ruxmusl" {
+        cflags.extend(["-nostdinc", "-fno-builtin", "-ffreestanding", "-Wall"].map(String::from));
+        cflags.push(format!("-I{}", RUXMUSL_INC));
+    }
+    if os_config.platform.mode == "release" {
+        cflags.push(String::from("-O3"));
+    }
+    if os_config.platform.arch == "riscv64" {
+        cflags.extend(["-march=rv64gc", "-mabi=lp64d", "-mcmodel=medany"].map(String::from));
+    }
+    if !os_config.features.contains(&"fp_simd".to_string()) {
+        if os_config.platform.arch == "x86_64" {
+            cflags.push(String::from("-mno-sse"));
+        } else if os_config.platform.arch == "aarch64" {
+            cflags.push(String::from("-mgeneral-regs-only"));
+        }
+    }
+    Ok(cflags)
+}
+
+/// Returns the progress bar shown while compiling `len` source files
+pub(crate) fn compile_progress_bar(len: usize) -> ProgressBar {
+    let template = format!("    {}{}", "Compiling :".cyan(), "[{bar:40.}] {pos}/{len} ({percent}%) {msg}[{elapsed_precise}] ");
+    let progress_bar = ProgressBar::new(len as u64);
+    progress_bar.set_style(ProgressStyle::with_template(&template)
+        .unwrap()
+        .progress_chars("=>-"));
+    progress_bar
+}
+
+/// What a target has to build, see `Target::prepare`
+#[derive(Debug, Clone)]
+pub(crate) struct Plan {
+    /// The indices of the source files to compile
+    pub srcs: Vec<usize>,
+    /// Whether the precompiled header has to be compiled, before any source file
+    pub pch: bool,
+}
+
 /// Represents a source file (A single C or Cpp file)
 #[derive(Debug)]
 struct Src {
     path: String,
     name: String,
     obj_name: String,
-    bin_path: String,  // consider change to obj_path
+    dep_name: String,
+    /// Language of the source, named like the `-x` option of the compiler
+    language: String,
     dependant_includes: Vec<String>,
+    /// The header included first, through the precompiled header of the target
+    pch: Option<String>,
 }
 
 impl<'a> Target<'a> {
@@ -67,52 +166,22 @@ impl<'a> Target<'a> {
         target_config: &'a TargetConfig, 
         targets: &'a Vec<TargetConfig>, 
         packages: &'a Vec<Package>
-    ) -> Self {
+    ) -> Result<Self> {
         let srcs = Vec::new();
         let dependant_includes: HashMap<String, Vec<String>> = HashMap::new();
-        let mut bin_path = String::new();
-        bin_path.push_str(BUILD_DIR);
-        bin_path.push_str("/");
-        bin_path.push_str(&target_config.name);
-        let mut elf_path = String::new();
-        #[cfg(target_os = "windows")]
-        if target_config.typ == "exe" {
-            bin_path.push_str(".exe");
-        } else if target_config.typ == "dll" {
-            bin_path.push_str(".dll");
-        }
-        else if target_config.typ == "static" {
-            bin_path.push_str(".lib");
-        }
-        #[cfg(target_os = "linux")]
-        if target_config.typ == "exe" {
-            elf_path = bin_path.clone();
-            bin_path.push_str(".bin");
-            elf_path.push_str(".elf");
-        } else if target_config.typ == "dll" {
-            bin_path.push_str(".so");
-        } else if target_config.typ == "static" {
-            bin_path.push_str(".a");
-        } else if target_config.typ == "object" {
-            bin_path.push_str(".o");
-        }
-        #[cfg(target_os = "windows")]
-        let hash_file_path = format!("ruxos_bld/{}.win32.hash", &target_config.name);
-        #[cfg(target_os = "linux")]
-        let hash_file_path = format!("ruxos_bld/{}.linux.hash", &target_config.name);
-        let path_hash = hasher::load_hashes_from_file(&hash_file_path);
+        let (bin_path, elf_path) = Target::bin_paths(target_config);
         let mut dependant_libs = Vec::new();
         // add dependant libs
         for dependant_lib in &target_config.deps {
             for target in targets {
                 if target.name == *dependant_lib {
-                    dependant_libs.push(Target::new(build_config, os_config, target, targets, packages));
+                    dependant_libs.push(Target::new(build_config, os_config, target, targets, packages)?);
                 }
             }
             for pkg in packages {
                 for target in &pkg.target_configs {
                     if target.name == *dependant_lib {
-                        dependant_libs.push(Target::new(&pkg.build_config, os_config, target, &pkg.target_configs, &pkg.sub_packages));
+                        dependant_libs.push(Target::new(&pkg.build_config, os_config, target, &pkg.target_configs, &pkg.sub_packages)?);
                     }
                 }
             }
@@ -120,36 +189,34 @@ impl<'a> Target<'a> {
         // check types of the dependant libs
         for dep_lib in &dependant_libs {
             if dep_lib.target_config.typ != "dll" && dep_lib.target_config.typ != "static" && dep_lib.target_config.typ != "object" {
-                log(LogLevel::Error, "Can add only dlls, static or object libraries as dependant libs");
-                log(LogLevel::Error, &format!("Target: {} is not a dll, static or object library", dep_lib.target_config.name));
-                log(LogLevel::Error, &format!("Target: {} is a {}", dep_lib.target_config.name, dep_lib.target_config.typ));
-                std::process::exit(1);
+                return Err(RuxgoError::Config(format!(
+                    "Can add only dlls, static or object libraries as dependant libs, but target: {} is a {}",
+                    dep_lib.target_config.name, dep_lib.target_config.typ
+                )));
             }
             else {
                 log(LogLevel::Info, &format!("Adding dependant lib: {}", dep_lib.target_config.name));
             }
             if !dep_lib.target_config.name.starts_with("lib") {
-                log(LogLevel::Error, "Dependant lib name must start with lib");
-                log(LogLevel::Error, &format!("Target: {} does not start with lib", dep_lib.target_config.name));
-                std::process::exit(1);
+                return Err(RuxgoError::Config(format!(
+                    "Dependant lib name must start with lib, but target: {} does not", dep_lib.target_config.name
+                )));
             } 
         }
         if target_config.deps.len() > dependant_libs.len() {
-            log(LogLevel::Error, "Dependant libs not found!");
-            log(LogLevel::Error, &format!("Dependant libs: {:?}", target_config.deps));
             let mut targets_pkg = Vec::new();
             for pkg in packages {
                 targets_pkg.extend(pkg.target_configs.clone());
             }
             let targets_all = targets.iter().chain(targets_pkg.iter());
-            log(LogLevel::Error, &format!("Found libs: {:?}", targets_all.map(|x| {
-                if x.typ == "dll" || x.typ == "static" || x.typ == "object"{
-                    x.name.clone()
-                } else {
-                    "".to_string()
-                }
-            }).collect::<Vec<String>>().into_iter().filter(|x| !x.is_empty()).collect::<Vec<String>>()));
-            std::process::exit(1);
+            let found_libs = targets_all
+                .filter(|x| x.typ == "dll" || x.typ == "static" || x.typ == "object")
+                .map(|x| x.name.clone())
+                .collect::<Vec<String>>();
+            return Err(RuxgoError::Config(format!(
+                "Dependant libs not found!\nDependant libs: {:?}\nFound libs: {:?}",
+                target_config.deps, found_libs
+            )));
         }
         let mut target = Target::<'a> {
             srcs,
@@ -159,359 +226,526 @@ impl<'a> Target<'a> {
             dependant_includes,
             bin_path,
             elf_path,
-            path_hash,
-            hash_file_path,
             dependant_libs,
+            os_cflags: os_cflags(os_config)?,
             packages,
+            pch: None,
         };
         let mut src_exclude:Vec<&str> = target_config.src_excluded.iter().map(|s| s.as_str()).collect();
-        target.get_srcs(&target_config.src, &mut src_exclude);
-        target
+        target.get_srcs(&target_config.src, &mut src_exclude)?;
+        if target_config.unity {
+            target.merge_unity_srcs()?;
+        }
+        if !target_config.pch.is_empty() {
+            target.pch = Some(target.add_pch()?);
+        }
+        Ok(target)
+    }
+
+    /// Returns the paths of the binary of a target, and of its elf file if it has one
+    /// # Arguments
+    /// * `target_config` - Target config
+    fn bin_paths(target_config: &TargetConfig) -> (String, String) {
+        let mut bin_path = String::new();
+        bin_path.push_str(BUILD_DIR);
+        bin_path.push('/');
+        bin_path.push_str(&target_config.name);
+        let mut elf_path = String::new();
+        #[cfg(target_os = "windows")]
+        if target_config.typ == "exe" {
+            bin_path.push_str(".exe");
+        } else if target_config.typ == "dll" {
+            bin_path.push_str(".dll");
+        }
+        else if target_config.typ == "static" {
+            bin_path.push_str(".lib");
+        }
+        #[cfg(target_os = "linux")]
+        if target_config.typ == "exe" {
+            elf_path = bin_path.clone();
+            bin_path.push_str(".bin");
+            elf_path.push_str(".elf");
+        } else if target_config.typ == "dll" {
+            bin_path.push_str(".so");
+        } else if target_config.typ == "static" {
+            bin_path.push_str(".a");
+        } else if target_config.typ == "object" {
+            bin_path.push_str(".o");
+        }
+        (bin_path, elf_path)
     }
 
     /// Builds the target
     /// # Arguments
     /// * `gen_cc` - Generate compile_commands.json
-    pub fn build(&mut self, gen_cc: bool) {
-        if !Path::new(ROOT_DIR).exists() {
-            std::fs::create_dir(ROOT_DIR).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Couldn't create ruxos_bld directory: {}", why));
-                std::process::exit(1);
-            });
+    pub fn build(&mut self, gen_cc: bool) -> Result<()> {
+        let plan = match self.prepare(gen_cc, None)? {
+            Some(plan) if !explain::is_dry_run() => plan,
+            _ => return Ok(()),
+        };
+        if plan.pch {
+            self.compile_pch()?;
         }
-        // build other lib targets of packages firstly
-        for pkg in self.packages {
-            for target in &pkg.target_configs {
-                if target.typ == "dll" || target.typ == "static" || target.typ == "object"{
-                    // If the root target(exe target) adds os_config, the pkg_tgt also adds os_config
-                    let mut pkg_tgt = Target::new(&pkg.build_config, &self.os_config, target, &pkg.target_configs, &pkg.sub_packages);
-                    pkg_tgt.build(gen_cc);
-                }
-            }
+        let to_compile = plan.srcs;
+        let progress_bar = compile_progress_bar(to_compile.len());
+        to_compile
+            .par_iter()
+            .try_for_each(|&src| self.compile(src, &progress_bar))?;
+        self.finish(&to_compile)
+    }
+
+    /// Prepares the build of the target
+    ///
+    /// Returns what has to be built, or None if the target is up to date
+    /// # Arguments
+    /// * `gen_cc` - Generate compile_commands.json
+    /// * `linked_dep` - The binary of a dependency that may be linked again before this target, if any
+    pub(crate) fn prepare(&self, gen_cc: bool, linked_dep: Option<&str>) -> Result<Option<Plan>> {
+        let dry_run = explain::is_dry_run();
+        if !dry_run && !Path::new(ROOT_DIR).exists() {
+            std::fs::create_dir(ROOT_DIR)
+                .map_err(|why| RuxgoError::io("Couldn't create ruxos_bld directory", why))?;
         }
-        let mut to_link: bool = false;
-        let mut link_causer: Vec<&str> = Vec::new();  // trace the linked source files
-        let mut srcs_needed = 0;
+        let mut to_compile = Vec::new();
         let total_srcs = self.srcs.len();
         let mut src_ccs = Vec::new();
-        if !self.dependant_libs.is_empty() {
-            to_link = true;
+        let mut build_pch = false;
+        if let Some(pch) = &self.pch {
+            let cmd = pch.command(self)?;
+            if let Some(reason) = pch.to_build(&command_record(&cmd)) {
+                log(LogLevel::Debug, &format!("\tPrecompiling {}: {}", &pch.path, reason));
+                explain::step("precompile", &pch.path, &cmd, &reason);
+                build_pch = true;
+            }
         }
-        for src in &self.srcs {
-            let (to_build, _) = src.to_build(&self.path_hash);
-            if to_build {
-                to_link = true;
-                link_causer.push(&src.path);
-                srcs_needed += 1;
+        for (i, src) in self.srcs.iter().enumerate() {
+            let cmd = src.command(self)?;
+            // the header is compiled after this, every source including it is compiled again
+            let reason = src.to_build(&command_record(&cmd)).or_else(|| {
+                self.pch.as_ref().filter(|_| build_pch).map(|pch| Reason::ChangedInput(pch.path.clone()))
+            });
+            if let Some(reason) = reason {
+                log(LogLevel::Debug, &format!("\tCompiling {}: {}", &src.path, reason));
+                explain::step("compile", &src.path, &cmd, &reason);
+                to_compile.push(i);
             }
             if gen_cc {
-                src_ccs.push(self.gen_cc(src));
+                src_ccs.push(self.gen_cc(src)?);
             }
         }
-        if gen_cc {
+        // a dry run lists compile_commands.json once, see `commands::pre_gen_cc`
+        if gen_cc && !dry_run {
             let mut file = std::fs::OpenOptions::new()
-                .write(true)
                 .append(true)
                 .open("./compile_commands.json")
-                .unwrap();
-            for src_cc in src_ccs {
-                if let Err(e) = writeln!(file, "{},", src_cc) {
-                    eprintln!("Couldn't write to file: {}", e);
-                }
-            }
+                .map_err(|why| RuxgoError::io("Couldn't open compile_commands.json", why))?;
+            // written at once, the targets are prepared in parallel
+            let entries = src_ccs.iter().map(|src_cc| format!("{},\n", src_cc)).collect::<String>();
+            file.write_all(entries.as_bytes())
+                .map_err(|why| RuxgoError::io("Couldn't write to compile_commands.json", why))?;
         }
-        if to_link {
+        let (cmd, cmd_bin) = self.link_command(&self.dependant_libs)?;
+        // the objects compiled and the dependencies linked again may come out the same,
+        // whether the target is linked is decided again once they are done
+        let link_reason = self.link_reason(&cmd)
+            .or_else(|| to_compile.first().map(|&src| Reason::ChangedInput(self.srcs[src].obj_name.clone())))
+            .or_else(|| linked_dep.map(|dep| Reason::ChangedDependency(dep.to_string())));
+        if let Some(reason) = link_reason {
+            log(LogLevel::Debug, &format!("\tLinking {}: {}", &self.bin_path, reason));
+            if dry_run {
+                self.explain_link(&cmd, cmd_bin.as_ref(), &reason);
+                return Ok(Some(Plan { srcs: to_compile, pch: build_pch }));
+            }
             log(LogLevel::Log, &format!("Compiling Target: {}", &self.target_config.name));
             log(
                 LogLevel::Log, 
-                &format!("\t {} of {} source files have to be compiled", srcs_needed, total_srcs)
+                &format!("\t {} of {} source files have to be compiled", to_compile.len(), total_srcs)
             );
             for dep_lib in &self.dependant_libs {
                 log(LogLevel::Log, &format!("\t {} need to be linked", dep_lib.bin_path)); 
             }
-            if !Path::new(OBJ_DIR).exists() {
-                fs::create_dir(OBJ_DIR).unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Couldn't create obj dir: {}", why));
-                });
+            for &src in &to_compile {
+                if let Some(obj_dir) = Path::new(&self.srcs[src].obj_name).parent() {
+                    fs::create_dir_all(obj_dir)
+                        .map_err(|why| RuxgoError::io(format!("Couldn't create obj dir: {}", obj_dir.display()), why))?;
+                }
             }
+            Ok(Some(Plan { srcs: to_compile, pch: build_pch }))
+        } else if build_pch {
+            // nothing is linked again, but the header is kept up to date for the next compiles
+            Ok(Some(Plan { srcs: Vec::new(), pch: true }))
         } else {
             log(LogLevel::Log, &format!("Target: {} is up to date", &self.target_config.name));
-            return;
-        }
-        // Add progress bar
-        let progress_bar = Arc::new(Mutex::new(ProgressBar::new(srcs_needed as u64)));
-        let num_complete = Arc::new(Mutex::new(0));
-        let src_hash_to_update = Arc::new(Mutex::new(Vec::new()));
-        let warns = Arc::new(Mutex::new(Vec::new()));
-        self.srcs.par_iter().for_each(|src| {
-            let (to_build, _message) = src.to_build(&self.path_hash);
-            //log(LogLevel::Debug, &format!("{} => {}", src.path, to_build));
-            if to_build {
-                let warn = src.build(self.build_config, self.os_config, self.target_config, &self.dependant_libs);
-                if let Some(warn) = warn {
-                    warns.lock().unwrap().push(warn);
-                }
-                src_hash_to_update.lock().unwrap().push(src);
-                log(LogLevel::Info, &format!("Compiled: {}", src.path));
-                // If the RUXOS_LOG_LEVEL is not "Info" or "Debug", update the compilation progress bar
-                let log_level = std::env::var("RUXOS_LOG_LEVEL").unwrap_or("".to_string());
-                if !(log_level == "Info" || log_level == "Debug") {
-                    let mut num_complete = num_complete.lock().unwrap();
-                    *num_complete += 1;
-                    let progress_bar = progress_bar.lock().unwrap();
-                    let template = format!("    {}{}", "Compiling :".cyan(), "[{bar:40.}] {pos}/{len} ({percent}%) {msg}[{elapsed_precise}] ");
-                    progress_bar.set_style(ProgressStyle::with_template(&template)
-                        .unwrap()
-                        .progress_chars("=>-"));
-                    progress_bar.inc(1);
-                }
-            }
-        });
-        let warns = warns.lock().unwrap();
-        if warns.len() > 0 {
-            log(LogLevel::Warn, "Warnings emitted during build:");
-            for warn in warns.iter() {
-                log(LogLevel::Warn, &format!("\t{}", warn));
-            }
+            Ok(None)
         }
-        for src in src_hash_to_update.lock().unwrap().iter() {
-            hasher::save_hash(&src.path, &mut self.path_hash);
+    }
+
+    /// Compiles the precompiled header of the target, before any source file including it
+    pub(crate) fn compile_pch(&self) -> Result<()> {
+        let Some(pch) = &self.pch else {
+            return Ok(());
+        };
+        let cmd = pch.command(self)?;
+        log(LogLevel::Log, &format!("Precompiling header: {}", &pch.path));
+        let result = {
+            let _slot = jobs::acquire();
+            pch.build(cmd.clone())
+        };
+        pch.record_diagnostics(&result);
+        result?;
+        let mut pch = Src::new(pch.path.clone(), pch.name.clone(), pch.obj_name.clone(), pch.language.clone(), Vec::new());
+        if let Some(deps) = depfile::read_depfile(&pch.dep_name, &pch.path) {
+            pch.dependant_includes = deps;
         }
-        if to_link {
-            log(LogLevel::Log, "Linking: Since source files were compiled");
-            for src in link_causer {
-                log(LogLevel::Info, &format!("\tFile: {}", &src));
-            }
-            for src in &self.srcs {
-                for include in &src.dependant_includes {
-                    hasher::save_hash(include, &mut self.path_hash);
-                }
+        pch.record(&cmd);
+        Ok(())
+    }
+
+    /// Compiles one source file of the target, recording the diagnostics emitted by the compiler
+    /// # Arguments
+    /// * `src` - The index of the source file
+    /// * `progress_bar` - The progress bar to advance once compiled
+    pub(crate) fn compile(&self, src: usize, progress_bar: &ProgressBar) -> Result<()> {
+        let src = &self.srcs[src];
+        let cmd = src.command(self)?;
+        let _slot = jobs::acquire();
+        let result = src.build(cmd);
+        src.record_diagnostics(&result);
+        result?;
+        log(LogLevel::Info, &format!("Compiled: {}", src.path));
+        // If the RUXOS_LOG_LEVEL is not "Info" or "Debug", update the compilation progress bar
+        let log_level = std::env::var("RUXOS_LOG_LEVEL").unwrap_or("".to_string());
+        if !(log_level == "Info" || log_level == "Debug") {
+            progress_bar.inc(1);
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the target
+    pub(crate) fn name(&self) -> &str {
+        &self.target_config.name
+    }
+
+    /// Saves the hashes of the compiled source files and links the target
+    /// # Arguments
+    /// * `compiled` - The indices of the compiled source files
+    pub(crate) fn finish(&mut self, compiled: &[usize]) -> Result<()> {
+        self.record_objects(compiled)?;
+        let (cmd, cmd_bin) = self.link_command(&self.dependant_libs)?;
+        let link_reason = self.link_reason(&cmd);
+        let Some(reason) = link_reason else {
+            log(LogLevel::Log, &format!("Linking skipped, the inputs of {} did not change", self.target_config.name));
+            return Ok(());
+        };
+        self.explain_link(&cmd, cmd_bin.as_ref(), &reason);
+        log(LogLevel::Log, &format!("Linking: {}", self.target_config.name));
+        for &src in compiled {
+            log(LogLevel::Info, &format!("\tFile: {}", &self.srcs[src].path));
+        }
+        self.link(&self.dependant_libs)?;
+        let link = LinkState { command: command_record(&cmd), inputs: self.link_inputs() };
+        let mut state = build_state::lock();
+        state.record_link(&self.bin_path, link);
+        state.save()
+    }
+
+    /// Saves the hashes of the compiled source files, so that they are not compiled again
+    /// # Arguments
+    /// * `compiled` - The indices of the compiled source files
+    pub(crate) fn record_objects(&mut self, compiled: &[usize]) -> Result<()> {
+        for &src in compiled {
+            let cmd = self.srcs[src].command(self)?;
+            let src = &mut self.srcs[src];
+            // the depfile of the compiler is authoritative, the include scanner is only a fallback
+            match depfile::read_depfile(&src.dep_name, &src.path) {
+                Some(deps) => src.dependant_includes = deps,
+                None => log(LogLevel::Debug, &format!("No depfile found for {}, using scanned includes", src.path)),
             }
-            hasher::save_hashes_to_file(&self.hash_file_path, &self.path_hash);
-            self.link(&self.dependant_libs);
+            src.record(&cmd);
         }
+        build_state::lock().save()
     }
 
     /// Links the dependant libs(or targets)
     /// # Arguments
     /// * `dep_targets` - The targets that this target depends on
-    pub fn link(&self, dep_targets: &Vec<Target>) {
-        let mut objs = Vec::new();
+    pub fn link(&self, dep_targets: &[Target]) -> Result<()> {
         if !Path::new(BUILD_DIR).exists() {
-            fs::create_dir_all(BUILD_DIR).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Couldn't create build dir: {}", why));
-                std::process::exit(1);
-            }) 
+            fs::create_dir_all(BUILD_DIR)
+                .map_err(|why| RuxgoError::io("Couldn't create build dir", why))?;
         }
-        for src in &self.srcs {
-            objs.push(&src.obj_name);
+        let (cmd, cmd_bin) = self.link_command(dep_targets)?;
+        log(LogLevel::Info, &format!("Linking target: {}", &self.target_config.name));
+        log(LogLevel::Info, &format!("  Command: {}", &cmd));
+        // libraries are shared through the remote cache, executables depend on the local os build
+        let remote = remote_cache::get().filter(|_| self.target_config.typ != "exe");
+        let key = remote.and_then(|_| self.link_cache_key(&cmd));
+        if let (Some(remote), Some(key)) = (remote, &key) {
+            if let Some(bin) = remote.download(key, "bin") {
+                fs::write(&self.bin_path, bin)
+                    .map_err(|why| RuxgoError::io(format!("Failed to write file: {}", &self.bin_path), why))?;
+                log(LogLevel::Log, &format!("Linking skipped, {} found in the remote cache", &self.bin_path));
+                return Ok(());
+            }
         }
-        let mut cmd = String::new();
-        let mut cmd_bin = String::new();
+        let category = if self.target_config.typ == "static" { "archive" } else { "link" };
+        let span = timings::span(category, &self.target_config.name);
+        let output = cmd
+            .output()
+            .map_err(|why| RuxgoError::io("Failed to execute linker", why))?;
+        drop(span);
+        if output.status.success() {
+            log(LogLevel::Log, "Linking successful");
+            if let (Some(remote), Some(key)) = (remote, &key) {
+                if let Ok(bin) = fs::read(&self.bin_path) {
+                    remote.upload(key, "bin", &bin);
+                }
+            }
+        } else {
+            return Err(RuxgoError::Link {
+                target: self.target_config.name.clone(),
+                cmd: cmd.to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+        if let Some(cmd_bin) = cmd_bin {
+            let _span = timings::span("objcopy", &self.target_config.name);
+            let output_bin = cmd_bin
+                .output()
+                .map_err(|why| RuxgoError::io("Failed to execute rust-objcopy", why))?;
+            if output_bin.status.success() {
+                log(LogLevel::Info, &format!(" Bin_path: {}", &self.bin_path));
+                log(LogLevel::Info, &format!(" Elf_path: {}", &self.elf_path));
+             } else {
+                return Err(RuxgoError::Toolchain(format!(
+                    "Rust-objcopy failed\n  Command: {}\n  Error: {}",
+                    cmd_bin, String::from_utf8_lossy(&output_bin.stderr)
+                )));
+             }
+        }
+        Ok(())
+    }
+
+    /// Determines whether the target needs to be linked, returns why if it does
+    /// # Arguments
+    /// * `state` - The build state recorded by the previous builds
+    /// * `cmd` - The command that would link the target now
+    fn link_reason(&self, cmd: &Cmd) -> Option<Reason> {
+        // only an exe running on the os keeps its elf file next to the bin file
+        let outputs = if self.os_link_files().is_some() { vec![&self.bin_path, &self.elf_path] } else { vec![&self.bin_path] };
+        for output in outputs {
+            if !Path::new(output).exists() {
+                return Some(Reason::MissingOutput(output.clone()));
+            }
+        }
+        let link = build_state::lock().link(&self.bin_path).cloned();
+        let link = match link {
+            Some(link) => link,
+            None => return Some(Reason::Forced(format!("no link record for {}", &self.bin_path))),
+        };
+        if link.command != command_record(cmd) {
+            return Some(Reason::ChangedFlags);
+        }
+        for (input, hash) in self.link_inputs() {
+            if link.inputs.get(&input) == Some(&hash) {
+                continue;
+            }
+            if self.dependant_libs.iter().any(|dep_lib| dep_lib.bin_path == input) {
+                return Some(Reason::ChangedDependency(input));
+            }
+            return Some(Reason::ChangedInput(input));
+        }
+        None
+    }
+
+    /// Returns the files read by the linker with their current content hashes: the objects of the target,
+    /// the binaries of its dependencies, the binaries of the packages a dll is linked with,
+    /// and for an exe running on the os its linker script and archives
+    ///
+    /// The files may have been written since they were last hashed, they are hashed again
+    fn link_inputs(&self) -> BTreeMap<String, String> {
+        let mut inputs = self.srcs.iter().map(|src| src.obj_name.clone())
+            .chain(self.dependant_libs.iter().map(|dep_lib| dep_lib.bin_path.clone()))
+            .collect::<Vec<String>>();
+        // a dll is linked with every target of its packages, see `link_command`
         if self.target_config.typ == "dll" {
-            cmd.push_str(&self.build_config.compiler.read().unwrap());
-            cmd.push_str(" -shared");
-            cmd.push_str(" ");
-            cmd.push_str(" -o ");
-            cmd.push_str(&self.bin_path);
-            for obj in objs {
-                cmd.push_str(" ");
-                cmd.push_str(obj);
-            }
-            cmd.push_str(" ");
-            cmd.push_str(&self.target_config.cflags);
-            cmd.push_str(" ");
+            for package in self.packages {
+                inputs.extend(package.target_configs.iter().map(|target| Target::bin_paths(target).0));
+            }
+        }
+        if let Some((linker_script, libs)) = self.os_link_files() {
+            inputs.push(linker_script);
+            inputs.extend(libs);
+        }
+        inputs
+            .into_iter()
+            .map(|input| {
+                let hash = build_state::rehash(&input).unwrap_or_default();
+                (input, hash)
+            })
+            .collect()
+    }
+
+    /// Returns the linker script of an exe running on the os, and the ulib and os archives it is linked with,
+    /// None for the other targets
+    fn os_link_files(&self) -> Option<(String, Vec<String>)> {
+        if self.os_config.name.is_empty() || self.target_config.typ != "exe" {
+            return None;
+        }
+        let linker_script = format!(
+            "{}/{}/modules/ruxhal/linker_{}.lds",
+            env!("HOME"), self.os_config.name, self.os_config.platform.name
+        );
+        let os_lib_dir = format!("{}/target/{}/{}", ROOT_DIR, &self.os_config.platform.target, &self.os_config.platform.mode);
+        let libs = match self.os_config.ulib.as_str() {
+            "ruxlibc" => vec![RUXLIBC_C_LIB.to_string(), format!("{}/{}", os_lib_dir, RUXLIBC_RUST_LIB)],
+            "ruxmusl" => vec![RUXMUSL_C_LIB.to_string(), format!("{}/{}", os_lib_dir, RUXMUSL_RUST_LIB)],
+            _ => Vec::new(),
+        };
+        Some((linker_script, libs))
+    }
+
+    /// Reports the link of the target and the objcopy generating its bin file, for the dry-run and explain modes
+    fn explain_link(&self, cmd: &Cmd, cmd_bin: Option<&Cmd>, reason: &Reason) {
+        let action = if self.target_config.typ == "static" { "archive" } else { "link" };
+        explain::step(action, &self.bin_path, cmd, reason);
+        if let Some(cmd_bin) = cmd_bin {
+            explain::step("objcopy", &self.elf_path, cmd_bin, &Reason::ChangedInput(self.elf_path.clone()));
+        }
+    }
+
+    /// Returns the key of the binary in the remote cache: the link command and the content of its inputs,
+    /// see `link_inputs`, None if an input can't be read
+    /// # Arguments
+    /// * `cmd` - The link command
+    fn link_cache_key(&self, cmd: &Cmd) -> Option<String> {
+        let mut inputs = String::new();
+        for (_, hash) in self.link_inputs() {
+            if hash.is_empty() {
+                return None;
+            }
+            inputs.push_str(&hash);
+        }
+        Some(ObjectCache::key(&command_record(cmd), inputs.as_bytes()))
+    }
+
+    /// Returns whether the target, or a static or object library linked into it, has C++ sources
+    fn has_cxx_srcs(&self) -> bool {
+        self.srcs.iter().any(|src| src.language == "c++")
+            || self.dependant_libs.iter().any(|dep_lib| dep_lib.target_config.typ != "dll" && dep_lib.has_cxx_srcs())
+    }
+
+    /// Returns the compiler driving the link, the C++ one if C++ objects are linked so that libstdc++ is pulled in
+    fn link_driver(&self) -> String {
+        if self.has_cxx_srcs() {
+            self.build_config.cxx.read().unwrap().clone()
+        } else {
+            self.build_config.cc.read().unwrap().clone()
+        }
+    }
+
+    /// Returns the link command of the target, and the objcopy command generating its bin file if any
+    /// # Arguments
+    /// * `dep_targets` - The targets that this target depends on
+    fn link_command(&self, dep_targets: &[Target]) -> Result<(Cmd, Option<Cmd>)> {
+        let objs = self.srcs.iter().map(|src| &src.obj_name);
+        // '$ORIGIN' represents the directory path where the executable is located
+        let rpath = ["-L", BUILD_DIR, "-Wl,-rpath,$ORIGIN"];
+        let mut cmd_bin = None;
+        let cmd = if self.target_config.typ == "dll" {
+            let mut cmd = Cmd::new(self.link_driver());
+            cmd.args(["-shared", "-o", &self.bin_path]).args(objs);
+            cmd.flags(&self.target_config.cflags)?;
             // link other dependant libraries
             for dep_target in dep_targets {
-                cmd.push_str(" -I");
-                cmd.push_str(&dep_target.target_config.include_dir);
-                cmd.push_str(" ");
-                let lib_name = dep_target.target_config.name.clone();
-                let lib_name = lib_name.replace("lib", "-l");
-                cmd.push_str(&lib_name);
-                cmd.push_str(" ");
+                cmd.arg(format!("-I{}", dep_target.target_config.include_dir));
+                cmd.arg(dep_target.target_config.name.replace("lib", "-l"));
             }
             // get libraries as packages
             for package in self.packages {
                 for target in &package.target_configs {
-                    cmd.push_str(" -I");
-                    cmd.push_str(&target.include_dir);
-                    cmd.push_str(" ");
-                    let lib_name = target.name.clone();
-                    let lib_name = lib_name.replace("lib", "-l");
-                    cmd.push_str(&lib_name);
-                    cmd.push_str(" ");
+                    cmd.arg(format!("-I{}", target.include_dir));
+                    cmd.arg(target.name.replace("lib", "-l"));
                 }
             }
             // added -L library search path
             if self.packages.len() + self.dependant_libs.len() > 0 {
-                cmd.push_str(" -L");
-                cmd.push_str(BUILD_DIR);
-                cmd.push_str(" -Wl,-rpath,\'$ORIGIN\' ");  // '$ORIGIN' represents the directory path where the executable is located
-                cmd.push_str(" ");
+                cmd.args(rpath);
             }
-            cmd.push_str(" ");
-            cmd.push_str(&self.target_config.ldflags);
+            cmd.flags(&self.target_config.ldflags)?;
+            cmd
         } else if self.target_config.typ == "static" {
-            cmd.push_str(&self.target_config.archive);
-            cmd.push_str(" ");
-            cmd.push_str(&self.target_config.ldflags);
-            cmd.push_str(" ");
-            cmd.push_str(&self.bin_path);
-            for obj in objs {
-                cmd.push_str(" ");
-                cmd.push_str(obj);
-            }
+            let mut cmd = Cmd::parse(&self.target_config.archive)?;
+            cmd.flags(&self.target_config.ldflags)?;
+            cmd.arg(&self.bin_path).args(objs);
+            cmd
         } else if self.target_config.typ == "object" {
-            cmd.push_str(&self.build_config.compiler.read().unwrap());
-            cmd.push_str(" ");
-            cmd.push_str(&self.target_config.ldflags);
-            cmd.push_str(" -o ");
-            cmd.push_str(&self.bin_path);
-            for obj in objs {
-                cmd.push_str(" ");
-                cmd.push_str(obj);
+            let mut cmd = Cmd::new(self.link_driver());
+            cmd.flags(&self.target_config.ldflags)?;
+            cmd.args(["-o", &self.bin_path]).args(objs);
+            // link other dependant libraries
+            cmd.args(dep_targets.iter().map(|dep_target| &dep_target.bin_path));
+            cmd
+        } else if let Some((linker_script, libs)) = self.os_link_files() {
+            // the ldflags of an exe running on the os start with the linker
+            let mut cmd = Cmd::parse(&self.target_config.ldflags)?;
+            // add os_ldflags
+            cmd.args(["-nostdlib", "-static", "-no-pie", "--gc-sections"]);
+            cmd.arg(format!("-T{}", linker_script));
+            if self.os_config.platform.arch == "x86_64" {
+                cmd.arg("--no-relax");
             }
+
+            // link ulib and os
+            cmd.args(libs);
+
+            // link other obj
+            cmd.args(objs);
+
             // link other dependant libraries
-            for dep_target in dep_targets {
-                cmd.push_str(" ");
-                cmd.push_str(&dep_target.bin_path);
-            }
-        } else if self.target_config.typ == "exe" {
-            if !self.os_config.name.is_empty() {
-                // add os_ldflags
-                let mut os_ldflags = String::new();
-                os_ldflags.push_str("-nostdlib -static -no-pie --gc-sections");
-                let ld_script = format!(
-                    "{}/{}/modules/ruxhal/linker_{}.lds",
-                     env!("HOME"), self.os_config.name, self.os_config.platform.name
-                );
-                os_ldflags.push_str(&format!(" -T{}", &ld_script));
-                if self.os_config.platform.arch == "x86_64".to_string() {
-                    os_ldflags.push_str(" --no-relax");
-                }
-                let mut ldflags = String::new();
-                ldflags.push_str(&self.target_config.ldflags);
-                ldflags.push_str(" ");
-                ldflags.push_str(&os_ldflags);
-                cmd.push_str(&ldflags);
-
-                // link ulib and os
-                if self.os_config.ulib == "ruxlibc" {
-                    cmd.push_str(" ");
-                    cmd.push_str(RUXLIBC_C_LIB);
-                    cmd.push_str(" ");
-                    cmd.push_str(&format!("{}/target/{}/{}/{}",
-                                ROOT_DIR, &self.os_config.platform.target, &self.os_config.platform.mode, RUXLIBC_RUST_LIB));
-                } else if self.os_config.ulib == "ruxmusl" {
-                    cmd.push_str(" ");
-                    cmd.push_str(RUXMUSL_C_LIB);
-                    cmd.push_str(" ");
-                    cmd.push_str(&format!("{}/target/{}/{}/{}",
-                                ROOT_DIR, &self.os_config.platform.target, &self.os_config.platform.mode, RUXMUSL_RUST_LIB));
-                }
+            cmd.args(dep_targets.iter().map(|dep_target| &dep_target.bin_path));
+            cmd.args(["-o", &self.elf_path]);
 
-                // link other obj
-                for obj in objs {
-                    cmd.push_str(" ");
-                    cmd.push_str(obj);
-                }
+            // generate a bin file
+            let mut objcopy = Cmd::new("rust-objcopy");
+            objcopy.arg(format!("--binary-architecture={}", &self.os_config.platform.arch));
+            objcopy.arg(&self.elf_path).args(["--strip-all", "-O", "binary", &self.bin_path]);
+            cmd_bin = Some(objcopy);
+            cmd
+        } else {
+            let mut cmd = Cmd::new(self.link_driver());
+            cmd.args(["-o", &self.bin_path]).args(objs);
+            cmd.flags(&self.target_config.ldflags)?;
 
-                // link other dependant libraries
-                for dep_target in dep_targets {
-                    cmd.push_str(" ");
-                    cmd.push_str(&dep_target.bin_path);
-                }
-                cmd.push_str(" -o ");
-                cmd.push_str(&self.elf_path);
-
-                // generate a bin file
-                cmd_bin.push_str(&format!("rust-objcopy --binary-architecture={}", &self.os_config.platform.arch));
-                cmd_bin.push_str(" ");
-                cmd_bin.push_str(&self.elf_path);
-                cmd_bin.push_str(" --strip-all -O binary ");
-                cmd_bin.push_str(&self.bin_path);
-            } else {
-                cmd.push_str(&self.build_config.compiler.read().unwrap());
-                cmd.push_str(" -o ");
-                cmd.push_str(&self.bin_path);
-                for obj in objs {
-                    cmd.push_str(" ");
-                    cmd.push_str(obj);
-                }
-                cmd.push_str(" ");
-                cmd.push_str(&self.target_config.ldflags);
-                cmd.push_str(" ");
-
-                // link other dependant libraries
-                for dep_target in dep_targets {
-                    if dep_target.target_config.typ == "object" || dep_target.target_config.typ == "static" {
-                        cmd.push_str(&dep_target.bin_path);
-                    } else if dep_target.target_config.typ == "dll" {
-                        cmd.push_str(" -I");
-                        cmd.push_str(&dep_target.target_config.include_dir);
-                        cmd.push_str(" ");
-                        let lib_name = dep_target.target_config.name.clone();
-                        let lib_name = lib_name.replace("lib", "-l");
-                        cmd.push_str(&lib_name);
-                        cmd.push_str(" ");
-                        // added -L library search path
-                        cmd.push_str(" -L");
-                        cmd.push_str(BUILD_DIR);
-                        cmd.push_str(" -Wl,-rpath,\'$ORIGIN\' ");  // '$ORIGIN' represents the directory path where the executable is located
-                        cmd.push_str(" ");
-                    }
+            // link other dependant libraries
+            for dep_target in dep_targets {
+                if dep_target.target_config.typ == "object" || dep_target.target_config.typ == "static" {
+                    cmd.arg(&dep_target.bin_path);
+                } else if dep_target.target_config.typ == "dll" {
+                    cmd.arg(format!("-I{}", dep_target.target_config.include_dir));
+                    cmd.arg(dep_target.target_config.name.replace("lib", "-l"));
+                    // added -L library search path
+                    cmd.args(rpath);
                 }
             }
-        }
+            cmd
+        };
 
-        log(LogLevel::Info, &format!("Linking target: {}", &self.target_config.name));
-        log(LogLevel::Info, &format!("  Command: {}", &cmd));
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&cmd)
-            .output()
-            .expect("failed to execute process");
-        if output.status.success() {
-            log(LogLevel::Log, "Linking successful");
-            hasher::save_hashes_to_file(&self.hash_file_path, &self.path_hash);
-        } else {
-            log(LogLevel::Error, "Linking failed");
-            log(LogLevel::Error, &format!(" Command: {}", &cmd));
-            log(LogLevel::Error, &format!("  Error: {}", String::from_utf8_lossy(&output.stderr)));
-            std::process::exit(1);
-        }
-        if !cmd_bin.is_empty() {
-            let output_bin = Command::new("sh")
-                .arg("-c")
-                .arg(&cmd_bin)
-                .output()
-                .expect("failed to execute process");
-            if output_bin.status.success() {
-                log(LogLevel::Info, &format!(" Bin_path: {}", &self.bin_path));
-                log(LogLevel::Info, &format!(" Elf_path: {}", &self.elf_path));
-             } else {
-                log(LogLevel::Error, "  Rust-objcopy failed");
-                log(LogLevel::Error, &format!(" Command: {}", &cmd_bin));
-                log(LogLevel::Error, &format!("  Error: {}", String::from_utf8_lossy(&output_bin.stderr)));
-                std::process::exit(1);
-             }
-        }
+        Ok((cmd, cmd_bin))
     }
 
     /// Generates the compile_commands.json file for a src
-    fn gen_cc(&self, src: &Src) -> String {
+    fn gen_cc(&self, src: &Src) -> Result<String> {
         let mut cc = String::new();
         cc.push_str("{\n");  // Json start
-        if *self.build_config.compiler.read().unwrap() == "clang++" || *self.build_config.compiler.read().unwrap() == "g++" {
-            cc.push_str("\t\"command\": \"c++");
-        } else if *self.build_config.compiler.read().unwrap() == "clang" || *self.build_config.compiler.read().unwrap() == "gcc" {
-            cc.push_str("\t\"command\": \"cc");
+        // the compiler resolved for the language of the source, as in `Src::command`
+        let compiler = if src.language.starts_with("c++") {
+            self.build_config.cxx.read().unwrap().clone()
         } else {
-            log(LogLevel::Error, &format!("Compiler: {} is not supported", &self.build_config.compiler.read().unwrap()));
-            log(LogLevel::Error, "Supported compilers: clang++, g++, clang, gcc");
-            std::process::exit(1);
-        }
+            self.build_config.cc.read().unwrap().clone()
+        };
+        cc.push_str("\t\"command\": \"");
+        cc.push_str(&process::quote(compiler.as_ref()));
         cc.push_str(" -c -o ");
         cc.push_str(&src.obj_name);
         cc.push_str(" -I");
@@ -528,45 +762,11 @@ impl<'a> Target<'a> {
             }
         }
 
-        cc.push_str(" ");
-        let cflags = &self.target_config.cflags;
-
-        let subcmds = cflags.split('`').collect::<Vec<&str>>();
-        // Take even entries are non-subcmds and odd entries are subcmds
-        let (subcmds, non_subcmds): (Vec<String>, String) = subcmds.iter().enumerate().fold(
-            (Vec::new(), String::new()),
-            |(mut subcmds, mut non_subcmds), (i, subcmd)| {
-                if i % 2 != 0 {
-                    subcmds.push(subcmd.to_string());
-                } else {
-                    non_subcmds.push_str(subcmd);
-                    non_subcmds.push_str(" ");
-                }
-                (subcmds, non_subcmds)
-            },
-        );
-
-        cc.push_str(&non_subcmds);
-
-        for subcmd in subcmds {
-            let cmd_output = Command::new("sh")
-                .arg("-c")
-                .arg(&subcmd)
-                .output()
-                .expect("failed to execute process");
-            if cmd_output.status.success() {
-                let stdout = String::from_utf8_lossy(&cmd_output.stdout);
-                let stdout = stdout.replace("\n", " ");
-                cc.push_str(&stdout);
-            } else {
-                let stderr = String::from_utf8_lossy(&cmd_output.stderr);
-                log(
-                    LogLevel::Error,
-                    &format!("Failed to execute subcmd: {}", &subcmd),
-                );
-                log(LogLevel::Error, &format!("  Stderr: {}", stderr));
-                std::process::exit(1);
-            }
+        cc.push(' ');
+        // subcommands between backticks are expanded, the database is read without a shell
+        for flag in process::split_flags(self.target_config.flags(&src.language))? {
+            cc.push_str(&process::quote(flag.as_ref()));
+            cc.push(' ');
         }
 
         #[cfg(target_os = "linux")]
@@ -586,32 +786,31 @@ impl<'a> Target<'a> {
         let mut fileent = String::new();
         fileent.push_str("\t\"file\": \"");
         fileent.push_str(&std::env::current_dir().unwrap().to_str().unwrap().replace("\\", "/"));
-        fileent.push_str("/");
+        fileent.push('/');
         fileent.push_str(&src.path);
-        fileent.push_str("\"");
+        fileent.push('"');
         let fileent = fileent.replace("/", "\\\\").replace("\\\\.\\\\", "\\\\");
         cc.push_str(&fileent);
 
         cc.push_str("\n}");
         #[cfg(target_os = "linux")]
-        return cc.replace("\\\\", "/");
+        return Ok(cc.replace("\\\\", "/"));
         #[cfg(target_os = "windows")]
-        return cc;
+        return Ok(cc);
     }
 
     /// Recursively gets all the source files in the given root path
-    fn get_srcs(&mut self, root_path: &str, src_exclude: &mut Vec<&str>) -> Vec<Src> {
+    fn get_srcs(&mut self, root_path: &str, src_exclude: &mut Vec<&str>) -> Result<Vec<Src>> {
         if root_path.is_empty() {
-            return Vec::new();
+            return Ok(Vec::new());
         }
         let root_dir = PathBuf::from(root_path);
         let mut srcs: Vec<Src> = Vec::new();
-        let root_entries = std::fs::read_dir(root_dir).unwrap_or_else(|_| {
-            log(LogLevel::Error, &format!("Could not read directory: {}", root_path));
-            std::process::exit(1);
-        });
+        let root_entries = std::fs::read_dir(root_dir)
+            .map_err(|why| RuxgoError::io(format!("Could not read directory: {}", root_path), why))?;
         for entry in root_entries {
-            let entry = entry.unwrap(); 
+            let entry = entry
+                .map_err(|why| RuxgoError::io(format!("Could not read directory: {}", root_path), why))?;
             let path = entry.path().to_str().unwrap().to_string().replace("\\", "/"); // if windows's path
             if entry.path().is_dir() {  
                 let skip_dir = src_exclude.iter().any(|&excluded| path.contains(excluded));
@@ -620,7 +819,7 @@ impl<'a> Target<'a> {
                     src_exclude.retain(|&excluded| !path.contains(excluded));
                     continue;
                 }
-                srcs.append(&mut self.get_srcs(&path, src_exclude));
+                srcs.append(&mut self.get_srcs(&path, src_exclude)?);
             } else {
                 let skip_file = src_exclude.iter().any(|&excluded| path.ends_with(excluded));
                 if skip_file {
@@ -628,22 +827,112 @@ impl<'a> Target<'a> {
                     src_exclude.retain(|&excluded| !path.ends_with(excluded));
                     continue;
                 }
-                if !path.ends_with(".cpp") && !path.ends_with(".c") {
+                if self.target_config.src_language(&path).is_none() {
                     continue;
                 }
                 self.add_src(path);
             }
         }
-        srcs
+        Ok(srcs)
+    }
+
+    /// Replaces the C and C++ sources of the target with generated unity files, each including a batch of them
+    ///
+    /// Unity files are generated as `OBJ_DIR/<target>/unity/unity_N.<ext>` and only rewritten when their
+    /// batch changes, the depfile of each one lists its members so that it is rebuilt when any of them changes.
+    fn merge_unity_srcs(&mut self) -> Result<()> {
+        let unity_dir = format!("{}/{}/unity", OBJ_DIR, self.target_config.name);
+        let (mut merged, srcs): (Vec<Src>, Vec<Src>) = std::mem::take(&mut self.srcs).into_iter().partition(|src| {
+            (src.language == "c" || src.language == "c++")
+                && !self.target_config.unity_excluded.iter().any(|excluded| src.path.ends_with(excluded.as_str()))
+        });
+        self.srcs = srcs;
+        // sorted, so that batches keep their members from one build to the next
+        merged.sort_by(|a, b| a.path.cmp(&b.path));
+        for (language, ext) in [("c", "c"), ("c++", "cpp")] {
+            let members = merged.iter().filter(|src| src.language == language).collect::<Vec<&Src>>();
+            let batch = match self.target_config.unity_batch {
+                0 => members.len().max(1),
+                batch => batch,
+            };
+            for (i, batch) in members.chunks(batch).enumerate() {
+                let path = format!("{}/unity_{}.{}", unity_dir, i, ext);
+                let mut contents = String::from("/* generated by ruxgo, do not edit */\n");
+                for member in batch {
+                    let member_path = fs::canonicalize(&member.path)
+                        .map_err(|why| RuxgoError::io(format!("Couldn't find source: {}", member.path), why))?;
+                    contents.push_str(&format!("#include \"{}\"\n", member_path.display()));
+                }
+                let reason = match fs::read_to_string(&path) {
+                    Err(_) => Some(Reason::MissingOutput(path.clone())),
+                    Ok(old) if old != contents => Some(Reason::ChangedInput(self.target_config.src.clone())),
+                    Ok(_) => None,
+                };
+                if reason.is_some_and(|reason| explain::write(&path, &reason)) {
+                    fs::create_dir_all(&unity_dir)
+                        .map_err(|why| RuxgoError::io(format!("Couldn't create unity dir: {}", unity_dir), why))?;
+                    fs::write(&path, contents)
+                        .map_err(|why| RuxgoError::io(format!("Couldn't write file: {}", path), why))?;
+                }
+                let obj_name = format!("{}.o", path);
+                let dependant_includes = batch.iter().map(|member| member.path.clone()).collect();
+                let name = Target::get_src_name(&path);
+                self.srcs.push(Src::new(path, name, obj_name, language.to_string(), dependant_includes));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets up the precompiled header of the target and makes the sources of its language include it
+    ///
+    /// The header is compiled to `OBJ_DIR/<target>/pch/<header>.gch`, next to a stub header including the
+    /// real one, so that the compiler falls back to the real header when the precompiled one can't be used.
+    fn add_pch(&mut self) -> Result<Src> {
+        let path = self.target_config.pch.clone();
+        let file_name = Path::new(&path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| RuxgoError::Config(format!("Invalid pch of target {}: {}", self.target_config.name, path)))?;
+        let stub_path = format!("{}/{}/pch/{}", OBJ_DIR, self.target_config.name, file_name);
+        let header = fs::canonicalize(&path)
+            .map_err(|why| RuxgoError::io(format!("Couldn't find pch of target {}: {}", self.target_config.name, path), why))?;
+        let stub = format!("#include \"{}\"\n", header.display());
+        let reason = match fs::read_to_string(&stub_path) {
+            Err(_) => Some(Reason::MissingOutput(stub_path.clone())),
+            Ok(old) if old != stub => Some(Reason::ChangedInput(path.clone())),
+            Ok(_) => None,
+        };
+        if reason.is_some_and(|reason| explain::write(&stub_path, &reason)) {
+            if let Some(pch_dir) = Path::new(&stub_path).parent() {
+                fs::create_dir_all(pch_dir)
+                    .map_err(|why| RuxgoError::io(format!("Couldn't create pch dir: {}", pch_dir.display()), why))?;
+            }
+            fs::write(&stub_path, stub)
+                .map_err(|why| RuxgoError::io(format!("Couldn't write file: {}", stub_path), why))?;
+        }
+        // a precompiled header only serves sources of its own language
+        let language = if self.srcs.iter().any(|src| src.language == "c++") { "c++" } else { "c" };
+        for src in self.srcs.iter_mut().filter(|src| src.language == language) {
+            src.pch = Some(stub_path.clone());
+        }
+        let name = Target::get_src_name(&path);
+        Ok(Src::new(path, name, format!("{}.gch", stub_path), format!("{}-header", language), Vec::new()))
     }
 
     /// Adds a source file to the target's srcs field
     fn add_src(&mut self, path: String) {
         let name = Target::get_src_name(&path);
-        let obj_name = self.get_src_obj_name(&name);
-        let dependant_includes=self.get_dependant_includes(&path);
-        let bin_path = self.bin_path.clone();
-        self.srcs.push(Src::new(path, name, obj_name, bin_path, dependant_includes));
+        let obj_name = self.get_src_obj_name(&path);
+        // the headers recorded by the last build, or the scanned ones for a new source
+        let recorded_includes = build_state::lock().object(&obj_name).map(|object| {
+            object.inputs.keys().filter(|input| **input != path).cloned().collect::<Vec<String>>()
+        });
+        let dependant_includes = match recorded_includes {
+            Some(includes) => includes,
+            None => self.get_dependant_includes(&path),
+        };
+        let language = self.target_config.src_language(&path).unwrap_or("c").to_string();
+        self.srcs.push(Src::new(path, name, obj_name, language, dependant_includes));
     }
 
     /// Returns the file name without the extension from the path
@@ -655,12 +944,29 @@ impl<'a> Target<'a> {
     }
 
     /// Returns the object file name corresponding to the source file
-    fn get_src_obj_name(&self, src_name: &str) -> String {
+    ///
+    /// Objects mirror the source tree, eg: `./src/net/util.c` => `OBJ_DIR/<target>/src/net/util.c.o`
+    fn get_src_obj_name(&self, src_path: &str) -> String {
+        let path = Path::new(src_path);
+        // sources out of the project (eg: ruxlibc) are placed relative to the src dir of the target
+        let path = match path.strip_prefix(&self.target_config.src) {
+            Ok(rel_path) if path.is_absolute() => rel_path,
+            _ => path,
+        };
         let mut obj_name = String::new();
         obj_name.push_str(OBJ_DIR);
-        obj_name.push_str("/");
+        obj_name.push('/');
         obj_name.push_str(&self.target_config.name);
-        obj_name.push_str(src_name);
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => {
+                    obj_name.push('/');
+                    obj_name.push_str(&part.to_string_lossy());
+                }
+                Component::ParentDir => obj_name.push_str("/__"),
+                _ => {}
+            }
+        }
         obj_name.push_str(".o");
         obj_name
     }
@@ -695,7 +1001,10 @@ impl<'a> Target<'a> {
         }
         let mut file = file.unwrap();
         let mut buf = String::new();
-        file.read_to_string(&mut buf).unwrap();
+        if file.read_to_string(&mut buf).is_err() {
+            log(LogLevel::Warn, &format!("Failed to read file: {}", path));
+            return None;
+        }
 
         let lines = buf.lines();
         let mut include_substrings = Vec::new();
@@ -715,134 +1024,286 @@ impl Src {
         path: String, 
         name: String, 
         obj_name: String, 
-        bin_path: String, 
+        language: String,
         dependant_includes: Vec<String>
     ) -> Self {
+        let dep_name = format!("{}.d", obj_name.trim_end_matches(".o"));
         Self {
             path,
             name,
             obj_name,
-            bin_path,
+            dep_name,
+            language,
             dependant_includes,
+            pch: None,
+        }
+    }
+
+    /// Records what the object was built from in the build state
+    /// # Arguments
+    /// * `state` - The build state
+    /// * `cmd` - The command the object was built with
+    fn record(&self, cmd: &Cmd) {
+        let mut inputs = BTreeMap::new();
+        for input in std::iter::once(&self.path).chain(self.dependant_includes.iter()) {
+            inputs.insert(input.clone(), build_state::hash(input).unwrap_or_default());
+        }
+        // the object is stale once the precompiled header it was built with changes, the header
+        // may have been compiled again since it was hashed
+        if let Some(pch) = &self.pch {
+            let gch = format!("{}.gch", pch);
+            let hash = build_state::rehash(&gch).unwrap_or_default();
+            inputs.insert(gch, hash);
         }
+        let object = ObjectState { command: command_record(cmd), inputs };
+        build_state::lock().record_object(&self.obj_name, object);
     }
 
-    /// Determines whether the object file needs to be rebuilt
-    fn to_build(&self, path_hash: &HashMap<String, String>) -> (bool, String) {
-        if !Path::new(&self.bin_path).exists() {
-            let result = (true, format!("\tBinary does not exist: {}", &self.bin_path));
-            return result;
+    /// Determines whether the object file needs to be rebuilt, returns why if it does
+    /// # Arguments
+    /// * `cmd` - The command that would build the object now
+    fn to_build(&self, cmd: &str) -> Option<Reason> {
+        if !Path::new(&self.obj_name).exists() {
+            return Some(Reason::MissingOutput(self.obj_name.clone()));
         }
+        let object = build_state::lock().object(&self.obj_name).cloned();
+        let object = match object {
+            Some(object) => object,
+            None => return Some(Reason::Forced(format!("no build record for {}", &self.obj_name))),
+        };
 
-        if hasher::is_file_changed(&self.path, path_hash) {
-            let result = (true, format!("\tSource file has changed: {}", &self.path));
-            return result;
+        if object.command != cmd {
+            return Some(Reason::ChangedFlags);
         }
-        for dependant_include in &self.dependant_includes {
-            if hasher::is_file_changed(&dependant_include.clone(), path_hash) {
-                let result = (true, format!("\tSource file: {} depends on changed include file: {}", &self.path, &dependant_include));
-                return result;
+
+        for (input, hash) in &object.inputs {
+            if build_state::hash(input).unwrap_or_default() != *hash {
+                return Some(Reason::ChangedInput(input.clone()));
             }
         }
         
-        (false, format!("Source file: {} does not need to be built", &self.path))
+        None
     }
     
-    /// Builds the source files
-    fn build(
-        &self, 
-        build_config: &BuildConfig, 
-        os_config: &OSConfig,
-        target_config: &TargetConfig, 
-        dependant_libs: &Vec<Target>
-    ) -> Option<String> {
-        let mut cmd = String::new();
-        cmd.push_str(&build_config.compiler.read().unwrap());
-        let mut os_cflags = String::new();
-        // Add os_cflags
-        if !os_config.name.is_empty() {
-            if os_config.ulib == "ruxlibc" {
-                let (_, lib_feats) = cfg_feat(os_config);
-                // generate the preprocessing macro definition
-                for lib_feat in lib_feats {
-                    let processed_lib_feat = lib_feat.to_uppercase().replace("-", "_");
-                    os_cflags.push_str(&format!(" -DRUX_CONFIG_{}", &processed_lib_feat));
-                }
-                os_cflags.push_str(&format!(" -DRUX_CONFIG_{}", os_config.platform.log.to_uppercase()));
-                os_cflags.push_str(" -nostdinc -fno-builtin -ffreestanding -Wall");
-                os_cflags.push_str(" -I");
-                os_cflags.push_str(RUXLIBC_INC);
-                os_cflags.push_str(" ");
-            } else if os_config.ulib == "ruxmusl" {
-                os_cflags.push_str(" -nostdinc -fno-builtin -ffreestanding -Wall");
-                os_cflags.push_str(" -I");
-                os_cflags.push_str(RUXMUSL_INC);
-                os_cflags.push_str(" ");
-            }
-            if os_config.platform.mode == "release" {
-                os_cflags.push_str(" -O3");
-            }
-            if os_config.platform.arch == "riscv64" {
-                os_cflags.push_str(" -march=rv64gc -mabi=lp64d -mcmodel=medany");
-            }
-            if !os_config.features.contains(&"fp_simd".to_string()) {
-                if os_config.platform.arch == "x86_64".to_string() {
-                    os_cflags.push_str(" -mno-sse");
-                } else if os_config.platform.arch == "aarch64".to_string() {
-                    os_cflags.push_str(" -mgeneral-regs-only");
-                }
-            }
+    /// Returns the command compiling the source file
+    /// # Arguments
+    /// * `target` - The target of the source file
+    fn command(&self, target: &Target) -> Result<Cmd> {
+        let target_config = target.target_config;
+        let mut cmd = if self.language.starts_with("c++") {
+            Cmd::new(&*target.build_config.cxx.read().unwrap())
+        } else {
+            Cmd::new(&*target.build_config.cc.read().unwrap())
+        };
+        cmd.args(&target.os_cflags);
+        cmd.flags(target_config.flags(self.language.trim_end_matches("-header")))?;
+        if let Some(pch) = &self.pch {
+            cmd.args(["-include", pch, "-Winvalid-pch"]);
         }
-
-        let mut cflags = String::new();
-        cflags.push_str(&os_cflags);
-        cflags.push_str(" ");
-        cflags.push_str(&target_config.cflags);
-        cmd.push_str(" ");
-        cmd.push_str(&cflags);
-        cmd.push_str(" -I");
-        cmd.push_str(&target_config.include_dir);
-        cmd.push_str(" -o ");
-        cmd.push_str(&self.obj_name);
+        cmd.arg(format!("-I{}", target_config.include_dir));
+        cmd.args(["-o", &self.obj_name]);
+        // let the compiler list the headers the object depends on
+        cmd.args(["-MMD", "-MF", &self.dep_name]);
 
         // consider some includes in other depandant_libs
-        for dependant_lib in dependant_libs {
-            cmd.push_str(" -I");
-            cmd.push_str(dependant_lib.target_config.include_dir.as_str());
-            cmd.push_str(" ");
+        for dependant_lib in &target.dependant_libs {
+            cmd.arg(format!("-I{}", dependant_lib.target_config.include_dir));
         }
 
-        cmd.push_str(" -c ");
-        cmd.push_str(&self.path);
+        // the compiler only needs to be told the language of extensions it does not know
+        let ext = Path::new(&self.path).extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        if default_src_extensions().get(ext) != Some(&self.language) {
+            cmd.args(["-x", &self.language]);
+        }
+        cmd.args(["-c", &self.path]);
 
         if target_config.typ == "dll" {
-            cmd.push_str(" -fPIC");
+            cmd.arg("-fPIC");
         }
+        Ok(cmd)
+    }
+
+    /// Records the diagnostics the compiler emitted for the source file, whether it compiled or not
+    /// # Arguments
+    /// * `result` - The result of the build of the source file
+    fn record_diagnostics(&self, result: &Result<Option<String>>) {
+        match result {
+            Ok(Some(stderr)) | Err(RuxgoError::Compile { stderr, .. }) => diagnostics::record(&self.path, stderr),
+            _ => {}
+        }
+    }
 
+    /// Returns the key of the object in the object cache, or None if the source can't be preprocessed
+    /// # Arguments
+    /// * `cmd` - The compile command
+    fn cache_key(&self, cmd: &Cmd) -> Option<String> {
+        // the same command, printing the preprocessed source instead of writing the object and depfile
+        let mut preprocess_cmd = Cmd::new(cmd.program());
+        let mut args = cmd.get_args().iter();
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("-o") | Some("-MF") => {
+                    args.next();
+                }
+                Some("-MMD") => {}
+                Some("-c") => {
+                    preprocess_cmd.arg("-E");
+                }
+                _ => {
+                    preprocess_cmd.arg(arg);
+                }
+            }
+        }
+        let output = preprocess_cmd.output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(ObjectCache::key(&command_record(cmd), &output.stdout))
+    }
+
+    /// Builds the source file with the given command, returns what the compiler printed on stderr if anything
+    ///
+    /// The object is taken from the local or the remote object cache instead if they are enabled and hold it
+    fn build(&self, cmd: Cmd) -> Result<Option<String>> {
         log(LogLevel::Info, &format!("Building: {}", &self.name));
         log(LogLevel::Info, &format!("  Command: {}", &cmd));
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&cmd)
+        let _span = timings::span("compile", &self.path);
+        let key = if cache::get().is_some() || remote_cache::get().is_some() {
+            self.cache_key(&cmd)
+        } else {
+            None
+        };
+        if let Some(key) = &key {
+            if let Some(cache) = cache::get() {
+                if let Some(hit) = cache.fetch(key, &self.obj_name, &self.dep_name)? {
+                    log(LogLevel::Info, &format!("  Cache hit: {}", &self.name));
+                    return Ok(Some(hit.stderr).filter(|stderr| !stderr.is_empty()));
+                }
+            }
+            if let Some(remote) = remote_cache::get() {
+                if let Some(stderr) = remote.fetch(key, &self.obj_name, &self.dep_name)? {
+                    log(LogLevel::Info, &format!("  Remote cache hit: {}", &self.name));
+                    if let Some(cache) = cache::get() {
+                        if let Err(e) = cache.store(key, &self.obj_name, &self.dep_name, &stderr) {
+                            log(LogLevel::Warn, &format!("Failed to cache {}: {}", &self.obj_name, e));
+                        }
+                    }
+                    return Ok(Some(stderr).filter(|stderr| !stderr.is_empty()));
+                }
+            }
+        }
+        let output = cmd
             .output()
-            .expect("failed to execute process");
+            .map_err(|why| RuxgoError::io("Failed to execute compiler", why))?;
         if output.status.success() {
             log(LogLevel::Info, &format!("  Success: {}", &self.name));
             let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.len() > 0 {
+            if !stdout.is_empty() {
                 log(LogLevel::Info, &format!("  Stdout: {}", stdout));
             }
             let stderr = String::from_utf8_lossy(&output.stderr);
-            if stderr.len() > 0 {
-                return Some(stderr.to_string());
+            if let Some(key) = &key {
+                // a broken cache must not fail the build
+                if let Some(cache) = cache::get() {
+                    if let Err(e) = cache.store(key, &self.obj_name, &self.dep_name, &stderr) {
+                        log(LogLevel::Warn, &format!("Failed to cache {}: {}", &self.obj_name, e));
+                    }
+                }
+                if let Some(remote) = remote_cache::get() {
+                    remote.store(key, &self.obj_name, &self.dep_name, &stderr);
+                }
             }
-            return None;
+            if !stderr.is_empty() {
+                return Ok(Some(stderr.to_string()));
+            }
+            Ok(None)
         } else {
-            log(LogLevel::Error, &format!("  Error: {}", &self.name));
-            log(LogLevel::Error, &format!("  Command: {}", &cmd));
-            log(LogLevel::Error, &format!("  Stdout: {}", String::from_utf8_lossy(&output.stdout)));
-            log(LogLevel::Error, &format!("  Stderr: {}", String::from_utf8_lossy(&output.stderr)));
-            std::process::exit(1);
+            Err(RuxgoError::Compile {
+                src: self.path.clone(),
+                cmd: cmd.to_string(),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_paths_mirror_the_source_tree() {
+        let src_dir = std::env::temp_dir().join(format!("ruxgo-{}-obj-names", std::process::id()));
+        fs::create_dir_all(&src_dir).unwrap();
+        let src_dir = src_dir.to_str().unwrap().to_string();
+        let build_config: BuildConfig = toml::from_str("compiler = \"gcc\"").unwrap();
+        let target_config: TargetConfig =
+            toml::from_str(&format!("name = \"libnet\"\ntype = \"static\"\nsrc = \"{}\"", src_dir)).unwrap();
+        let (os_config, targets, packages) = (OSConfig::default(), Vec::new(), Vec::new());
+        let target = Target::new(&build_config, &os_config, &target_config, &targets, &packages).unwrap();
+        let obj_name = |path: &str| target.get_src_obj_name(path);
+        // sources sharing a name get distinct objects
+        assert_eq!(obj_name("./src/net/util.c"), format!("{}/libnet/src/net/util.c.o", OBJ_DIR));
+        assert_eq!(obj_name("./src/fs/util.c"), format!("{}/libnet/src/fs/util.c.o", OBJ_DIR));
+        // parent dirs can't escape the object dir of the target
+        assert_eq!(obj_name("./src/../shared/util.c"), format!("{}/libnet/src/__/shared/util.c.o", OBJ_DIR));
+        assert_eq!(obj_name(&format!("{}/ulib/stdio.c", src_dir)), format!("{}/libnet/ulib/stdio.c.o", OBJ_DIR));
+        fs::remove_dir_all(&src_dir).unwrap();
+    }
+
+    #[test]
+    fn command_record_starts_with_the_identity_of_the_tool() {
+        let cmd = Cmd::parse("ruxgo-no-such-cc -O2 -c 'my main.c'").unwrap();
+        assert_eq!(command_record(&cmd), "unknown\truxgo-no-such-cc -O2 -c 'my main.c'");
+    }
+
+    #[test]
+    fn dll_links_the_binaries_of_its_packages() {
+        let src_dir = std::env::temp_dir().join(format!("ruxgo-{}-link-inputs", std::process::id()));
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(src_dir.join("net.c"), "int net(void) { return 0; }\n").unwrap();
+        let src_dir = src_dir.to_str().unwrap().to_string();
+        let build_config: BuildConfig = toml::from_str("compiler = \"gcc\"").unwrap();
+        let target = |typ: &str| -> TargetConfig {
+            toml::from_str(&format!("name = \"libnet\"\ntype = \"{}\"\nsrc = \"{}\"", typ, src_dir)).unwrap()
+        };
+        let package_target: TargetConfig = toml::from_str("name = \"libpkg\"\ntype = \"static\"").unwrap();
+        let packages = vec![Package::new(
+            "pkg".to_string(), "user/pkg".to_string(), "main".to_string(),
+            build_config.clone(), vec![package_target], Vec::new(),
+        )];
+        let (os_config, targets) = (OSConfig::default(), Vec::new());
+        let link_inputs = |target_config: &TargetConfig| {
+            let target = Target::new(&build_config, &os_config, target_config, &targets, &packages).unwrap();
+            target.link_inputs().into_keys().collect::<Vec<String>>()
+        };
+        // the objects mirror the sources from the src dir of the target
+        let obj = format!("{}/libnet/net.c.o", OBJ_DIR);
+        assert_eq!(link_inputs(&target("dll")), [format!("{}/libpkg.a", BUILD_DIR), obj.clone()]);
+        assert_eq!(link_inputs(&target("static")), [obj]);
+        fs::remove_dir_all(&src_dir).unwrap();
+    }
+
+    #[test]
+    fn os_cflags_follow_the_ulib_the_platform_and_the_features() {
+        assert!(os_cflags(&OSConfig::default()).unwrap().is_empty());
+
+        let mut os_config = OSConfig { name: "ruxos".to_string(), ulib: "ruxlibc".to_string(), ..OSConfig::default() };
+        os_config.platform.arch = "x86_64".to_string();
+        os_config.platform.mode = "release".to_string();
+        os_config.platform.log = "warn".to_string();
+        os_config.features = vec!["fs".to_string(), "paging".to_string()];
+        let cflags = os_cflags(&os_config).unwrap();
+        assert_eq!(cflags[..3], ["-DRUX_CONFIG_FS", "-DRUX_CONFIG_WARN", "-nostdinc"]);
+        assert_eq!(cflags[cflags.len() - 2..], ["-O3", "-mno-sse"]);
+
+        os_config.ulib = "ruxmusl".to_string();
+        os_config.platform.arch = "riscv64".to_string();
+        os_config.platform.mode = "debug".to_string();
+        let cflags = os_cflags(&os_config).unwrap();
+        assert_eq!(cflags[..4], ["-nostdinc", "-fno-builtin", "-ffreestanding", "-Wall"]);
+        assert_eq!(cflags[4], format!("-I{}", RUXMUSL_INC));
+        assert_eq!(cflags[5..], ["-march=rv64gc", "-mabi=lp64d", "-mcmodel=medany"]);
+    }
+}
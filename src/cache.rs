@@ -0,0 +1,388 @@
+//! This module contains the local object cache, shared by every project of the user.
+//!
+//! Objects are stored under a key hashed from the compiler identity, the command line and the
+//! preprocessed source, so the same source compiled the same way is only compiled once.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+use crate::utils::{log, LogLevel};
+use crate::error::{Result, RuxgoError};
+
+static CACHE: OnceLock<ObjectCache> = OnceLock::new();
+static STATS_FILE: &str = "stats.json";
+
+/// Represents an object cache directory
+pub struct ObjectCache {
+    dir: PathBuf,
+    /// Size limit in bytes, the least recently used objects are evicted above it
+    max_size: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// An object found in the cache
+pub struct CacheHit {
+    /// What the compiler printed on stderr when the object was built
+    pub stderr: String,
+}
+
+/// A file of a cache entry
+struct CacheFile {
+    path: PathBuf,
+    size: u64,
+    /// When the file was last written or used
+    used: SystemTime,
+}
+
+/// Statistics of a cache directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    #[serde(skip)]
+    pub entries: u64,
+    #[serde(skip)]
+    pub size: u64,
+}
+
+/// Enables the cache for the compilations of this invocation
+pub fn enable(cache: ObjectCache) {
+    let _ = CACHE.set(cache);
+}
+
+/// Returns the cache if it is enabled
+pub fn get() -> Option<&'static ObjectCache> {
+    CACHE.get()
+}
+
+/// Records the statistics of this invocation and evicts objects above the size limit, if the cache is enabled
+pub fn flush() -> Result<()> {
+    match get() {
+        Some(cache) => cache.flush(),
+        None => Ok(()),
+    }
+}
+
+impl ObjectCache {
+    /// Creates a cache
+    /// # Arguments
+    /// * `dir` - The directory holding the cached objects
+    /// * `max_size` - The size limit in bytes
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        ObjectCache {
+            dir,
+            max_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the directory of the cache
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the key of an object
+    /// # Arguments
+    /// * `command` - The compile command, along with the identity of the compiler
+    /// * `preprocessed` - The preprocessed source
+    pub fn key(command: &str, preprocessed: &[u8]) -> String {
+        let mut hasher = Xxh3::new();
+        hasher.update(command.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(preprocessed);
+        format!("{:032x}", hasher.digest128())
+    }
+
+    /// Returns the path of a file of an entry, entries are spread over subdirectories by key prefix
+    fn entry_path(&self, key: &str, ext: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.{}", key, ext))
+    }
+
+    /// Copies a cached object and its depfile into the build tree, returns None on a miss
+    /// # Arguments
+    /// * `key` - The key of the object
+    /// * `obj_path` - Where the object is expected
+    /// * `dep_path` - Where the depfile of the object is expected
+    pub fn fetch(&self, key: &str, obj_path: &str, dep_path: &str) -> Result<Option<CacheHit>> {
+        let cached_obj = self.entry_path(key, "o");
+        let cached_dep = self.entry_path(key, "d");
+        if !cached_obj.exists() || !cached_dep.exists() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        // another invocation may evict the entry while it is copied, a part of it is not kept
+        if fs::copy(&cached_obj, obj_path).is_err() || fs::copy(&cached_dep, dep_path).is_err() {
+            let _ = fs::remove_file(obj_path);
+            let _ = fs::remove_file(dep_path);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        let stderr = fs::read_to_string(self.entry_path(key, "stderr")).unwrap_or_default();
+        // the modification time of the object tells when it was last used
+        if let Ok(file) = File::options().write(true).open(&cached_obj) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(CacheHit { stderr }))
+    }
+
+    /// Stores a freshly compiled object and its depfile
+    /// # Arguments
+    /// * `key` - The key of the object
+    /// * `obj_path` - The path of the object
+    /// * `dep_path` - The path of the depfile of the object
+    /// * `stderr` - What the compiler printed on stderr
+    pub fn store(&self, key: &str, obj_path: &str, dep_path: &str, stderr: &str) -> Result<()> {
+        let entry_dir = self.dir.join(&key[..2]);
+        fs::create_dir_all(&entry_dir)
+            .map_err(|why| RuxgoError::io(format!("Failed to create {}", entry_dir.display()), why))?;
+        if !stderr.is_empty() {
+            write_atomic(&self.entry_path(key, "stderr"), |tmp| fs::write(tmp, stderr))?;
+        }
+        write_atomic(&self.entry_path(key, "d"), |tmp| fs::copy(dep_path, tmp).map(|_| ()))?;
+        // the object is written last, an entry is complete once it exists
+        write_atomic(&self.entry_path(key, "o"), |tmp| fs::copy(obj_path, tmp).map(|_| ()))
+    }
+
+    /// Returns the statistics recorded by the previous invocations, and the current content of the cache
+    pub fn stats(&self) -> Result<CacheStats> {
+        let mut stats = self.recorded_stats();
+        for files in self.entries()? {
+            stats.entries += 1;
+            stats.size += files.iter().map(|file| file.size).sum::<u64>();
+        }
+        Ok(stats)
+    }
+
+    /// Removes every object and the statistics
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)
+                .map_err(|why| RuxgoError::io(format!("Failed to remove {}", self.dir.display()), why))?;
+        }
+        Ok(())
+    }
+
+    /// Adds the hits and misses of this invocation to the recorded statistics, then evicts
+    /// the least recently used objects until the cache fits its size limit
+    fn flush(&self) -> Result<()> {
+        let hits = self.hits.swap(0, Ordering::Relaxed);
+        let misses = self.misses.swap(0, Ordering::Relaxed);
+        if hits == 0 && misses == 0 {
+            return Ok(());
+        }
+        let mut stats = self.recorded_stats();
+        stats.hits += hits;
+        stats.misses += misses;
+        let contents = serde_json::to_string(&stats)
+            .map_err(|why| RuxgoError::Config(format!("Failed to serialize cache stats: {}", why)))?;
+        fs::create_dir_all(&self.dir)
+            .map_err(|why| RuxgoError::io(format!("Failed to create {}", self.dir.display()), why))?;
+        write_atomic(&self.dir.join(STATS_FILE), |tmp| fs::write(tmp, &contents))?;
+        log(LogLevel::Info, &format!("Object cache: {} hit(s), {} miss(es)", hits, misses));
+
+        let mut entries = self.entries()?
+            .into_iter()
+            .map(|files| {
+                let size = files.iter().map(|file| file.size).sum::<u64>();
+                let used = files.iter().map(|file| file.used).max().unwrap_or(SystemTime::UNIX_EPOCH);
+                (used, size, files)
+            })
+            .collect::<Vec<_>>();
+        let mut total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        entries.sort_by_key(|(used, _, _)| *used);
+        for (_, size, files) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            // the object goes first, so that a half removed entry is a miss
+            for file in files.iter().rev() {
+                let _ = fs::remove_file(&file.path);
+            }
+            total -= size;
+        }
+        Ok(())
+    }
+
+    /// Reads the statistics file, a missing or unreadable file gives zeros
+    fn recorded_stats(&self) -> CacheStats {
+        fs::read_to_string(self.dir.join(STATS_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Lists the entries of the cache, each with its files
+    fn entries(&self) -> Result<Vec<Vec<CacheFile>>> {
+        let mut entries: HashMap<String, Vec<CacheFile>> = HashMap::new();
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let dirs = fs::read_dir(&self.dir)
+            .map_err(|why| RuxgoError::io(format!("Failed to read {}", self.dir.display()), why))?;
+        for dir in dirs.flatten().filter(|dir| dir.path().is_dir()) {
+            for file in fs::read_dir(dir.path()).into_iter().flatten().flatten() {
+                let path = file.path();
+                let (Some(key), Ok(metadata)) = (path.file_stem(), file.metadata()) else {
+                    continue;
+                };
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                entries
+                    .entry(key.to_string_lossy().to_string())
+                    .or_default()
+                    .push(CacheFile { path, size: metadata.len(), used });
+            }
+        }
+        // keeps the object last, it is what marks an entry as complete
+        let mut entries = entries.into_values().collect::<Vec<_>>();
+        for files in entries.iter_mut() {
+            files.sort_by_key(|file| file.path.extension().is_some_and(|ext| ext == "o"));
+        }
+        Ok(entries)
+    }
+}
+
+/// Writes a file through a temp file renamed over it, so that readers never see it half written
+/// # Arguments
+/// * `path` - The path of the file
+/// * `write` - Writes the content to the given temp path
+fn write_atomic(path: &Path, write: impl FnOnce(&Path) -> std::io::Result<()>) -> Result<()> {
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    write(&tmp_path)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|why| {
+            let _ = fs::remove_file(&tmp_path);
+            RuxgoError::io(format!("Failed to write {}", path.display()), why)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A cache in an empty temp dir, along with a dir for the build outputs
+    fn cache(name: &str, max_size: u64) -> (ObjectCache, PathBuf) {
+        let root = std::env::temp_dir().join(format!("ruxgo-{}-cache-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("bld")).unwrap();
+        (ObjectCache::new(root.join("cache"), max_size), root.join("bld"))
+    }
+
+    /// Compiles an object, that is writes the object and its depfile into the build dir
+    fn compile(bld: &Path, name: &str, obj: &str) -> (String, String) {
+        let obj_path = bld.join(format!("{}.o", name)).to_str().unwrap().to_string();
+        let dep_path = bld.join(format!("{}.d", name)).to_str().unwrap().to_string();
+        fs::write(&obj_path, obj).unwrap();
+        fs::write(&dep_path, format!("{}.o: {}.c", name, name)).unwrap();
+        (obj_path, dep_path)
+    }
+
+    /// Sets when every file of an entry was last used
+    fn set_used(cache: &ObjectCache, key: &str, secs: u64) {
+        for ext in ["o", "d"] {
+            let file = File::options().write(true).open(cache.entry_path(key, ext)).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        }
+    }
+
+    #[test]
+    fn stored_object_is_fetched_with_its_depfile_and_warnings() {
+        let (cache, bld) = cache("round-trip", u64::MAX);
+        let key = ObjectCache::key("gcc 13\tgcc -c main.c", b"int main(void) { return 0; }");
+        let (obj_path, dep_path) = compile(&bld, "main", "object");
+        assert!(cache.fetch(&key, &obj_path, &dep_path).unwrap().is_none());
+
+        cache.store(&key, &obj_path, &dep_path, "main.c:1: warning: unused").unwrap();
+        fs::remove_file(&obj_path).unwrap();
+        fs::remove_file(&dep_path).unwrap();
+        let hit = cache.fetch(&key, &obj_path, &dep_path).unwrap().unwrap();
+        assert_eq!(hit.stderr, "main.c:1: warning: unused");
+        assert_eq!(fs::read_to_string(&obj_path).unwrap(), "object");
+        assert_eq!(fs::read_to_string(&dep_path).unwrap(), "main.o: main.c");
+    }
+
+    #[test]
+    fn entry_failing_to_copy_is_a_miss_leaving_nothing_behind() {
+        let (cache, bld) = cache("partial", u64::MAX);
+        let key = ObjectCache::key("gcc -c main.c", b"int a;");
+        let (obj_path, dep_path) = compile(&bld, "main", "object");
+        cache.store(&key, &obj_path, &dep_path, "").unwrap();
+        fs::remove_file(&obj_path).unwrap();
+        fs::remove_file(&dep_path).unwrap();
+        // the object is copied, the depfile can't be, as if the entry was evicted in between
+        let cached_dep = cache.entry_path(&key, "d");
+        fs::remove_file(&cached_dep).unwrap();
+        fs::create_dir(&cached_dep).unwrap();
+
+        assert!(cache.fetch(&key, &obj_path, &dep_path).unwrap().is_none());
+        assert!(!Path::new(&obj_path).exists() && !Path::new(&dep_path).exists());
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn key_changes_with_the_command_and_the_preprocessed_source() {
+        let key = ObjectCache::key("gcc -c main.c", b"int a;");
+        assert_ne!(key, ObjectCache::key("gcc -O2 -c main.c", b"int a;"));
+        assert_ne!(key, ObjectCache::key("gcc -c main.c", b"int b;"));
+        // the command can't run into the source
+        assert_ne!(ObjectCache::key("gcc -c a", b"b"), ObjectCache::key("gcc -c ", b"ab"));
+    }
+
+    #[test]
+    fn stats_add_up_the_invocations() {
+        let (cache, bld) = cache("stats", u64::MAX);
+        let (obj_path, dep_path) = compile(&bld, "main", "object");
+        let key = ObjectCache::key("gcc -c main.c", b"int a;");
+        assert!(cache.fetch(&key, &obj_path, &dep_path).unwrap().is_none());
+        cache.store(&key, &obj_path, &dep_path, "").unwrap();
+        assert!(cache.fetch(&key, &obj_path, &dep_path).unwrap().is_some());
+        cache.flush().unwrap();
+
+        let cache = ObjectCache::new(cache.dir.clone(), u64::MAX);
+        assert!(cache.fetch(&key, &obj_path, &dep_path).unwrap().is_some());
+        cache.flush().unwrap();
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.size, ("object".len() + "main.o: main.c".len()) as u64);
+
+        cache.clear().unwrap();
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 0, 0));
+    }
+
+    #[test]
+    fn flush_evicts_the_least_recently_used_objects() {
+        let entry_size = ("object".len() + "a.o: a.c".len()) as u64;
+        let (cache, bld) = cache("eviction", 2 * entry_size);
+        let mut keys = Vec::new();
+        for name in ["a", "b", "c"] {
+            let (obj_path, dep_path) = compile(&bld, name, "object");
+            let key = ObjectCache::key(name, b"");
+            cache.store(&key, &obj_path, &dep_path, "").unwrap();
+            keys.push((key, obj_path, dep_path));
+        }
+        set_used(&cache, &keys[0].0, 1000);
+        set_used(&cache, &keys[1].0, 2000);
+        set_used(&cache, &keys[2].0, 3000);
+        // a is used again, b becomes the least recently used
+        let (key, obj_path, dep_path) = &keys[0];
+        assert!(cache.fetch(key, obj_path, dep_path).unwrap().is_some());
+        cache.flush().unwrap();
+
+        let cached = |key: &str| cache.entry_path(key, "o").exists();
+        assert!(cached(&keys[0].0));
+        assert!(!cached(&keys[1].0));
+        assert!(!cache.entry_path(&keys[1].0, "d").exists());
+        assert!(cached(&keys[2].0));
+        assert_eq!(cache.stats().unwrap().entries, 2);
+    }
+}
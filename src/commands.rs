@@ -2,11 +2,24 @@ use crate::builder::Target;
 use crate::global_cfg::GlobalConfig;
 use crate::utils::{self, BuildConfig, TargetConfig, OSConfig, QemuConfig, Package, log, LogLevel};
 use crate::features;
+use crate::graph::BuildGraph;
+use crate::build_state;
+use crate::scheduler;
+use crate::error::{Result, RuxgoError};
+use crate::process::Cmd;
+use crate::jobs;
+use crate::explain::{self, Reason};
+use crate::timings;
 use std::path::Path;
 use std::io::Write;
 use std::fs;
 use std::process::{Command, Stdio};
 
+/// The config file of the project, in the directory ruxgo works in
+#[cfg(target_os = "linux")]
+pub static CONFIG_FILE: &str = "./config_linux.toml";
+#[cfg(target_os = "windows")]
+pub static CONFIG_FILE: &str = "./config_win32.toml";
 static ROOT_DIR: &str = "ruxos_bld";
 static BUILD_DIR: &str = "ruxos_bld/bin";
 #[cfg(target_os = "windows")]
@@ -26,124 +39,71 @@ static ULIB_RUXMUSL_SRC: &str = concat!(env!("HOME"), "/ruxos/ulib/ruxmusl/musl-
 /// * `os_config` - The local os configuration
 /// * `packages` - A vector of packages to clean
 /// * `choices` - A vector of choices to select which components to delete
-pub fn clean(targets: &Vec<TargetConfig>, os_config: &OSConfig, packages: &Vec<Package>, choices: Vec<String>) {
+pub fn clean(targets: &Vec<TargetConfig>, os_config: &OSConfig, packages: &Vec<Package>, choices: Vec<String>) -> Result<()> {
     if Path::new(ROOT_DIR).exists() {
-        fs::create_dir_all(ROOT_DIR).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not remove binary directory: {}", why));
-        });
+        fs::create_dir_all(ROOT_DIR)
+            .map_err(|why| RuxgoError::io("Could not remove binary directory", why))?;
     }
 
     // removes os if choice includes "OS" or choice includes "All"
-    if choices.contains(&String::from("OS")) || choices.contains(&String::from("All")) {
-        if Path::new(TARGET_DIR).exists() {
-            log(LogLevel::Log, &format!("Cleaning: {}", TARGET_DIR));
-            fs::remove_dir_all(TARGET_DIR).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not remove target directory: {}", why));
-            });
-        }
+    if (choices.contains(&String::from("OS")) || choices.contains(&String::from("All")))
+        && Path::new(TARGET_DIR).exists() {
+        log(LogLevel::Log, &format!("Cleaning: {}", TARGET_DIR));
+        fs::remove_dir_all(TARGET_DIR)
+            .map_err(|why| RuxgoError::io("Could not remove target directory", why))?;
     }
 
     // removes ulib if choice includes "Ulib" or choice includes "All"
     if choices.contains(&String::from("Ulib")) || choices.contains(&String::from("All")) {
         if os_config.ulib == "ruxlibc" {
-            let libc_hash_pash = "ruxos_bld/libc.linux.hash";
-            if Path::new(libc_hash_pash).exists() {
-                fs::remove_file(libc_hash_pash).unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Could not remove hash file: {}", why));
-                });
-                log(LogLevel::Info, &format!("Cleaning: {}", libc_hash_pash));
-            }
+            let mut state = build_state::lock();
+            state.forget_target(&format!("{}/libc", OBJ_DIR), &format!("{}/libc.a", BUILD_DIR));
+            state.save()?;
             if Path::new(BUILD_DIR).exists() {
                 let mut ulib_bin_name = String::from("");
                 if os_config.ulib == "ruxlibc" {
                     ulib_bin_name = format!("{}/libc.a", BUILD_DIR);
                 }
                 if Path::new(&ulib_bin_name).exists() {
-                    fs::remove_file(&ulib_bin_name).unwrap_or_else(|why| {
-                        log(LogLevel::Error, &format!("Could not remove binary file: {}", why));
-                    });
+                    fs::remove_file(&ulib_bin_name)
+                        .map_err(|why| RuxgoError::io("Could not remove binary file", why))?;
                     log(LogLevel::Log, &format!("Cleaning: {}", &ulib_bin_name));
                 }
             }
-        } else if os_config.ulib == "ruxmusl" {
-            if Path::new(RUXMUSL_DIR).exists() {
-                log(LogLevel::Log, &format!("Cleaning: {}", RUXMUSL_DIR));
-                fs::remove_dir_all(RUXMUSL_DIR).unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Could not remove target directory: {}", why));
-                });
-            }
+        } else if os_config.ulib == "ruxmusl" && Path::new(RUXMUSL_DIR).exists() {
+            log(LogLevel::Log, &format!("Cleaning: {}", RUXMUSL_DIR));
+            fs::remove_dir_all(RUXMUSL_DIR)
+                .map_err(|why| RuxgoError::io("Could not remove target directory", why))?;
         }
     }
 
     // removes bins of targets if choice includes "App_libs" or choice includes "All"
     if choices.contains(&String::from("App_libs")) || choices.contains(&String::from("All")) {
+        let mut state = build_state::lock();
         // removes local bins of targets
         for target in targets {
-            #[cfg(target_os = "windows")]
-            let hash_path = format!("ruxos_bld/{}.win32.hash", &target.name);
-            #[cfg(target_os = "linux")]
-            let hash_path = format!("ruxos_bld/{}.linux.hash", &target.name);
-            if Path::new(&hash_path).exists() {
-                log(LogLevel::Info, &format!("Cleaning: {}", &hash_path));
-                fs::remove_file(&hash_path).unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Could not remove hash file: {}", why));
-                });
-            }
             if Path::new(BUILD_DIR).exists() {
-                let mut bin_name = String::new();
-                let mut elf_name = String::new();
-                bin_name.push_str(BUILD_DIR);
-                bin_name.push_str("/");
-                bin_name.push_str(&target.name);
-                #[cfg(target_os = "windows")]
-                if target.typ == "exe" {
-                    bin_name.push_str(".exe");
-                } else if target.typ == "dll" {
-                    bin_name.push_str(".dll");
-                }
-                #[cfg(target_os = "linux")]
-                if target.typ == "exe" {
-                    elf_name = bin_name.clone();
-                    bin_name.push_str(".bin");
-                    elf_name.push_str(".elf");
-                } else if target.typ == "dll" {
-                    bin_name.push_str(".so");
-                } else if target.typ == "static" {
-                    bin_name.push_str(".a");
-                } else if target.typ == "object" {
-                    bin_name.push_str(".o");
-                }
+                let (bin_name, elf_name) = target_bins(target);
                 if Path::new(&bin_name).exists() {
                     log(LogLevel::Log, &format!("Cleaning: {}", &bin_name));
-                    fs::remove_file(&bin_name).unwrap_or_else(|why| {
-                        log(LogLevel::Error, &format!("Could not remove binary file: {}", why));
-                    });
+                    fs::remove_file(&bin_name)
+                        .map_err(|why| RuxgoError::io("Could not remove binary file", why))?;
                 }
                 if Path::new(&elf_name).exists() {
                     log(LogLevel::Log, &format!("Cleaning: {}", &elf_name));
-                    fs::remove_file(&elf_name).unwrap_or_else(|why| {
-                        log(LogLevel::Error, &format!("Could not remove ELF file: {}", why));
-                    });
+                    fs::remove_file(&elf_name)
+                        .map_err(|why| RuxgoError::io("Could not remove ELF file", why))?;
                 }
+                state.forget_target(&format!("{}/{}", OBJ_DIR, &target.name), &bin_name);
             }
         }
         // removes bins of packages if have
         for pack in packages {
             for target in &pack.target_configs {
-                #[cfg(target_os = "windows")]
-                let hash_path = format!("ruxos_bld/{}.win32.hash", &target.name);
-                #[cfg(target_os = "linux")]
-                let hash_path = format!("ruxos_bld/{}.linux.hash", &target.name);
-                if Path::new(&hash_path).exists() {
-                    log(LogLevel::Info, &format!("Cleaning: {}", &hash_path));
-                    fs::remove_file(&hash_path).unwrap_or_else(|why| {
-                        log(LogLevel::Error, &format!("Could not remove hash file: {}", why));
-                    });
-                }
                 if Path::new(BUILD_DIR).exists() {
                     let mut bin_name = String::new();
                     bin_name.push_str(BUILD_DIR);
-                    bin_name.push_str("/");
+                    bin_name.push('/');
                     bin_name.push_str(&target.name);
                     #[cfg(target_os = "windows")]
                     if target.typ == "dll" {
@@ -159,82 +119,150 @@ pub fn clean(targets: &Vec<TargetConfig>, os_config: &OSConfig, packages: &Vec<P
                     }
                     if Path::new(&bin_name).exists() {
                         log(LogLevel::Log, &format!("Cleaning: {}", &bin_name));
-                        fs::remove_file(&bin_name).unwrap_or_else(|why| {
-                            log(LogLevel::Error, &format!("Could not remove binary file: {}", why));
-                        });
+                        fs::remove_file(&bin_name)
+                            .map_err(|why| RuxgoError::io("Could not remove binary file", why))?;
                     }
+                    state.forget_target(&format!("{}/{}", OBJ_DIR, &target.name), &bin_name);
                 }
             }
         }
+        state.save()?;
     }
 
     // removes obj if choice includes "Obj" or choice includes "All"
-    if choices.contains(&String::from("Obj")) || choices.contains(&String::from("All")) {
-        if Path::new(OBJ_DIR).exists() {
-            log(LogLevel::Log, &format!("Cleaning: {}", OBJ_DIR));
-            fs::remove_dir_all(OBJ_DIR).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not remove object directory: {}", why));
-            });
-        }
+    if (choices.contains(&String::from("Obj")) || choices.contains(&String::from("All")))
+        && Path::new(OBJ_DIR).exists() {
+        log(LogLevel::Log, &format!("Cleaning: {}", OBJ_DIR));
+        fs::remove_dir_all(OBJ_DIR)
+            .map_err(|why| RuxgoError::io("Could not remove object directory", why))?;
+        let mut state = build_state::lock();
+        state.forget_all();
+        state.save()?;
     }
 
     // removes downloaded packages if choice includes "Packages" or choice includes "All"
-    if choices.contains(&String::from("Packages")) || choices.contains(&String::from("All")) {
-        if Path::new(PACKAGES_DIR).exists() {
-            log(LogLevel::Log, &format!("Cleaning: {}", PACKAGES_DIR));
-            fs::remove_dir_all(PACKAGES_DIR).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not remove packages directory: {}", why));
-            });
+    if (choices.contains(&String::from("Packages")) || choices.contains(&String::from("All")))
+        && Path::new(PACKAGES_DIR).exists() {
+        log(LogLevel::Log, &format!("Cleaning: {}", PACKAGES_DIR));
+        fs::remove_dir_all(PACKAGES_DIR)
+            .map_err(|why| RuxgoError::io("Could not remove packages directory", why))?;
+    }
+    Ok(())
+}
+
+/// Cleans the binaries and objects of the given targets only
+/// # Arguments
+/// * `targets` - A vector of targets
+/// * `packages` - A vector of packages, whose targets can be cleaned too
+/// * `names` - The names of the targets to clean
+pub fn clean_targets(targets: &[TargetConfig], packages: &[Package], names: &[String]) -> Result<()> {
+    let mut state = build_state::lock();
+    for name in names {
+        // a local target shadows a package target with the same name
+        let target = targets
+            .iter()
+            .chain(packages.iter().flat_map(|pkg| pkg.target_configs.iter()))
+            .find(|target| target.name == *name)
+            .ok_or_else(|| RuxgoError::Config(format!("Target {} does not exist", name)))?;
+        let (bin_name, elf_name) = target_bins(target);
+        let obj_dir = format!("{}/{}", OBJ_DIR, &target.name);
+        for bin in [&bin_name, &elf_name] {
+            if Path::new(bin).is_file() {
+                log(LogLevel::Log, &format!("Cleaning: {}", bin));
+                fs::remove_file(bin)
+                    .map_err(|why| RuxgoError::io("Could not remove binary file", why))?;
+            }
         }
+        if Path::new(&obj_dir).exists() {
+            log(LogLevel::Log, &format!("Cleaning: {}", &obj_dir));
+            fs::remove_dir_all(&obj_dir)
+                .map_err(|why| RuxgoError::io("Could not remove object directory", why))?;
+        }
+        state.forget_target(&obj_dir, &bin_name);
     }
+    state.save()
+}
+
+/// Returns the binary of a target, and its ELF file if it has one
+fn target_bins(target: &TargetConfig) -> (String, String) {
+    let name = format!("{}/{}", BUILD_DIR, &target.name);
+    #[cfg(target_os = "windows")]
+    let suffix = match target.typ.as_str() {
+        "exe" => ".exe",
+        "dll" => ".dll",
+        _ => "",
+    };
+    #[cfg(target_os = "linux")]
+    let suffix = match target.typ.as_str() {
+        "exe" => ".bin",
+        "dll" => ".so",
+        "static" => ".a",
+        "object" => ".o",
+        _ => "",
+    };
+    let elf_name = if cfg!(target_os = "linux") && target.typ == "exe" {
+        format!("{}.elf", name)
+    } else {
+        String::new()
+    };
+    (format!("{}{}", name, suffix), elf_name)
 }
 
-/// Builds all targets
+/// Options of a build, given on the command line
+#[derive(Debug, Default, Clone)]
+pub struct BuildOptions {
+    /// Generate a compile_commands.json file
+    pub gen_cc: bool,
+    /// Generate a .vscode/c_cpp_properties.json file
+    pub gen_vsc: bool,
+    /// Build everything that does not depend on a failure, instead of stopping at the first error
+    pub keep_going: bool,
+    /// Names of the targets to build along with their dependencies, every target if empty
+    pub targets: Vec<String>,
+}
+
+/// Builds the selected targets and their dependencies, all targets if none is selected
 /// # Arguments
 /// * `build_config` - The local build configuration
 /// * `targets` - A vector of targets to build
 /// * `os_config` - The local os configuration
-/// * `gen_cc` - Whether to generate a compile_commands.json file
-/// * `gen_vsc` - Whether to generate a .vscode/c_cpp_properties.json file
 /// * `packages` - A vector of packages to get libs
+/// * `options` - The options of the build
 pub fn build(
     build_config: &BuildConfig, 
     targets: &Vec<TargetConfig>, 
     os_config: &OSConfig,
-    gen_cc: bool, 
-    gen_vsc: bool, 
-    packages: &Vec<Package>
-) {
-    if !Path::new(ROOT_DIR).exists() {
-        fs::create_dir(ROOT_DIR).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not create ruxos_bld directory: {}", why));
-            std::process::exit(1);
-        });
+    packages: &Vec<Package>,
+    options: &BuildOptions,
+) -> Result<()> {
+    // the generated files are only listed by a dry run, see `pre_gen_cc` and `pre_gen_vsc`
+    let gen_cc = options.gen_cc && !explain::is_dry_run();
+    // Resolve the dependencies before anything gets compiled
+    let mut graph = BuildGraph::new(build_config, targets, packages)?.select(&options.targets)?;
+    // ruxlibc is built along with the targets, and the exe targets wait for it
+    let ruxlibc = ruxlibc_target(os_config);
+    let (ulib_targets, ulib_packages) = (Vec::new(), Vec::new());
+    if os_config != &OSConfig::default() && os_config.ulib == "ruxlibc" {
+        graph.add_ulib(build_config, &ruxlibc, &ulib_targets, &ulib_packages);
+    }
+    if !explain::is_dry_run() && !Path::new(ROOT_DIR).exists() {
+        fs::create_dir(ROOT_DIR)
+            .map_err(|why| RuxgoError::io("Could not create ruxos_bld directory", why))?;
     }
     if gen_cc {
         let mut cc_file = fs::OpenOptions::new()
-            .write(true)
             .append(true)
             .open("compile_commands.json")
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not open cc file: {}", why));
-                std::process::exit(1);
-            });
-        cc_file.write_all(b"[").unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not write to cc file: {}", why));
-            std::process::exit(1);
-        });
+            .map_err(|why| RuxgoError::io("Could not open cc file", why))?;
+        cc_file.write_all(b"[")
+            .map_err(|why| RuxgoError::io("Could not write to cc file", why))?;
     }
     
-    if gen_vsc {
+    if options.gen_vsc && !explain::is_dry_run() {
         let mut vsc_file = fs::OpenOptions::new()
-            .write(true)
             .append(true)
             .open(".vscode/c_cpp_properties.json")
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not open vsc file: {}", why));
-                std::process::exit(1);
-            });
+            .map_err(|why| RuxgoError::io("Could not open vsc file", why))?;
 
         let mut inc_dirs: Vec<String> = targets.iter().map(|t| t.include_dir.clone()).collect();
         for package in packages {
@@ -253,22 +281,20 @@ pub fn build(
         }
 
         #[cfg(target_os = "windows")]
-        let compiler_path = Command::new("sh")
-            .arg("-c")
-            .arg(&format!("where {}", &compiler_path))
+        let compiler_path = Command::new("where")
+            .arg(&compiler_path)
             .output()
-            .expect("failed to execute process")
+            .map_err(|why| RuxgoError::io("Failed to locate compiler", why))?
             .stdout;
 
         #[cfg(target_os = "windows")]
         //Pick the first compiler path
-        let compiler_path = String::from_utf8(compiler_path)
-            .unwrap()
-            .split("\n")
-            .collect::<Vec<&str>>()[0]
-            .to_string()
-            .replace("\r", "")
-            .replace("\\", "/");
+        let compiler_path = String::from_utf8_lossy(&compiler_path)
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .replace('\r', "")
+            .replace('\\', "/");
         #[cfg(target_os = "windows")]
         let vsc_json = format!(
             r#"{{
@@ -296,15 +322,14 @@ pub fn build(
             intellimode
         );
         #[cfg(target_os = "linux")]
-        let compiler_path = Command::new("sh")
-            .arg("-c")
-            .arg(&format!("which {}", &compiler_path))
+        let compiler_path = Command::new("which")
+            .arg(&compiler_path)
             .output()
-            .expect("failed to execute process")
+            .map_err(|why| RuxgoError::io("Failed to locate compiler", why))?
             .stdout;
 
         #[cfg(target_os = "linux")]
-        let compiler_path = String::from_utf8(compiler_path).unwrap().replace('\n', "");
+        let compiler_path = String::from_utf8_lossy(&compiler_path).replace('\n', "");
 
         #[cfg(target_os = "linux")]
         let vsc_json = format!(
@@ -334,175 +359,213 @@ pub fn build(
         );
 
         //Write to file
-        vsc_file.write_all(vsc_json.as_bytes()).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not write to vsc file: {}", why));
-            std::process::exit(1);
-        });
+        vsc_file.write_all(vsc_json.as_bytes())
+            .map_err(|why| RuxgoError::io("Could not write to vsc file", why))?;
     }
     
-    // Construct os and ulib
+    // Construct os and ulib, in keep-going mode their failures only stop the targets linked with them
+    let mut failures = Vec::new();
     if os_config != &OSConfig::default() {
-        let (rux_feats_final, lib_feats_final) = features::cfg_feat_addprefix(os_config);
-        if os_config.ulib == "ruxlibc" {
-            log(LogLevel::Log, &format!("Compiling OS: {}", os_config.name));
-            build_os(&os_config, &os_config.ulib, &rux_feats_final, &lib_feats_final);
-            log(LogLevel::Log, &format!("Compiling Ulib: {}", os_config.ulib));
-            build_ruxlibc(build_config, os_config, gen_cc);
-        } else if os_config.ulib == "ruxmusl" {
+        let (rux_feats_final, lib_feats_final) = features::cfg_feat_addprefix(os_config)?;
+        if os_config.ulib == "ruxlibc" || os_config.ulib == "ruxmusl" {
             log(LogLevel::Log, &format!("Compiling OS: {}", os_config.name));
-            build_os(&os_config, &os_config.ulib, &rux_feats_final, &lib_feats_final);
+            if let Err(e) = build_os(os_config, &os_config.ulib, &rux_feats_final, &lib_feats_final) {
+                if !options.keep_going {
+                    return Err(e);
+                }
+                failures.push(e);
+            }
+        }
+        if os_config.ulib == "ruxmusl" {
             log(LogLevel::Log, &format!("Compiling Ulib: {}", os_config.ulib));
-            build_ruxmusl(build_config, os_config);
+            if let Err(e) = build_ruxmusl(build_config, os_config) {
+                if !options.keep_going {
+                    return Err(e);
+                }
+                failures.push(e);
+            }
         }
     };
 
-    // Construct each target separately
-    for target in targets {
-        let mut tgt = Target::new(build_config, os_config, target, targets, packages);
-        tgt.build(gen_cc);
-    }
+    // Construct the targets, independent targets are built in parallel
+    scheduler::build_graph(&graph, os_config, options, failures)?;
 
     if gen_cc {
         let mut cc_file = fs::OpenOptions::new()
-            .write(true)
             .read(true)
             .append(true)
             .open("compile_commands.json")
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not open cc file: {}", why));
-                std::process::exit(1);
-            });
-        cc_file.write_all(b"]").unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not write to cc file: {}", why));
-            std::process::exit(1);
-        });
-    }
-    log(LogLevel::Log, "Build complete!");
+            .map_err(|why| RuxgoError::io("Could not open cc file", why))?;
+        cc_file.write_all(b"]")
+            .map_err(|why| RuxgoError::io("Could not write to cc file", why))?;
+    }
+    if explain::is_dry_run() {
+        log(LogLevel::Log, "Dry run complete, nothing was run");
+    } else {
+        log(LogLevel::Log, "Build complete!");
+    }
+    Ok(())
 }
 
 /// Builds the specified os
-fn build_os(os_config: &OSConfig, ulib: &str, rux_feats: &Vec<String>, lib_feats: &Vec<String>) {
-    let target = format!("--target {}", os_config.platform.target);
-    let target_dir = format!("--target-dir {}/target", ROOT_DIR);
-    let mode = format!("--{}", os_config.platform.mode);
-    let os_ulib = format!("-p {}", ulib);
+fn build_os(os_config: &OSConfig, ulib: &str, rux_feats: &[String], lib_feats: &[String]) -> Result<()> {
+    let mut cmd = Cmd::new("cargo");
+    cmd.args(["build", "--target", &os_config.platform.target]);
+    cmd.arg("--target-dir").arg(format!("{}/target", ROOT_DIR));
+    cmd.arg(format!("--{}", os_config.platform.mode));
+    cmd.args(["-p", ulib]);
+    cmd.arg("-j").arg(jobs::count().to_string());
     // add verbose
-    let verbose = match os_config.platform.v.as_str() {
-        "1" => "-v",
-        "2" => "-vv",
-        _ => "",
+    match os_config.platform.v.as_str() {
+        "1" => cmd.arg("-v"),
+        "2" => cmd.arg("-vv"),
+        _ => &mut cmd,
     };
     // add features
-    let features = [&rux_feats[..], &lib_feats[..]].concat().join(" ");
-    let cmd = format!(
-        "cargo build {} {} {} {} {} --features \"{}\"",
-        target, target_dir, mode, os_ulib, verbose, features
-    );
+    cmd.arg("--features").arg([rux_feats, lib_feats].concat().join(" "));
+    // cargo tells itself whether the os is up to date
+    if !explain::step("build", &format!("os {}", os_config.name), &cmd, &Reason::Forced("cargo checks the os itself".to_string())) {
+        return Ok(());
+    }
     log(LogLevel::Info, &format!("Command: {}", cmd));
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-        .expect("Failed to execute command");
-    if !output.status.success() {
-        log(LogLevel::Error, &format!("Command execution failed: {:?}", output.stderr));
-        std::process::exit(1);
+    let _span = timings::span("cargo", &os_config.name);
+    let status = cmd
+        .status()
+        .map_err(|why| RuxgoError::io("Failed to execute cargo", why))?;
+    if !status.success() {
+        return Err(RuxgoError::Toolchain(format!("Cargo build of the OS failed: {}", status)));
     }
+    Ok(())
 } 
 
-/// Builds the ruxlibc
-fn build_ruxlibc(build_config: &BuildConfig, os_config: &OSConfig, gen_cc: bool) {
-    if !Path::new(BUILD_DIR).exists() {
-        fs::create_dir_all(BUILD_DIR).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Couldn't create build dir: {}", why));
-            std::process::exit(1);
-        })
-    }
-    let ulib_tgt = TargetConfig {
+/// Returns the target building ruxlibc from the sources of the os
+fn ruxlibc_target(os_config: &OSConfig) -> TargetConfig {
+    TargetConfig {
         name: "libc".to_string(),
         src: format!("{}/{}/ulib/ruxlibc/c", env!("HOME"), os_config.name),
         src_excluded: Vec::new(),
         include_dir: format!("{}/{}/ulib/ruxlibc/include", env!("HOME"), os_config.name),
         typ: "static".to_string(),
         cflags: String::from(""),
+        cxxflags: None,
+        asflags: None,
         archive: format!("{}-linux-musl-ar", os_config.platform.arch),
         ldflags: String::from("rcs"),
         deps: Vec::new(),
-    };
-    let ulib_targets = Vec::new();
-    let ulib_packages = Vec::new();
-    let mut tgt = Target::new(build_config, os_config, &ulib_tgt, &ulib_targets, &ulib_packages);
-    tgt.build(gen_cc);
+        src_extensions: utils::default_src_extensions(),
+        pch: String::new(),
+        unity: false,
+        unity_batch: 0,
+        unity_excluded: Vec::new(),
+    }
 }
 
 /// Builds the ruxmusl
-fn build_ruxmusl(build_config: &BuildConfig, os_config: &OSConfig) {
-    if !Path::new(RUXMUSL_DIR).exists() {
-        // download ruxmusl
-        if !Path::new(ULIB_RUXMUSL_SRC).exists() {
+fn build_ruxmusl(build_config: &BuildConfig, os_config: &OSConfig) -> Result<()> {
+    if Path::new(RUXMUSL_DIR).exists() {
+        log(LogLevel::Log, &format!("Ruxmusl: {} is up to date", RUXMUSL_DIR));
+        return Ok(());
+    }
+    // download ruxmusl
+    if !Path::new(ULIB_RUXMUSL_SRC).exists() {
+        let reason = Reason::MissingOutput(ULIB_RUXMUSL_SRC.to_string());
+        let tarball = format!("{}/musl-1.2.3.tar.gz", ULIB_RUXMUSL);
+        let mut wget = Cmd::new("wget");
+        wget.args(["https://musl.libc.org/releases/musl-1.2.3.tar.gz", "-P", ULIB_RUXMUSL]);
+        if explain::step("download", "musl", &wget, &reason) {
             log(LogLevel::Info, "Downloading musl-1.2.3 source code");
-            Command::new("wget")
-                .args(&["https://musl.libc.org/releases/musl-1.2.3.tar.gz", "-P", ULIB_RUXMUSL])
-                .spawn().expect("Failed to execute command")
-                .wait().expect("Failed to wait for command");
-            Command::new("tar")
-                .args(&["-zxvf", &format!("{}/musl-1.2.3.tar.gz", ULIB_RUXMUSL), "-C", ULIB_RUXMUSL])
-                .spawn().expect("Failed to execute command")
-                .wait().expect("Failed to wait for command");
-            Command::new("rm")
-                .args(&["-f", &format!("{}/musl-1.2.3.tar.gz", ULIB_RUXMUSL)])
-                .spawn().expect("Failed to execute command")
-                .wait().expect("Failed to wait for command");
+            wget.status().map_err(|why| RuxgoError::io("Failed to execute wget", why))?;
         }
+        let mut tar = Cmd::new("tar");
+        tar.args(["-zxvf", &tarball, "-C", ULIB_RUXMUSL]);
+        if explain::step("extract", "musl", &tar, &reason) {
+            tar.status().map_err(|why| RuxgoError::io("Failed to execute tar", why))?;
+        }
+        let mut rm = Cmd::new("rm");
+        rm.args(["-f", &tarball]);
+        if explain::step("remove", &tarball, &rm, &reason) {
+            rm.status().map_err(|why| RuxgoError::io("Failed to execute rm", why))?;
+        }
+    }
 
-        // create ruxos_bld/ruxmusl
-        fs::create_dir_all(RUXMUSL_DIR).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Couldn't create build dir: {}", why));
-            std::process::exit(1);
-        });
+    // create ruxos_bld/ruxmusl
+    if !explain::is_dry_run() {
+        fs::create_dir_all(RUXMUSL_DIR)
+            .map_err(|why| RuxgoError::io("Couldn't create build dir", why))?;
+    }
+    let reason = Reason::MissingOutput(RUXMUSL_DIR.to_string());
 
-        // config ruxmusl to generate makefile
-        let cmd = format!(
-            "{}/configure --prefix=./install --exec-prefix=./ --syslibdir=./install/lib --disable-shared ARCH={} CC={}",
-            ULIB_RUXMUSL_SRC, os_config.platform.arch, build_config.compiler.read().unwrap());
+    // config ruxmusl to generate makefile
+    let mut cmd = Cmd::new(format!("{}/configure", ULIB_RUXMUSL_SRC));
+    cmd.args(["--prefix=./install", "--exec-prefix=./", "--syslibdir=./install/lib", "--disable-shared"]);
+    cmd.arg(format!("ARCH={}", os_config.platform.arch));
+    cmd.arg(format!("CC={}", build_config.cc.read().unwrap()));
+    cmd.current_dir(RUXMUSL_DIR);
+    if explain::step("configure", "musl", &cmd, &reason) {
         log(LogLevel::Info, &format!("Command: {}", cmd));
-        let configure_output = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .current_dir(RUXMUSL_DIR)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Failed to execute configure command");
-        if !configure_output.status.success() {
-            log(LogLevel::Error, &format!("configure command execution failed: {:?}", configure_output.stderr));
-            std::process::exit(1);
+        let _span = timings::span("musl", "configure");
+        let configure_status = cmd
+            .status()
+            .map_err(|why| RuxgoError::io("Failed to execute configure command", why))?;
+        if !configure_status.success() {
+            return Err(RuxgoError::Toolchain(format!(
+                "configure command execution failed: {}", configure_status
+            )));
         }
+    }
 
-        // compile and install ruxmusl
+    // compile and install ruxmusl
+    let mut make = Cmd::new("make");
+    make.arg(format!("-j{}", jobs::count())).current_dir(RUXMUSL_DIR);
+    if explain::step("build", "musl", &make, &reason) {
         log(LogLevel::Log, "Musl source code is installing...");
-        let make_output = Command::new("make")
-            .args(&["-j"])
-            .current_dir(RUXMUSL_DIR)
+        let _span = timings::span("musl", "make");
+        let make_output = make
             .output()
-            .expect("Failed to run make command");
+            .map_err(|why| RuxgoError::io("Failed to run make command", why))?;
         if !make_output.status.success() {
-            log(LogLevel::Error, &format!("\"make -j\" command execution failed: {:?}", make_output.status.code()));
-            std::process::exit(1);
+            return Err(RuxgoError::Toolchain(format!(
+                "\"{}\" command execution failed: {:?}", make, make_output.status.code()
+            )));
         }
-        let make_install_output = Command::new("make")
-            .args(&["install"])
-            .current_dir(RUXMUSL_DIR)
+    }
+    let mut make_install = Cmd::new("make");
+    make_install.arg("install").current_dir(RUXMUSL_DIR);
+    if explain::step("install", "musl", &make_install, &reason) {
+        let _span = timings::span("musl", "make install");
+        let make_install_output = make_install
+            .to_command()
             .stderr(Stdio::inherit())
             .output()
-            .expect("Failed to run make install command");
+            .map_err(|why| RuxgoError::io("Failed to run make install command", why))?;
         if !make_install_output.status.success() {
-            log(LogLevel::Error, &format!("\"make install\" command execution failed: {:?}", make_install_output.status.code()));
-            std::process::exit(1);
+            return Err(RuxgoError::Toolchain(format!(
+                "\"make install\" command execution failed: {:?}", make_install_output.status.code()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the exe target to run: the only exe target among the given names, or among all targets
+/// if no name is given
+/// # Arguments
+/// * `targets` - A vector of targets
+/// * `names` - The names of the targets selected on the command line
+pub fn exe_target<'a>(targets: &'a [TargetConfig], names: &[String]) -> Result<&'a TargetConfig> {
+    let exes = targets
+        .iter()
+        .filter(|target| target.typ == "exe" && (names.is_empty() || names.contains(&target.name)))
+        .collect::<Vec<&TargetConfig>>();
+    match exes[..] {
+        [exe] => Ok(exe),
+        [] if names.is_empty() => Err(RuxgoError::Config("No executable target found".to_string())),
+        [] => Err(RuxgoError::Config(format!("No executable target named {}", names.join(" or ")))),
+        _ => {
+            let exes = exes.iter().map(|exe| exe.name.as_str()).collect::<Vec<&str>>();
+            Err(RuxgoError::Config(format!(
+                "Several executable targets found: {}, select the one to run, eg: ruxgo run {}", exes.join(", "), exes[0]
+            )))
         }
     }
 }
@@ -521,115 +584,94 @@ pub fn run (
     exe_target: &TargetConfig, 
     targets: &Vec<TargetConfig>, 
     packages: &Vec<Package>
-) {
-    let trgt = Target::new(build_config, os_config, exe_target, targets, packages);
+) -> Result<()> {
+    let trgt = Target::new(build_config, os_config, exe_target, targets, packages)?;
     if !Path::new(&trgt.bin_path).exists() {
-        log(LogLevel::Error, &format!("Could not find binary: {}", &trgt.bin_path));
-        std::process::exit(1);
+        return Err(RuxgoError::Config(format!("Could not find binary: {}", &trgt.bin_path)));
     }
     if os_config.platform.qemu != QemuConfig::default() {
-        let (qemu_args_final, _) = QemuConfig::config_qemu(&os_config.platform.qemu, &os_config.platform, &trgt);
+        let (qemu_args_final, _) = QemuConfig::config_qemu(&os_config.platform.qemu, &os_config.platform, &trgt)?;
         // enable virtual disk image
         if os_config.platform.qemu.blk == "y" {
             let path = Path::new(&os_config.platform.qemu.disk_img);
             if path.exists() {
                 log(LogLevel::Log, &format!("disk image \"{}\" already exists!", os_config.platform.qemu.disk_img));
             } else {
-                make_disk_image_fat32(&os_config.platform.qemu.disk_img);
+                make_disk_image_fat32(&os_config.platform.qemu.disk_img)?;
             }
         }
-        run_qemu(qemu_args_final);
+        run_qemu(qemu_args_final)?;
     } else {
         log(LogLevel::Log, &format!("Running: {}", &trgt.bin_path));
-        let mut cmd = Command::new(&trgt.bin_path);
+        let mut cmd = Cmd::new(&trgt.bin_path);
         if let Some(bin_args) = bin_args {
-            for arg in bin_args {
-                cmd.arg(arg);
-            }
-        }
-        log(LogLevel::Info, &format!("Command: {:?}", cmd));
-        // sets the stdout,stdin and stderr of the cmd to be inherited by the parent process.
-        cmd.stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit());
-        let output = cmd.output();
-        if output.is_ok() {
-            log(LogLevel::Info, &format!("  Success: {}", &trgt.bin_path));
-        } else {
-            log(LogLevel::Error, &format!("  Error: {}", &trgt.bin_path));
-            std::process::exit(1);
+            cmd.args(bin_args);
         }
+        log(LogLevel::Info, &format!("Command: {}", cmd));
+        // the stdout,stdin and stderr of the cmd are inherited from the parent process.
+        cmd.status().map_err(|why| RuxgoError::io(format!("Failed to run: {}", &trgt.bin_path), why))?;
+        log(LogLevel::Info, &format!("  Success: {}", &trgt.bin_path));
     }
+    Ok(())
 }
 
 /// Makes the disk_img of fat32
-fn make_disk_image_fat32(file_name: &str) {
+fn make_disk_image_fat32(file_name: &str) -> Result<()> {
     log(LogLevel::Log, &format!("Creating FAT32 disk image \"{}\" ...", file_name));
     let output = Command::new("dd")
         .arg("if=/dev/zero")
-        .arg(&format!("of={}", file_name))
+        .arg(format!("of={}", file_name))
         .arg("bs=1M")
         .arg("count=64")
         .output()
-        .expect("failed to execute dd command");
+        .map_err(|why| RuxgoError::io("Failed to execute dd command", why))?;
     if !output.status.success() {
-        log(LogLevel::Error, &format!("dd command failed with exit code {:?}", output.status.code()));
-        std::process::exit(1);
+        return Err(RuxgoError::Qemu(format!("dd command failed with exit code {:?}", output.status.code())));
     }
     let mkfs_output = Command::new("mkfs.fat")
         .arg("-F")
         .arg("32")
         .arg(file_name)
         .output()
-        .expect("failed to execute mkfs.fat command");
+        .map_err(|why| RuxgoError::io("Failed to execute mkfs.fat command", why))?;
     if !mkfs_output.status.success() {
-        log(LogLevel::Error, &format!("mkfs.fat command failed with exit code {:?}", mkfs_output.status.code()));
-        std::process::exit(1);
+        return Err(RuxgoError::Qemu(format!(
+            "mkfs.fat command failed with exit code {:?}", mkfs_output.status.code()
+        )));
     }
+    Ok(())
 }
 
 /// Runs the bin by qemu
-fn run_qemu(qemu_args: Vec<String>) {
+fn run_qemu(qemu_args: Vec<String>) -> Result<()> {
     log(LogLevel::Log, "Running on qemu...");
-    let mut cmd = String::new();
-    for qemu_arg in qemu_args {
-        cmd.push_str(&qemu_arg);
-        cmd.push_str(" ");
-    }
+    let Some((program, args)) = qemu_args.split_first() else {
+        return Err(RuxgoError::Qemu("No qemu command configured".to_string()));
+    };
+    let mut cmd = Cmd::new(program);
+    cmd.args(args);
     log(LogLevel::Debug, &format!("Command: {}", cmd));
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-        .expect("Failed to execute command");
-    if !output.status.success() {
-        log(LogLevel::Error, &format!("Command execution failed: {:?}", output.stderr));
-        std::process::exit(1);
+    let status = cmd
+        .status()
+        .map_err(|why| RuxgoError::io("Failed to execute qemu", why))?;
+    if !status.success() {
+        return Err(RuxgoError::Qemu(format!("Qemu exited with {}", status)));
     }
+    Ok(())
 }
 
 /// Initialises a new project in the current directory
-pub fn init_project(project_name: &str, is_c: Option<bool>, config: &GlobalConfig) {
+pub fn init_project(project_name: &str, is_c: Option<bool>, config: &GlobalConfig) -> Result<()> {
     log(LogLevel::Log, "Initializing project...");
 
     if Path::new(project_name).exists() {
-        log(LogLevel::Error, &format!("{} already exists", project_name));
-        log(LogLevel::Error, "Cannot initialise project");
-        std::process::exit(1);
+        return Err(RuxgoError::Config(format!("{} already exists, cannot initialise project", project_name)));
     }
 
     //Initialise git repo in project directory
     let mut cmd = Command::new("git");
     cmd.arg("init").arg(project_name);
-    let output = cmd.output();
-    if output.is_err() {
-        log(LogLevel::Error, "Could not initialise git repo");
-        log(LogLevel::Error, &format!("{}", output.err().unwrap()));
-        std::process::exit(1);
-    }
+    cmd.output().map_err(|why| RuxgoError::io("Could not initialise git repo", why))?;
 
     //Initialise config_linux.toml
     #[cfg(target_os = "windows")]
@@ -637,38 +679,28 @@ pub fn init_project(project_name: &str, is_c: Option<bool>, config: &GlobalConfi
     #[cfg(target_os = "linux")]
     let config_file = project_name.to_owned() + "/config_linux.toml";
     if Path::new(&config_file).exists() {
-        log(LogLevel::Error, &format!("{} already exists", config_file));
-        log(LogLevel::Error, "Cannot initialise project");
-        std::process::exit(1);
+        return Err(RuxgoError::Config(format!("{} already exists, cannot initialise project", config_file)));
     }
     let mut config_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(config_file)
-        .unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not create config file: {}", why));
-            std::process::exit(1);
-        });
+        .map_err(|why| RuxgoError::io("Could not create config file", why))?;
 
     let c_compiler = match config.get_default_compiler().as_str() {
         "gcc" => "gcc",
         "clang" => "clang",
-        _ => {
-            log(LogLevel::Error, "Invalid default compiler");
-            std::process::exit(1);
-        }
+        _ => return Err(RuxgoError::Config("Invalid default compiler".to_string())),
     };
     let cpp_compiler = match config.get_default_compiler().as_str() {
         "gcc" => "g++",
         "clang" => "clang++",
-        _ => {
-            log(LogLevel::Error, "Invalid default compiler");
-            std::process::exit(1);
-        }
+        _ => return Err(RuxgoError::Config("Invalid default compiler".to_string())),
     };
-    let sample_cpp_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = [\"\"]\n", cpp_compiler);
+    let sample_cpp_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\ndeps = []\n", cpp_compiler);
 
-    let sample_c_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\nlibs = \"\"\ndeps = [\"\"]\n", c_compiler);
+    let sample_c_config = format!("[build]\ncompiler = \"{}\"\n\n[[targets]]\nname = \"main\"\nsrc = \"./src/\"\ninclude_dir = \"./src/include/\"\ntype = \"exe\"\ncflags = \"-g -Wall -Wextra\"\ndeps = []\n", c_compiler);
 
     let sample_config = match is_c {
         Some(true) => sample_c_config,
@@ -676,88 +708,59 @@ pub fn init_project(project_name: &str, is_c: Option<bool>, config: &GlobalConfi
         None => match config.get_default_language().as_str() {
             "c" => sample_c_config,
             "cpp" => sample_cpp_config,
-            _ => {
-                log(LogLevel::Error, "Invalid default language");
-                std::process::exit(1);
-            }
+            _ => return Err(RuxgoError::Config("Invalid default language".to_string())),
         },
     };
-    config_file.write_all(sample_config.as_bytes()).unwrap_or_else(|why| {
-        log(LogLevel::Error, &format!("Could not write to config file: {}", why));
-        std::process::exit(1);
-    });
+    config_file.write_all(sample_config.as_bytes())
+        .map_err(|why| RuxgoError::io("Could not write to config file", why))?;
 
     //Create src and src/include directories
     let src_dir = project_name.to_owned() + "/src";
     let include_dir = project_name.to_owned() + "/src/include";
     if !Path::new(&src_dir).exists() {
-        fs::create_dir(&src_dir).unwrap_or_else(|why| {
-            log(LogLevel::Warn , &format!("Project name {}", project_name));
-            log(LogLevel::Error, &format!("Could not create src directory: {}", why));
-            std::process::exit(1);
-        });
+        fs::create_dir(&src_dir)
+            .map_err(|why| RuxgoError::io(format!("Could not create src directory for project {}", project_name), why))?;
     }
     if !Path::new(&include_dir).exists() {
-        fs::create_dir(&include_dir).unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not create src/include directory: {}", why));
-            std::process::exit(1);
-        });
+        fs::create_dir(&include_dir)
+            .map_err(|why| RuxgoError::io("Could not create src/include directory", why))?;
     }
 
     //Create main.c or main.cpp
-    let main_path: String;
-    match is_c {
-        Some(true) => main_path = src_dir.to_owned() + "/main.c",
-        Some(false) => main_path = src_dir.to_owned() + "/main.cpp",
+    let main_path = match is_c {
+        Some(true) => src_dir.to_owned() + "/main.c",
+        Some(false) => src_dir.to_owned() + "/main.cpp",
         None => match config.get_default_language().as_str() {
-            "c" => main_path = src_dir.to_owned() + "/main.c",
-            "cpp" => main_path = src_dir.to_owned() + "/main.cpp",
-            _ => {
-                log(LogLevel::Error, "Invalid default language");
-                std::process::exit(1);
-            }
+            "c" => src_dir.to_owned() + "/main.c",
+            "cpp" => src_dir.to_owned() + "/main.cpp",
+            _ => return Err(RuxgoError::Config("Invalid default language".to_string())),
         },
-    }
+    };
     if !Path::new(&main_path).exists() {
         let mut main_file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&main_path)
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not create main.cpp: {}", why));
-                std::process::exit(1);
-            });
+            .map_err(|why| RuxgoError::io("Could not create main.cpp", why))?;
 
         let c_sample_program =
             b"#include <stdio.h>\n\nint main() {\n\tprintf(\"Hello World!\\n\");\n\treturn 0;\n}";
         let cpp_sample_program = 
             b"#include <iostream>\n\nint main() {\n\tstd::cout << \"Hello World!\" << std::endl;\n\treturn 0;\n}";
         match is_c {
-            Some(true) => main_file.write_all(c_sample_program).unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not write to main.c: {}", why));
-                std::process::exit(1);
-            }),
+            Some(true) => main_file.write_all(c_sample_program)
+                .map_err(|why| RuxgoError::io("Could not write to main.c", why))?,
             Some(false) => main_file
                 .write_all(cpp_sample_program)
-                .unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Could not write to main.cpp: {}", why));
-                    std::process::exit(1);
-                }),
+                .map_err(|why| RuxgoError::io("Could not write to main.cpp", why))?,
             None => match config.get_default_language().as_str() {
-                "c" => main_file.write_all(c_sample_program).unwrap_or_else(|why| {
-                    log(LogLevel::Error, &format!("Could not write to main.c: {}", why));
-                    std::process::exit(1);
-                }),
+                "c" => main_file.write_all(c_sample_program)
+                    .map_err(|why| RuxgoError::io("Could not write to main.c", why))?,
                 "cpp" => main_file
                     .write_all(cpp_sample_program)
-                    .unwrap_or_else(|why| {
-                        log(LogLevel::Error, &format!("Could not write to main.cpp: {}", why));
-                        std::process::exit(1);
-                    }),
-                _ => {
-                    log(LogLevel::Error, "Invalid default language");
-                    std::process::exit(1);
-                }
+                    .map_err(|why| RuxgoError::io("Could not write to main.cpp", why))?,
+                _ => return Err(RuxgoError::Config("Invalid default language".to_string())),
             },
         }
     }
@@ -768,128 +771,280 @@ pub fn init_project(project_name: &str, is_c: Option<bool>, config: &GlobalConfi
         let mut gitignore_file = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&gitignore_path)
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not create .gitignore: {}", why));
-                std::process::exit(1);
-            });
+            .map_err(|why| RuxgoError::io("Could not create .gitignore", why))?;
         gitignore_file
             .write_all(b"ruxos_bld\ncompile_commands.json\n.cache\n")
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not write to .gitignore: {}", why));
-                std::process::exit(1);
-            });
+            .map_err(|why| RuxgoError::io("Could not write to .gitignore", why))?;
     }
 
     //Create README.md
     let mut readme_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(project_name.to_owned() + "/README.md")
-        .unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not create README.md: {}", why));
-            std::process::exit(1);
-        });
+        .map_err(|why| RuxgoError::io("Could not create README.md", why))?;
     readme_file
         .write_all(format!("# {}", project_name).as_bytes())
-        .unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not write to README.md: {}", why));
-            std::process::exit(1);
-        });
+        .map_err(|why| RuxgoError::io("Could not write to README.md", why))?;
 
     //Create LICENSE
     let mut license_file = fs::OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(project_name.to_owned() + "/LICENSE")
-        .unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not create LICENSE: {}", why));
-            std::process::exit(1);
-        });
+        .map_err(|why| RuxgoError::io("Could not create LICENSE", why))?;
 
     let license = config.get_license();
     if license.as_str() == "NONE" {
-        license_file.write_all(b"No license").unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Could not write to LICENSE: {}", why));
-            std::process::exit(1);
-        });
+        license_file.write_all(b"No license")
+            .map_err(|why| RuxgoError::io("Could not write to LICENSE", why))?;
     } else {
         license_file
             .write_all(license.as_bytes())
-            .unwrap_or_else(|why| {
-                log(LogLevel::Error, &format!("Could not write to LICENSE: {}", why));
-                std::process::exit(1);
-            });
+            .map_err(|why| RuxgoError::io("Could not write to LICENSE", why))?;
     }
 
     log(LogLevel::Log, &format!("Project {} initialised", project_name));
+    Ok(())
 }
 
-pub fn parse_config() -> (BuildConfig, OSConfig, Vec<TargetConfig>, Vec<Package>) {
-    #[cfg(target_os = "linux")]
-    let (build_config, os_config, targets) = utils::parse_config("./config_linux.toml", true);
-    #[cfg(target_os = "windows")]
-    let (build_config, os_config, targets) = utils::parse_config("./config_win32.toml", true);
+/// Validates a config file without building anything
+/// Returns every problem found, the config is valid when none is returned
+/// # Arguments
+/// * `path` - The path to the config file
+pub fn check_config(path: &str) -> Result<Vec<String>> {
+    // Syntax, unknown keys, `type`, `ulib` and `os.platform.name` are checked while parsing
+    let (build_config, os_config, targets) = utils::parse_config(path, false)?;
+    let root = Path::new(path).parent().unwrap_or(Path::new("."));
+    let mut problems = Vec::new();
 
-    let mut num_exe = 0;
-    let mut exe_target: Option<&TargetConfig> = None;
-    if targets.is_empty() {
-        log(LogLevel::Error, "No targets in config");
-        std::process::exit(1);
-    } else {
-        // Allow only one exe and set it as the exe_target
-        for target in &targets {
-            if target.typ == "exe" {
-                num_exe += 1;
-                exe_target = Some(target);
+    if !targets.iter().any(|t| t.typ == "exe") {
+        problems.push("At least one executable target must be specified".to_string());
+    }
+
+    for feat in &os_config.features {
+        if !features::KNOWN_SERVICES.contains(&feat.as_str()) {
+            problems.push(format!("Unknown service in os.services: {}", feat));
+        }
+    }
+
+    // Targets of packages that were already fetched, packages are never cloned here
+    let mut fetched: Vec<Package> = Vec::new();
+    let mut unfetched = Vec::new();
+    for package in &build_config.packages {
+        let mut deets = package.split_whitespace();
+        let repo = deets.next().unwrap_or_default().replace(',', "");
+        let branch = deets.next().unwrap_or_default().to_string();
+        let name = match repo.split('/').nth(1) {
+            Some(name) => name.to_string(),
+            None => {
+                problems.push(format!("Invalid package: {}, must be in the form of \"<git_repo> <branch>\"", package));
+                continue;
             }
+        };
+        #[cfg(target_os = "linux")]
+        let pkg_toml = root.join(PACKAGES_DIR).join(&name).join("config_linux.toml");
+        #[cfg(target_os = "windows")]
+        let pkg_toml = root.join(PACKAGES_DIR).join(&name).join("config_win32.toml");
+        if pkg_toml.exists() {
+            let (pkg_build_config, _, pkg_targets) = utils::parse_config(&pkg_toml.to_string_lossy(), false)?;
+            fetched.push(Package::new(name, repo, branch, pkg_build_config, pkg_targets, Vec::new()));
+        } else {
+            unfetched.push(name);
         }
     }
-    if num_exe != 1 || exe_target.is_none() {
-        log(LogLevel::Error, "Exactly one executable target must be specified");
-        std::process::exit(1);
+
+    let pkg_targets = fetched.iter().flat_map(|pkg| pkg.target_configs.iter()).collect::<Vec<_>>();
+    let mut deps_resolved = unfetched.is_empty();
+    for target in &targets {
+        for (key, dir) in [("src", &target.src), ("include_dir", &target.include_dir)] {
+            if !dir.is_empty() && !root.join(dir).is_dir() {
+                problems.push(format!("{} of target {} does not exist: {}", key, target.name, dir));
+            }
+        }
+        if !target.pch.is_empty() && !root.join(&target.pch).is_file() {
+            problems.push(format!("pch of target {} does not exist: {}", target.name, target.pch));
+        }
+        for dep in &target.deps {
+            match targets.iter().chain(pkg_targets.iter().copied()).find(|t| t.name == *dep) {
+                Some(dep_target) => {
+                    if dep_target.typ == "exe" {
+                        problems.push(format!(
+                            "Target {} depends on {}, but only dlls, static or object libraries can be dependant libs",
+                            target.name, dep
+                        ));
+                    } else if !dep.starts_with("lib") {
+                        problems.push(format!("Dependant lib name must start with lib, but target: {} does not", dep));
+                    }
+                }
+                None if unfetched.is_empty() => {
+                    deps_resolved = false;
+                    problems.push(format!("Dependency {} of target {} does not exist", dep, target.name));
+                }
+                None => {
+                    log(LogLevel::Warn, &format!(
+                        "Dependency {} of target {} not found, it may come from a package not fetched yet: {}",
+                        dep, target.name, unfetched.join(", ")
+                    ));
+                }
+            }
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    let packages = Package::parse_packages("./config_linux.toml");
-    #[cfg(target_os = "windows")]
-    let packages = Package::parse_packages("./config_win32.toml");
+    // Cycles can only be found once every dependency resolves
+    if deps_resolved {
+        match BuildGraph::new(&build_config, &targets, &fetched) {
+            Err(RuxgoError::Config(msg)) => problems.push(msg),
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
 
-    (build_config, os_config, targets, packages)
+    Ok(problems)
 }
 
-pub fn pre_gen_cc() {
-    if !Path::new("./compile_commands.json").exists() {
-        fs::File::create(Path::new("./compile_commands.json")).unwrap();
-    } else {
-        fs::remove_file(Path::new("./compile_commands.json")).unwrap();
-        fs::File::create(Path::new("./compile_commands.json")).unwrap();
+/// Parses the config file of the project, along with its packages
+/// # Arguments
+/// * `path` - The path to the config file, eg: `CONFIG_FILE`
+pub fn parse_config(path: &str) -> Result<(BuildConfig, OSConfig, Vec<TargetConfig>, Vec<Package>)> {
+    let (build_config, os_config, targets) = utils::parse_config(path, true)?;
+
+    if targets.is_empty() {
+        return Err(RuxgoError::Config("No targets in config".to_string()));
+    }
+    // several exe targets are allowed, `run` picks one of them
+    if !targets.iter().any(|target| target.typ == "exe") {
+        return Err(RuxgoError::Config("At least one executable target must be specified".to_string()));
     }
+
+    let packages = Package::parse_packages(path)?;
+
+    Ok((build_config, os_config, targets, packages))
 }
 
-pub fn pre_gen_vsc() {
-    if !Path::new("./.vscode").exists() {
-        fs::create_dir(Path::new("./.vscode")).unwrap();
+pub fn pre_gen_cc() -> Result<()> {
+    if !explain::write("compile_commands.json", &Reason::Forced("asked for by --gen-cc".to_string())) {
+        return Ok(());
     }
+    // File::create truncates an existing compile_commands.json
+    fs::File::create(Path::new("./compile_commands.json"))
+        .map_err(|why| RuxgoError::io("Could not create compile_commands.json", why))?;
+    Ok(())
+}
 
-    if !Path::new("./.vscode/c_cpp_properties.json").exists() {
-        fs::File::create(Path::new("./.vscode/c_cpp_properties.json")).unwrap();
-    } else {
-        fs::remove_file(Path::new("./.vscode/c_cpp_properties.json")).unwrap();
-        fs::File::create(Path::new("./.vscode/c_cpp_properties.json")).unwrap();
+pub fn pre_gen_vsc() -> Result<()> {
+    if !explain::write(".vscode/c_cpp_properties.json", &Reason::Forced("asked for by --gen-vsc".to_string())) {
+        return Ok(());
+    }
+    if !Path::new("./.vscode").exists() {
+        fs::create_dir(Path::new("./.vscode"))
+            .map_err(|why| RuxgoError::io("Could not create .vscode directory", why))?;
     }
+    fs::File::create(Path::new("./.vscode/c_cpp_properties.json"))
+        .map_err(|why| RuxgoError::io("Could not create c_cpp_properties.json", why))?;
+    Ok(())
 }
 
-pub fn update_packages(packages: &Vec<utils::Package>) {
+pub fn update_packages(packages: &Vec<utils::Package>) -> Result<()> {
     log(LogLevel::Log, "Updating packages...");
     for package in packages {
-        package.update();
+        package.update()?;
     }
+    Ok(())
 }
 
-pub fn restore_packages(packages: &Vec<utils::Package>) {
+pub fn restore_packages(packages: &Vec<utils::Package>) -> Result<()> {
     log(LogLevel::Log, "Restoring packages...");
     for package in packages {
-        package.restore();
+        package.restore()?;
     }
-}
\ No newline at end of file
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a project with the given directories and config, and returns the path of its config
+    fn project(name: &str, dirs: &[&str], config: &str) -> String {
+        let root = std::env::temp_dir().join(format!("ruxgo-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&root).unwrap();
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let path = root.join("config_linux.toml");
+        fs::write(&path, config).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn check_config_accepts_a_valid_project() {
+        let path = project("check-valid", &["src", "lib", "inc"], concat!(
+            "[build]\ncompiler = \"gcc\"\n",
+            "[[targets]]\nname = \"libfoo\"\nsrc = \"lib\"\ninclude_dir = \"inc\"\ntype = \"static\"\n",
+            "[[targets]]\nname = \"main\"\nsrc = \"src\"\ninclude_dir = \"inc\"\ntype = \"exe\"\ndeps = [\"libfoo\"]\n",
+        ));
+        assert_eq!(check_config(&path).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn check_config_reports_every_problem() {
+        let path = project("check-problems", &["src"], concat!(
+            "[build]\ncompiler = \"gcc\"\n",
+            "[os]\nname = \"ruxos\"\nulib = \"ruxlibc\"\nservices = [\"fs\", \"teleport\"]\n",
+            "[[targets]]\nname = \"main\"\nsrc = \"src\"\ntype = \"exe\"\ndeps = [\"tool\", \"libnone\"]\n",
+            "[[targets]]\nname = \"tool\"\nsrc = \"tools\"\ntype = \"exe\"\npch = \"src/pch.h\"\n",
+        ));
+        assert_eq!(check_config(&path).unwrap(), [
+            "Unknown service in os.services: teleport",
+            "Target main depends on tool, but only dlls, static or object libraries can be dependant libs",
+            "Dependency libnone of target main does not exist",
+            "src of target tool does not exist: tools",
+            "pch of target tool does not exist: src/pch.h",
+        ]);
+    }
+
+    #[test]
+    fn check_config_asks_for_an_executable_target() {
+        let path = project("check-no-exe", &["lib"], concat!(
+            "[build]\ncompiler = \"gcc\"\n",
+            "[[targets]]\nname = \"libfoo\"\nsrc = \"lib\"\ntype = \"static\"\n",
+        ));
+        assert_eq!(check_config(&path).unwrap(), ["At least one executable target must be specified"]);
+    }
+
+    #[test]
+    fn exe_target_is_the_only_exe_selected() {
+        let targets: Vec<TargetConfig> = ["main", "tool", "libfoo"]
+            .iter()
+            .map(|name| {
+                let typ = if name.starts_with("lib") { "static" } else { "exe" };
+                toml::from_str(&format!("name = \"{}\"\ntype = \"{}\"", name, typ)).unwrap()
+            })
+            .collect();
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        let error = |result: Result<&TargetConfig>| match result {
+            Err(RuxgoError::Config(msg)) => msg,
+            result => panic!("expected a config error, got {:?}", result.map(|target| &target.name)),
+        };
+
+        assert_eq!(exe_target(&targets, &names(&["tool"])).unwrap().name, "tool");
+        assert_eq!(exe_target(&targets, &names(&["libfoo", "main"])).unwrap().name, "main");
+        assert_eq!(exe_target(&targets[..1], &[]).unwrap().name, "main");
+        assert_eq!(
+            error(exe_target(&targets, &[])),
+            "Several executable targets found: main, tool, select the one to run, eg: ruxgo run main"
+        );
+        assert_eq!(error(exe_target(&targets, &names(&["libfoo"]))), "No executable target named libfoo");
+        assert_eq!(error(exe_target(&targets[2..], &[])), "No executable target found");
+    }
+
+    #[test]
+    fn check_config_stops_at_a_config_that_does_not_parse() {
+        let path = project("check-syntax", &[], "[build]\ncompiler = \"gcc\"\n[[targets]]\nname = \"main\"\n");
+        assert!(matches!(check_config(&path), Err(RuxgoError::Config(_))));
+    }
+}
@@ -0,0 +1,101 @@
+//! This module contains functions for reading the dependency files written by the compiler (-MMD)
+use std::fs;
+
+/// Parses the content of a make style depfile and returns the prerequisites of its first rule.
+/// # Arguments
+/// * `contents` - The content of the depfile, eg: `obj.o: src.c inc/a.h \` followed by `inc/b.h`
+pub fn parse_depfile(contents: &str) -> Vec<String> {
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    let rule = contents.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    // the target ends at the first ": ", a plain ':' may be part of a windows drive letter
+    let prerequisites = match rule.find(": ") {
+        Some(pos) => &rule[pos + 2..],
+        None => match rule.strip_suffix(':') {
+            Some(_) => "",
+            None => return Vec::new(),
+        },
+    };
+    let mut deps = Vec::new();
+    let mut dep = String::new();
+    let mut chars = prerequisites.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                dep.push(' ');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                dep.push('$');
+                chars.next();
+            }
+            ' ' | '\t' => {
+                if !dep.is_empty() {
+                    deps.push(std::mem::take(&mut dep));
+                }
+            }
+            _ => dep.push(c),
+        }
+    }
+    if !dep.is_empty() {
+        deps.push(dep);
+    }
+    deps
+}
+
+/// Reads the depfile written while compiling `src` and returns the files it depends on.
+/// Returns None if the depfile does not exist or can't be read.
+/// # Arguments
+/// * `path` - The path of the depfile.
+/// * `src` - The path of the source file, left out of the result.
+pub fn read_depfile(path: &str, src: &str) -> Option<Vec<String>> {
+    let contents = fs::read_to_string(path).ok()?;
+    let src = src.trim_start_matches("./");
+    Some(
+        parse_depfile(&contents)
+            .into_iter()
+            .filter(|dep| dep.trim_start_matches("./") != src)
+            .collect()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_lines_are_joined_into_one_rule() {
+        let contents = "obj/main.o: src/main.c \\\n inc/a.h \\\r\n  inc/b.h\n";
+        assert_eq!(parse_depfile(contents), ["src/main.c", "inc/a.h", "inc/b.h"]);
+    }
+
+    #[test]
+    fn escaped_spaces_and_dollars_stay_in_the_path() {
+        let contents = "main.o: my\\ src/main.c inc/$$HOME.h\n";
+        assert_eq!(parse_depfile(contents), ["my src/main.c", "inc/$HOME.h"]);
+    }
+
+    #[test]
+    fn drive_letters_are_not_taken_for_the_end_of_the_target() {
+        let contents = "C:/bld/main.o: C:/src/main.c D:\\inc\\a.h\n";
+        assert_eq!(parse_depfile(contents), ["C:/src/main.c", "D:\\inc\\a.h"]);
+    }
+
+    #[test]
+    fn phony_rules_of_the_headers_are_ignored() {
+        // written by -MP after the rule of the object
+        let contents = "main.o: main.c a.h\n\na.h:\n";
+        assert_eq!(parse_depfile(contents), ["main.c", "a.h"]);
+        assert!(parse_depfile("a.h:\n").is_empty());
+        assert!(parse_depfile("not a rule\n").is_empty());
+    }
+
+    #[test]
+    fn read_depfile_leaves_the_source_out() {
+        let path = std::env::temp_dir().join(format!("ruxgo-{}-main.d", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "main.o: src/main.c ./inc/a.h\n").unwrap();
+        assert_eq!(read_depfile(path, "./src/main.c"), Some(vec!["./inc/a.h".to_string()]));
+        fs::remove_file(path).unwrap();
+        assert_eq!(read_depfile(path, "./src/main.c"), None);
+    }
+}
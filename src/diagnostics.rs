@@ -0,0 +1,278 @@
+//! This module contains the diagnostics of the compiler, collected over a build.
+//!
+//! GCC and Clang diagnostics are parsed from their text output, or from the output of
+//! `-fdiagnostics-format=json`. A diagnostic emitted by a header included by several sources is
+//! only reported once, and the diagnostics are summarized by file and warning option once the build ends.
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::utils::{log, LogLevel};
+
+static COLLECTOR: OnceLock<Mutex<Collector>> = OnceLock::new();
+static JSON: AtomicBool = AtomicBool::new(false);
+/// Formats of the diagnostics accepted by `--message-format`
+pub const MESSAGE_FORMATS: &[&str] = &["human", "json"];
+/// Severities of the compiler
+const SEVERITIES: &[&str] = &["fatal error", "error", "warning", "note"];
+
+/// Represents a diagnostic of the compiler
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Diagnostic {
+    pub file: String,
+    /// The line of the diagnostic, 0 if it is not tied to a line
+    pub line: u32,
+    /// The column of the diagnostic, 0 if it is not tied to a column
+    pub column: u32,
+    /// One of "fatal error", "error", "warning" or "note"
+    pub severity: String,
+    pub message: String,
+    /// The option controlling the diagnostic, eg: `-Wunused-variable`
+    pub option: Option<String>,
+}
+
+/// The diagnostics reported so far, each one once
+#[derive(Default)]
+struct Collector {
+    seen: HashSet<Diagnostic>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Streams the diagnostics as JSON lines on stdout, instead of summarizing them at the end of the build
+pub fn enable_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+/// Records the diagnostics printed by the compiler while compiling a source file
+///
+/// An output holding no diagnostic the parser knows is logged as is
+/// # Arguments
+/// * `src` - The source file compiled
+/// * `stderr` - What the compiler printed on stderr
+pub fn record(src: &str, stderr: &str) {
+    let json = JSON.load(Ordering::Relaxed);
+    let parsed = parse(stderr);
+    if parsed.is_empty() {
+        if !json && !stderr.trim().is_empty() {
+            log(LogLevel::Warn, &format!("Compiler output for {}:\n\t{}", src, stderr.trim_end()));
+        }
+        return;
+    }
+    let mut collector = COLLECTOR.get_or_init(Default::default).lock().unwrap();
+    for diagnostic in parsed {
+        if !collector.seen.insert(diagnostic.clone()) {
+            continue;
+        }
+        if json {
+            println!("{}", json!({ "reason": "compiler-message", "src": src, "diagnostic": &diagnostic }));
+        }
+        collector.diagnostics.push(diagnostic);
+    }
+}
+
+/// Reports the diagnostics recorded during the build, grouped by file and warning option
+///
+/// In JSON mode, only a last line with the counts is printed
+pub fn summary() {
+    let collector = COLLECTOR.get_or_init(Default::default).lock().unwrap();
+    let reported = collector.diagnostics.iter().filter(|diag| diag.severity != "note").collect::<Vec<_>>();
+    let warnings = reported.iter().filter(|diag| diag.severity == "warning").count();
+    let errors = reported.len() - warnings;
+    if JSON.load(Ordering::Relaxed) {
+        println!("{}", json!({ "reason": "build-finished", "warnings": warnings, "errors": errors }));
+        return;
+    }
+    if reported.is_empty() {
+        return;
+    }
+    let mut groups: BTreeMap<&str, BTreeMap<&str, Vec<&Diagnostic>>> = BTreeMap::new();
+    for diag in &reported {
+        let option = diag.option.as_deref().unwrap_or(&diag.severity);
+        groups.entry(&diag.file).or_default().entry(option).or_default().push(diag);
+    }
+    log(LogLevel::Warn, &format!(
+        "{} warning(s) and {} error(s) emitted by the compiler in {} file(s):",
+        warnings, errors, groups.len()
+    ));
+    for (file, options) in groups {
+        let count = options.values().map(Vec::len).sum::<usize>();
+        log(LogLevel::Warn, &format!("  {}: {} diagnostic(s)", file, count));
+        for (option, mut diags) in options {
+            diags.sort_by_key(|diag| (diag.line, diag.column));
+            log(LogLevel::Warn, &format!("    {} ({})", option, diags.len()));
+            for diag in diags {
+                log(LogLevel::Warn, &format!("      {}:{}: {}: {}", diag.line, diag.column, diag.severity, diag.message));
+            }
+        }
+    }
+}
+
+/// Parses the diagnostics printed by GCC or Clang, as text or as JSON
+/// # Arguments
+/// * `stderr` - What the compiler printed on stderr
+pub fn parse(stderr: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for line in strip_colors(stderr).lines() {
+        if line.starts_with('[') {
+            if let Ok(Value::Array(values)) = serde_json::from_str::<Value>(line) {
+                for value in &values {
+                    parse_json(value, &mut diagnostics);
+                }
+                continue;
+            }
+        }
+        if let Some(diagnostic) = parse_line(line) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+/// Parses a diagnostic of `-fdiagnostics-format=json` and its children
+fn parse_json(value: &Value, diagnostics: &mut Vec<Diagnostic>) {
+    let caret = &value["locations"][0]["caret"];
+    let (Some(severity), Some(message)) = (value["kind"].as_str(), value["message"].as_str()) else {
+        return;
+    };
+    diagnostics.push(Diagnostic {
+        file: caret["file"].as_str().unwrap_or_default().to_string(),
+        line: caret["line"].as_u64().unwrap_or_default() as u32,
+        column: caret["column"].as_u64().unwrap_or_default() as u32,
+        severity: severity.to_string(),
+        message: message.to_string(),
+        option: value["option"].as_str().map(str::to_string),
+    });
+    for child in value["children"].as_array().into_iter().flatten() {
+        parse_json(child, diagnostics);
+    }
+}
+
+/// Parses a text diagnostic, eg: `foo.c:3:9: warning: unused variable 'x' [-Wunused-variable]`
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    // the first severity marker of the line, a message may quote another one
+    let (location, severity, message) = SEVERITIES
+        .iter()
+        .filter_map(|&severity| {
+            let marker = format!(": {}: ", severity);
+            let start = line.find(&marker)?;
+            Some((&line[..start], severity, &line[start + marker.len()..]))
+        })
+        .min_by_key(|(location, _, _)| location.len())?;
+    // the location is "file:line:column", "file:line" or only the tool, eg: "cc1"
+    let mut parts = location.rsplitn(3, ':');
+    let numbers = parts.clone().map_while(|part| part.parse::<u32>().ok()).collect::<Vec<u32>>();
+    let (file, line, column) = match numbers[..] {
+        [column, line] => (parts.nth(2)?, line, column),
+        [line] => (location.rsplit_once(':')?.0, line, 0),
+        _ => (location, 0, 0),
+    };
+    // the option comes last between brackets, eg: "[-Wunused-variable]" or "[-Werror=unused-variable]"
+    let (message, option) = match message.rsplit_once(" [-") {
+        Some((text, option)) if option.ends_with(']') => {
+            (text, Some(format!("-{}", option.trim_end_matches(']'))))
+        }
+        _ => (message, None),
+    };
+    Some(Diagnostic {
+        file: file.to_string(),
+        line,
+        column,
+        severity: severity.to_string(),
+        message: message.to_string(),
+        option,
+    })
+}
+
+/// Removes the color escape sequences of `-fdiagnostics-color=always`
+fn strip_colors(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // skips the sequence up to its final letter, eg: "\x1b[01;35m"
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diag(file: &str, line: u32, column: u32, severity: &str, message: &str, option: Option<&str>) -> Diagnostic {
+        Diagnostic {
+            file: file.to_string(),
+            line,
+            column,
+            severity: severity.to_string(),
+            message: message.to_string(),
+            option: option.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn text_diagnostics_give_their_location_and_option() {
+        let stderr = concat!(
+            "a.c: In function 'main':\n",
+            "a.c:3:9: warning: unused variable 'x' [-Wunused-variable]\n",
+            "    3 |     int x;\n",
+            "      |         ^\n",
+            "b.c:7:1: error: unused variable 'y' [-Werror=unused-variable]\n",
+        );
+        assert_eq!(parse(stderr), [
+            diag("a.c", 3, 9, "warning", "unused variable 'x'", Some("-Wunused-variable")),
+            diag("b.c", 7, 1, "error", "unused variable 'y'", Some("-Werror=unused-variable")),
+        ]);
+        // brackets not holding an option are part of the message
+        assert_eq!(parse("a.c:1:1: error: bad [x]"), [diag("a.c", 1, 1, "error", "bad [x]", None)]);
+    }
+
+    #[test]
+    fn location_may_lack_its_column_or_be_the_tool() {
+        assert_eq!(parse("a.c:10: error: expected ';'"), [diag("a.c", 10, 0, "error", "expected ';'", None)]);
+        assert_eq!(
+            parse("cc1: fatal error: a.h: No such file or directory"),
+            [diag("cc1", 0, 0, "fatal error", "a.h: No such file or directory", None)],
+        );
+        // the colon of a drive letter is not a separator
+        assert_eq!(parse("C:/src/a.c:4:2: warning: w"), [diag("C:/src/a.c", 4, 2, "warning", "w", None)]);
+    }
+
+    #[test]
+    fn message_quoting_a_severity_keeps_the_first_one() {
+        assert_eq!(
+            parse("a.c:2:3: note: expanded from ': warning: '"),
+            [diag("a.c", 2, 3, "note", "expanded from ': warning: '", None)],
+        );
+    }
+
+    #[test]
+    fn colors_are_stripped_before_parsing() {
+        let stderr = "\x1b[01m\x1b[Ka.c:2:9:\x1b[m\x1b[K \x1b[01;31m\x1b[Kerror: \x1b[m\x1b[Kexpected expression";
+        assert_eq!(parse(stderr), [diag("a.c", 2, 9, "error", "expected expression", None)]);
+    }
+
+    #[test]
+    fn json_diagnostics_are_parsed_with_their_children() {
+        let stderr = concat!(
+            r#"[{"kind": "warning", "message": "unused variable 'x'", "option": "-Wunused-variable", "#,
+            r#""locations": [{"caret": {"file": "a.c", "line": 3, "column": 9}}], "#,
+            r#""children": [{"kind": "note", "message": "declared here", "#,
+            r#""locations": [{"caret": {"file": "a.h", "line": 1, "column": 2}}]}]}]"#,
+        );
+        assert_eq!(parse(stderr), [
+            diag("a.c", 3, 9, "warning", "unused variable 'x'", Some("-Wunused-variable")),
+            diag("a.h", 1, 2, "note", "declared here", None),
+        ]);
+        // a line starting with a bracket that is not json is parsed as text
+        assert_eq!(parse("[a.c:1:1: error: e"), [diag("[a.c", 1, 1, "error", "e", None)]);
+    }
+}
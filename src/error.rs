@@ -0,0 +1,138 @@
+//! This module contains the error type returned by the ruxgo library
+use std::fmt;
+use std::io;
+
+/// Represents every failure the ruxgo library can report to its caller
+#[derive(Debug)]
+pub enum RuxgoError {
+    /// The config file is missing, malformed or holds an invalid value
+    Config(String),
+    /// A tool of the toolchain (compiler, cargo, make, ...) is unsupported or failed
+    Toolchain(String),
+    /// A source file failed to compile
+    Compile {
+        src: String,
+        cmd: String,
+        stdout: String,
+        stderr: String,
+    },
+    /// A target failed to link
+    Link {
+        target: String,
+        cmd: String,
+        stderr: String,
+    },
+    /// Several targets failed in keep-going mode, `skipped` names the targets depending on a failed one
+    Build {
+        failures: Vec<RuxgoError>,
+        skipped: Vec<String>,
+    },
+    /// A package could not be cloned, parsed, updated or restored
+    Package(String),
+    /// Qemu could not be configured or exited with an error
+    Qemu(String),
+    /// An IO operation failed, `context` describes what was being done
+    Io {
+        context: String,
+        source: io::Error,
+    },
+}
+
+/// Result type used across the ruxgo library
+pub type Result<T> = std::result::Result<T, RuxgoError>;
+
+impl RuxgoError {
+    /// Wraps an IO error together with a description of the failed operation
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        RuxgoError::Io { context: context.into(), source }
+    }
+}
+
+impl fmt::Display for RuxgoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuxgoError::Config(msg) => write!(f, "Config error: {}", msg),
+            RuxgoError::Toolchain(msg) => write!(f, "Toolchain error: {}", msg),
+            RuxgoError::Compile { src, cmd, stdout, stderr } => {
+                write!(f, "Failed to compile: {}\n  Command: {}", src, cmd)?;
+                if !stdout.is_empty() {
+                    write!(f, "\n  Stdout: {}", stdout)?;
+                }
+                write!(f, "\n  Stderr: {}", stderr)
+            }
+            RuxgoError::Link { target, cmd, stderr } => {
+                write!(f, "Failed to link target: {}\n  Command: {}\n  Stderr: {}", target, cmd, stderr)
+            }
+            RuxgoError::Build { failures, skipped } => {
+                write!(f, "Build failed with {} error(s)", failures.len())?;
+                for (i, failure) in failures.iter().enumerate() {
+                    write!(f, "\n[{}/{}] {}", i + 1, failures.len(), failure)?;
+                }
+                if !skipped.is_empty() {
+                    write!(f, "\nSkipped targets depending on a failed one: {}", skipped.join(", "))?;
+                }
+                Ok(())
+            }
+            RuxgoError::Package(msg) => write!(f, "Package error: {}", msg),
+            RuxgoError::Qemu(msg) => write!(f, "Qemu error: {}", msg),
+            RuxgoError::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for RuxgoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RuxgoError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn compile_error_shows_stdout_only_when_the_compiler_printed_some() {
+        let mut err = RuxgoError::Compile {
+            src: "a.c".to_string(),
+            cmd: "gcc -c a.c".to_string(),
+            stdout: String::new(),
+            stderr: "a.c:1:1: error: e".to_string(),
+        };
+        assert_eq!(err.to_string(), "Failed to compile: a.c\n  Command: gcc -c a.c\n  Stderr: a.c:1:1: error: e");
+        if let RuxgoError::Compile { stdout, .. } = &mut err {
+            *stdout = "note".to_string();
+        }
+        assert_eq!(
+            err.to_string(),
+            "Failed to compile: a.c\n  Command: gcc -c a.c\n  Stdout: note\n  Stderr: a.c:1:1: error: e"
+        );
+    }
+
+    #[test]
+    fn io_error_keeps_its_context_and_source() {
+        let err = RuxgoError::io("Could not open config file: x.toml", io::Error::from(io::ErrorKind::NotFound));
+        assert!(err.to_string().starts_with("Could not open config file: x.toml: "));
+        let source = err.source().and_then(|source| source.downcast_ref::<io::Error>()).unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        assert!(RuxgoError::Config("bad".to_string()).source().is_none());
+    }
+
+    #[test]
+    fn build_error_numbers_its_failures_and_names_the_skipped_targets() {
+        let err = RuxgoError::Build {
+            failures: vec![RuxgoError::Config("a".to_string()), RuxgoError::Toolchain("b".to_string())],
+            skipped: vec!["main".to_string(), "tool".to_string()],
+        };
+        assert_eq!(
+            err.to_string(),
+            "Build failed with 2 error(s)\n[1/2] Config error: a\n[2/2] Toolchain error: b\n\
+             Skipped targets depending on a failed one: main, tool"
+        );
+        let err = RuxgoError::Build { failures: vec![RuxgoError::Config("a".to_string())], skipped: Vec::new() };
+        assert_eq!(err.to_string(), "Build failed with 1 error(s)\n[1/1] Config error: a");
+    }
+}
@@ -0,0 +1,105 @@
+//! This module contains the dry-run and explain modes of a build.
+//!
+//! Every step of a build (compile, archive, link, objcopy, cargo, musl) goes through `step` before it
+//! runs, and every file generated outside of a tool (unity sources, pch stubs, compile_commands.json)
+//! through `write`. A dry run lists the steps with their commands and runs or writes none of them,
+//! the explain mode tells why each step runs.
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::process::Cmd;
+use crate::utils::{log, LogLevel};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static EXPLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Why a step of the build runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    /// The output of the step does not exist
+    MissingOutput(String),
+    /// An input of the step changed since it last ran
+    ChangedInput(String),
+    /// The command of the step changed since it last ran, eg: its flags or the compiler
+    ChangedFlags,
+    /// A target the step depends on changed
+    ChangedDependency(String),
+    /// The step always runs, or nothing tells whether it is up to date
+    Forced(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::MissingOutput(path) => write!(f, "missing output {}", path),
+            Reason::ChangedInput(path) => write!(f, "changed input {}", path),
+            Reason::ChangedFlags => write!(f, "changed flags"),
+            Reason::ChangedDependency(dep) => write!(f, "changed dependency {}", dep),
+            Reason::Forced(why) => write!(f, "forced, {}", why),
+        }
+    }
+}
+
+/// Lists the steps of the build without running them
+pub fn enable_dry_run() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+/// Tells why each step of the build runs
+pub fn enable_explain() {
+    EXPLAIN.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether the steps of the build are only listed
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Reports a step about to run, returns whether it should actually run
+/// # Arguments
+/// * `action` - What the step does, eg: "compile"
+/// * `what` - What the step works on, eg: the path of a source file
+/// * `cmd` - The command of the step
+/// * `reason` - Why the step runs
+pub fn step(action: &str, what: &str, cmd: &Cmd, reason: &Reason) -> bool {
+    let run = report(action, what, reason);
+    if !run {
+        println!("    {}", cmd);
+    }
+    run
+}
+
+/// Reports a file about to be generated by ruxgo itself, returns whether it should actually be written
+/// # Arguments
+/// * `path` - The path of the file
+/// * `reason` - Why the file is written
+pub fn write(path: &str, reason: &Reason) -> bool {
+    report("write", path, reason)
+}
+
+/// Logs a step about to run in the dry-run or explain mode, returns whether it should actually run
+fn report(action: &str, what: &str, reason: &Reason) -> bool {
+    let explain = EXPLAIN.load(Ordering::Relaxed);
+    if is_dry_run() {
+        let why = if explain { format!(" ({})", reason) } else { String::new() };
+        log(LogLevel::Log, &format!("Would {} {}{}", action, what, why));
+        return false;
+    }
+    if explain {
+        log(LogLevel::Log, &format!("Explain: {} {}: {}", action, what, reason));
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reasons_name_what_changed() {
+        assert_eq!(Reason::MissingOutput("bin/main".to_string()).to_string(), "missing output bin/main");
+        assert_eq!(Reason::ChangedInput("inc/a.h".to_string()).to_string(), "changed input inc/a.h");
+        assert_eq!(Reason::ChangedFlags.to_string(), "changed flags");
+        assert_eq!(Reason::ChangedDependency("libfoo".to_string()).to_string(), "changed dependency libfoo");
+        assert_eq!(Reason::Forced("no build record".to_string()).to_string(), "forced, no build record");
+    }
+}
@@ -1,6 +1,14 @@
 use crate::utils::{OSConfig, log, LogLevel};
+use crate::error::{Result, RuxgoError};
 
-pub fn cfg_feat(os_config: &OSConfig) -> (Vec<String>, Vec<String>) {
+/// Services of ruxos that can be enabled through `services` in the `[os]` section
+pub const KNOWN_SERVICES: &[&str] = &[
+    "alloc", "alloc-tlsf", "alloc-slab", "alloc-buddy", "paging", "tls", "irq", "smp", "multitask",
+    "sched_fifo", "sched_rr", "sched_cfs", "fp_simd", "fs", "blkfs", "myfs", "virtio-9p", "net-9p",
+    "net", "dns", "display", "rtc", "signal", "pipe", "select", "poll", "epoll", "fd", "random-hw", "musl",
+];
+
+pub fn cfg_feat(os_config: &OSConfig) -> Result<(Vec<String>, Vec<String>)> {
     let mut lib_features = vec![
         "fp_simd", "alloc", "multitask", "fs", "net", "fd", "pipe", "select", "poll", "epoll", "random-hw", "signal"
         ]; 
@@ -18,8 +26,9 @@ pub fn cfg_feat(os_config: &OSConfig) -> (Vec<String>, Vec<String>) {
             rux_feats.push(format!("log-level-{}", os_config.platform.log));
         },
         _ => {
-            log(LogLevel::Error, "LOG must be one of 'off', 'error', 'warn', 'info', 'debug', 'trace'");
-            std::process::exit(1);
+            return Err(RuxgoError::Config(
+                "LOG must be one of 'off', 'error', 'warn', 'info', 'debug', 'trace'".to_string()
+            ));
         }
     }
     if os_config.platform.qemu.bus == "pci" {
@@ -37,27 +46,55 @@ pub fn cfg_feat(os_config: &OSConfig) -> (Vec<String>, Vec<String>) {
             lib_feats.push(feat);
         }
     }
-    (rux_feats, lib_feats)
+    Ok((rux_feats, lib_feats))
 }
 
-pub fn cfg_feat_addprefix(os_config: &OSConfig) -> (Vec<String>, Vec<String>) {
+pub fn cfg_feat_addprefix(os_config: &OSConfig) -> Result<(Vec<String>, Vec<String>)> {
     // Set prefix
     let rux_feat_prefix = "ruxfeat/";
     let lib_feat_prefix = match os_config.ulib.as_str() {
         "ruxlibc" => "ruxlibc/",
         "ruxmusl" => "ruxmusl/",
         _ => {
-            log(LogLevel::Error, "Invalid ulib value");
-            std::process::exit(1);
+            return Err(RuxgoError::Config(format!("Invalid ulib value: {}", os_config.ulib)));
         }
     };
 
     // Add prefix
-    let (rux_feats_pre, lib_feats_pre) = cfg_feat(os_config);
+    let (rux_feats_pre, lib_feats_pre) = cfg_feat(os_config)?;
     let rux_feats_final = rux_feats_pre.into_iter().map(|feat| format!("{}{}", rux_feat_prefix, feat)).collect::<Vec<String>>();
     let lib_feats_final = lib_feats_pre.into_iter().map(|feat| format!("{}{}", lib_feat_prefix, feat)).collect::<Vec<String>>();
     log(LogLevel::Debug, &format!("rux_feats_final : {:?}", rux_feats_final));
     log(LogLevel::Debug, &format!("lib_feats_final : {:?}", lib_feats_final));
 
-    (rux_feats_final, lib_feats_final)
+    Ok((rux_feats_final, lib_feats_final))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os_config(ulib: &str, log: &str) -> OSConfig {
+        let mut os_config = OSConfig { ulib: ulib.to_string(), ..OSConfig::default() };
+        os_config.platform.log = log.to_string();
+        os_config
+    }
+
+    #[test]
+    fn invalid_values_are_returned_as_config_errors() {
+        match cfg_feat_addprefix(&os_config("glibc", "warn")) {
+            Err(RuxgoError::Config(msg)) => assert_eq!(msg, "Invalid ulib value: glibc"),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+        assert!(matches!(cfg_feat(&os_config("ruxlibc", "verbose")), Err(RuxgoError::Config(_))));
+    }
+
+    #[test]
+    fn features_are_split_between_the_os_and_the_ulib() {
+        let mut os_config = os_config("ruxlibc", "info");
+        os_config.features = vec!["fs".to_string(), "paging".to_string()];
+        let (rux_feats, lib_feats) = cfg_feat_addprefix(&os_config).unwrap();
+        assert_eq!(rux_feats, ["ruxfeat/log-level-info", "ruxfeat/paging"]);
+        assert_eq!(lib_feats, ["ruxlibc/fs"]);
+    }
 }
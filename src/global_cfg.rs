@@ -1,4 +1,8 @@
-use crate::utils;
+use crate::cache::ObjectCache;
+use crate::remote_cache::{RemoteCache, REMOTE_CACHE_MODES};
+use crate::error::{Result, RuxgoError};
+use crate::jobs;
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -6,10 +10,18 @@ enum ConfigParam {
     DefaultCompiler(String),
     DefaultLanguage(String),
     License(String),
+    Cache(bool),
+    CacheDir(String),
+    CacheMaxSize(u64),
+    RemoteCache(String),
+    RemoteCacheMode(String),
+    Jobs(usize),
+    MaxLoad(f64),
+    MinFreeMemory(u64),
 }
 
-fn set_config_param(param: ConfigParam, config_file: &PathBuf) {
-    let mut global_conf = GlobalConfig::from_file(config_file);
+fn set_config_param(param: ConfigParam, config_file: &PathBuf) -> Result<()> {
+    let mut global_conf = GlobalConfig::from_file(config_file)?;
     match param {
         ConfigParam::DefaultCompiler(value) => {
             global_conf.default_compiler = value;
@@ -20,9 +32,36 @@ fn set_config_param(param: ConfigParam, config_file: &PathBuf) {
         ConfigParam::License(value) => {
             global_conf.license = value;
         }
+        ConfigParam::Cache(value) => {
+            global_conf.cache = value;
+        }
+        ConfigParam::CacheDir(value) => {
+            global_conf.cache_dir = value;
+        }
+        ConfigParam::CacheMaxSize(value) => {
+            global_conf.cache_max_size = value;
+        }
+        ConfigParam::RemoteCache(value) => {
+            global_conf.remote_cache = value;
+        }
+        ConfigParam::RemoteCacheMode(value) => {
+            global_conf.remote_cache_mode = value;
+        }
+        ConfigParam::Jobs(value) => {
+            global_conf.jobs = value;
+        }
+        ConfigParam::MaxLoad(value) => {
+            global_conf.max_load = value;
+        }
+        ConfigParam::MinFreeMemory(value) => {
+            global_conf.min_free_memory = value;
+        }
     }
 
-    std::fs::write(config_file, toml::to_string(&global_conf).unwrap()).unwrap();
+    let contents = toml::to_string(&global_conf)
+        .map_err(|why| RuxgoError::Config(format!("Failed to serialize global config: {}", why)))?;
+    std::fs::write(config_file, contents)
+        .map_err(|why| RuxgoError::io(format!("Failed to write global config: {}", config_file.display()), why))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,74 +69,142 @@ pub struct GlobalConfig {
     default_compiler: String,
     default_language: String,
     license: String,
+    /// Whether compiled objects are cached, off by default
+    #[serde(default)]
+    cache: bool,
+    /// The directory of the object cache, the user cache directory if empty
+    #[serde(default)]
+    cache_dir: String,
+    /// The size limit of the object cache in MiB
+    #[serde(default = "default_cache_max_size")]
+    cache_max_size: u64,
+    /// The url of the remote cache server, no remote cache if empty
+    #[serde(default)]
+    remote_cache: String,
+    /// Whether objects built locally are uploaded to the remote cache: `read-only` or `read-write`
+    #[serde(default = "default_remote_cache_mode")]
+    remote_cache_mode: String,
+    /// The number of jobs run in parallel, the number of CPUs if 0
+    #[serde(default)]
+    jobs: usize,
+    /// The load average above which no new job starts, no limit if 0
+    #[serde(default)]
+    max_load: f64,
+    /// The available memory in MiB below which no new job starts, no limit if 0
+    #[serde(default)]
+    min_free_memory: u64,
+}
+
+fn default_cache_max_size() -> u64 {
+    5 * 1024
+}
+
+fn default_remote_cache_mode() -> String {
+    String::from("read-only")
 }
 
 impl GlobalConfig {
-    pub fn set_defaults(config: &PathBuf, parameter: &str, value: &str) {
+    pub fn set_defaults(config: &PathBuf, parameter: &str, value: &str) -> Result<()> {
         match parameter {
             "default_compiler" => {
                 if value == "gcc" || value == "clang" {
-                    set_config_param(ConfigParam::DefaultCompiler(value.to_string()), config);
+                    set_config_param(ConfigParam::DefaultCompiler(value.to_string()), config)
                 } else {
-                    utils::log(
-                        utils::LogLevel::Error,
-                        "Invalid compiler. See `ruxgo config --help` for more info",
-                    );
-                    std::process::exit(1);
+                    Err(RuxgoError::Config(
+                        "Invalid compiler. See `ruxgo config --help` for more info".to_string()
+                    ))
                 }
             }
             "default_language" => {
                 if value == "c" || value == "cpp" {
-                    set_config_param(ConfigParam::DefaultLanguage(value.to_string()), config);
+                    set_config_param(ConfigParam::DefaultLanguage(value.to_string()), config)
                 } else {
-                    utils::log(
-                        utils::LogLevel::Error,
-                        "Invalid language. See `ruxgo config --help` for more info",
-                    );
-                    std::process::exit(1);
+                    Err(RuxgoError::Config(
+                        "Invalid language. See `ruxgo config --help` for more info".to_string()
+                    ))
                 }
             }
             "license" => {
                 if std::path::Path::new(value).exists() {
-                    let value = std::fs::read_to_string(value).unwrap();
-                    set_config_param(ConfigParam::License(value), config);
+                    let value = std::fs::read_to_string(value)
+                        .map_err(|why| RuxgoError::io(format!("Failed to read license file: {}", value), why))?;
+                    set_config_param(ConfigParam::License(value), config)
+                } else {
+                    Err(RuxgoError::Config(
+                        "Invalid license file. See `ruxgo config --help` for more info".to_string()
+                    ))
+                }
+            }
+            "cache" => {
+                match value.parse::<bool>() {
+                    Ok(value) => set_config_param(ConfigParam::Cache(value), config),
+                    Err(_) => Err(RuxgoError::Config(
+                        "Invalid cache switch. See `ruxgo config --help` for more info".to_string()
+                    )),
+                }
+            }
+            "cache_dir" => set_config_param(ConfigParam::CacheDir(value.to_string()), config),
+            "cache_max_size" => {
+                match value.parse::<u64>() {
+                    Ok(value) => set_config_param(ConfigParam::CacheMaxSize(value), config),
+                    Err(_) => Err(RuxgoError::Config(
+                        "Invalid cache size. See `ruxgo config --help` for more info".to_string()
+                    )),
+                }
+            }
+            "remote_cache" => {
+                if value.is_empty() || value.starts_with("http://") {
+                    set_config_param(ConfigParam::RemoteCache(value.to_string()), config)
                 } else {
-                    utils::log(
-                        utils::LogLevel::Error,
-                        "Invalid license file. See `ruxgo config --help` for more info",
-                    );
-                    std::process::exit(1);
+                    Err(RuxgoError::Config(
+                        "Invalid remote cache url, expected http://host:port. See `ruxgo config --help` for more info".to_string()
+                    ))
                 }
             }
-            _ => {
-                utils::log(
-                    utils::LogLevel::Error,
-                    "Invalid parameter. See `ruxgo config --help` for more info",
-                );
-                std::process::exit(1);
+            "remote_cache_mode" => {
+                if REMOTE_CACHE_MODES.contains(&value) {
+                    set_config_param(ConfigParam::RemoteCacheMode(value.to_string()), config)
+                } else {
+                    Err(RuxgoError::Config(
+                        "Invalid remote cache mode. See `ruxgo config --help` for more info".to_string()
+                    ))
+                }
+            }
+            "jobs" => {
+                match value.parse::<usize>() {
+                    Ok(value) => set_config_param(ConfigParam::Jobs(value), config),
+                    Err(_) => Err(RuxgoError::Config(
+                        "Invalid number of jobs. See `ruxgo config --help` for more info".to_string()
+                    )),
+                }
+            }
+            "max_load" => {
+                match value.parse::<f64>() {
+                    Ok(value) if value >= 0.0 => set_config_param(ConfigParam::MaxLoad(value), config),
+                    _ => Err(RuxgoError::Config(
+                        "Invalid load average. See `ruxgo config --help` for more info".to_string()
+                    )),
+                }
+            }
+            "min_free_memory" => {
+                match value.parse::<u64>() {
+                    Ok(value) => set_config_param(ConfigParam::MinFreeMemory(value), config),
+                    Err(_) => Err(RuxgoError::Config(
+                        "Invalid memory size. See `ruxgo config --help` for more info".to_string()
+                    )),
+                }
             }
+            _ => Err(RuxgoError::Config(
+                "Invalid parameter. See `ruxgo config --help` for more info".to_string()
+            )),
         }
     }
 
-    pub fn from_file(path: &PathBuf) -> Self {
-        let config = std::fs::read_to_string(path).unwrap();
-        let mut config: toml::Value = toml::from_str(&config).unwrap();
-        let config = config.as_table_mut().unwrap();
-        GlobalConfig {
-            default_compiler: config
-                .get("default_compiler")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string(),
-            default_language: config
-                .get("default_language")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string(),
-            license: config.get("license").unwrap().as_str().unwrap().to_string(),
-        }
+    pub fn from_file(path: &PathBuf) -> Result<Self> {
+        let config = std::fs::read_to_string(path)
+            .map_err(|why| RuxgoError::io(format!("Failed to read global config: {}", path.display()), why))?;
+        toml::from_str(&config)
+            .map_err(|why| RuxgoError::Config(format!("Invalid global config {}: {}", path.display(), why)))
     }
 
     pub fn get_default_compiler(&self) -> String {
@@ -111,4 +218,43 @@ impl GlobalConfig {
     pub fn get_license(&self) -> String {
         self.license.clone()
     }
+
+    /// Returns the object cache configured by the user, whether it is enabled or not
+    pub fn get_object_cache(&self) -> Result<ObjectCache> {
+        let dir = if self.cache_dir.is_empty() {
+            ProjectDirs::from("com", "RuxosApps", "ruxos-c")
+                .ok_or_else(|| RuxgoError::Config("Could not determine the home directory".to_string()))?
+                .cache_dir()
+                .join("objects")
+        } else {
+            PathBuf::from(&self.cache_dir)
+        };
+        Ok(ObjectCache::new(dir, self.cache_max_size * 1024 * 1024))
+    }
+
+    pub fn is_cache_enabled(&self) -> bool {
+        self.cache
+    }
+
+    /// Sets the limits of the jobs of the build
+    /// # Arguments
+    /// * `cli_jobs` - The `--jobs` option, if given
+    pub fn configure_jobs(&self, cli_jobs: Option<usize>) -> Result<()> {
+        let count = jobs::resolve(cli_jobs, self.jobs)?;
+        jobs::configure(count, self.max_load, self.min_free_memory)
+    }
+
+    /// Returns the remote cache configured by the user, None if there is none
+    pub fn get_remote_cache(&self) -> Result<Option<RemoteCache>> {
+        if self.remote_cache.is_empty() {
+            return Ok(None);
+        }
+        match self.remote_cache_mode.as_str() {
+            "read-only" => Ok(Some(RemoteCache::new(&self.remote_cache, false))),
+            "read-write" => Ok(Some(RemoteCache::new(&self.remote_cache, true))),
+            mode => Err(RuxgoError::Config(format!(
+                "Invalid remote_cache_mode {} in global config, expected one of: {}", mode, REMOTE_CACHE_MODES.join(", ")
+            ))),
+        }
+    }
 }
\ No newline at end of file
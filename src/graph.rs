@@ -0,0 +1,388 @@
+//! This module contains the dependency graph of the targets
+
+use crate::utils::{BuildConfig, TargetConfig, Package};
+use crate::error::{Result, RuxgoError};
+
+/// Represents a target in the build graph, along with the configs it is built with
+pub struct BuildNode<'a> {
+    pub build_config: &'a BuildConfig,
+    pub target_config: &'a TargetConfig,
+    /// The targets declared next to this target (the local targets or those of its package)
+    pub targets: &'a Vec<TargetConfig>,
+    /// The packages visible to this target
+    pub packages: &'a Vec<Package>,
+    /// Indices of the nodes this target depends on
+    pub deps: Vec<usize>,
+}
+
+/// Represents the dependency graph of the local targets and the targets of the packages
+pub struct BuildGraph<'a> {
+    nodes: Vec<BuildNode<'a>>,
+    order: Vec<usize>,
+}
+
+/// Visiting state of a node during the depth first search
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Unvisited,
+    InProgress,
+    Done,
+}
+
+impl<'a> BuildGraph<'a> {
+    /// Creates the build graph and orders it topologically
+    ///
+    /// Fails on dependency names that match no target and on dependency cycles
+    /// # Arguments
+    /// * `build_config` - The local build config
+    /// * `targets` - The local targets
+    /// * `packages` - The packages whose targets can be depended on
+    pub fn new(build_config: &'a BuildConfig, targets: &'a Vec<TargetConfig>, packages: &'a Vec<Package>) -> Result<Self> {
+        let mut nodes = Vec::new();
+        for target_config in targets {
+            nodes.push(BuildNode { build_config, target_config, targets, packages, deps: Vec::new() });
+        }
+        BuildGraph::add_packages(&mut nodes, packages);
+
+        // resolve deps, a local target shadows a package target with the same name
+        for i in 0..nodes.len() {
+            let mut deps = Vec::new();
+            for dep in &nodes[i].target_config.deps {
+                match nodes.iter().position(|node| node.target_config.name == *dep) {
+                    Some(j) => deps.push(j),
+                    None => {
+                        return Err(RuxgoError::Config(format!(
+                            "Dependency {} of target {} does not exist", dep, nodes[i].target_config.name
+                        )));
+                    }
+                }
+            }
+            // dlls link every lib of their packages, not only their deps
+            if nodes[i].target_config.typ == "dll" {
+                for target_config in nodes[i].packages.iter().flat_map(|pkg| pkg.target_configs.iter()) {
+                    // the sub packages of a package are copies of the packages of the flat list, match them by name
+                    if let Some(j) = nodes.iter().position(|node| node.target_config.name == target_config.name) {
+                        if !deps.contains(&j) {
+                            deps.push(j);
+                        }
+                    }
+                }
+            }
+            nodes[i].deps = deps;
+        }
+
+        let order = BuildGraph::sort(&nodes)?;
+        Ok(BuildGraph { nodes, order })
+    }
+
+    /// Adds the targets of the packages
+    ///
+    /// The packages are a flat list already holding the sub packages, see `Package::parse_packages`,
+    /// so the sub packages are not walked again: their targets would be built twice at the same time
+    fn add_packages(nodes: &mut Vec<BuildNode<'a>>, packages: &'a Vec<Package>) {
+        for pkg in packages {
+            for target_config in &pkg.target_configs {
+                nodes.push(BuildNode {
+                    build_config: &pkg.build_config,
+                    target_config,
+                    targets: &pkg.target_configs,
+                    packages: &pkg.sub_packages,
+                    deps: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Returns the node indices so that every node comes after its dependencies
+    fn sort(nodes: &[BuildNode]) -> Result<Vec<usize>> {
+        let mut marks = vec![Mark::Unvisited; nodes.len()];
+        let mut order = Vec::with_capacity(nodes.len());
+        for root in 0..nodes.len() {
+            if marks[root] != Mark::Unvisited {
+                continue;
+            }
+            // iterative dfs, each entry is a node and the index of its next dep to visit
+            let mut stack = vec![(root, 0)];
+            marks[root] = Mark::InProgress;
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                if let Some(&dep) = nodes[node].deps.get(*next) {
+                    *next += 1;
+                    match marks[dep] {
+                        Mark::Unvisited => {
+                            marks[dep] = Mark::InProgress;
+                            stack.push((dep, 0));
+                        }
+                        Mark::InProgress => {
+                            let start = stack.iter().position(|&(n, _)| n == dep).unwrap_or(0);
+                            let cycle = stack[start..]
+                                .iter()
+                                .map(|&(n, _)| nodes[n].target_config.name.as_str())
+                                .chain(std::iter::once(nodes[dep].target_config.name.as_str()))
+                                .collect::<Vec<&str>>();
+                            return Err(RuxgoError::Config(format!(
+                                "Circular dependency found: {}", cycle.join(" -> ")
+                            )));
+                        }
+                        Mark::Done => {}
+                    }
+                } else {
+                    marks[node] = Mark::Done;
+                    order.push(node);
+                    stack.pop();
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Keeps only the given targets and the targets they depend on, every target if none is given
+    ///
+    /// Fails on names that match no target
+    /// # Arguments
+    /// * `names` - The names of the targets to keep
+    pub fn select(self, names: &[String]) -> Result<Self> {
+        if names.is_empty() {
+            return Ok(self);
+        }
+        let BuildGraph { nodes, order } = self;
+        let mut stack = Vec::new();
+        for name in names {
+            // a local target shadows a package target with the same name, as for deps
+            match nodes.iter().position(|node| node.target_config.name == *name) {
+                Some(i) => stack.push(i),
+                None => {
+                    let known = nodes.iter().map(|node| node.target_config.name.as_str()).collect::<Vec<&str>>();
+                    return Err(RuxgoError::Config(format!(
+                        "Target {} does not exist, the targets are: {}", name, known.join(", ")
+                    )));
+                }
+            }
+        }
+        let mut keep = vec![false; nodes.len()];
+        while let Some(i) = stack.pop() {
+            if !keep[i] {
+                keep[i] = true;
+                stack.extend(&nodes[i].deps);
+            }
+        }
+
+        // the deps of a kept node are kept too, only their indices change
+        let mut index = vec![0; nodes.len()];
+        let mut kept = Vec::new();
+        for (i, node) in nodes.into_iter().enumerate() {
+            if keep[i] {
+                index[i] = kept.len();
+                kept.push(node);
+            }
+        }
+        for node in &mut kept {
+            node.deps = node.deps.iter().map(|&dep| index[dep]).collect();
+        }
+        let order = order.into_iter().filter(|&i| keep[i]).map(|i| index[i]).collect();
+        Ok(BuildGraph { nodes: kept, order })
+    }
+
+    /// Adds the ulib built from sources, which every exe links with when running on the os
+    /// # Arguments
+    /// * `build_config` - The local build config
+    /// * `target_config` - The target of the ulib
+    /// * `targets` - The targets declared next to the ulib, none
+    /// * `packages` - The packages visible to the ulib, none
+    pub fn add_ulib(
+        &mut self,
+        build_config: &'a BuildConfig,
+        target_config: &'a TargetConfig,
+        targets: &'a Vec<TargetConfig>,
+        packages: &'a Vec<Package>,
+    ) {
+        let ulib = self.nodes.len();
+        for node in &mut self.nodes {
+            if node.target_config.typ == "exe" {
+                node.deps.push(ulib);
+            }
+        }
+        self.nodes.push(BuildNode { build_config, target_config, targets, packages, deps: Vec::new() });
+        // the ulib depends on nothing, it can come first
+        self.order.insert(0, ulib);
+    }
+
+    /// Returns the nodes in topological order, dependencies first, along with their indices
+    pub fn ordered(&self) -> impl Iterator<Item = (usize, &BuildNode<'a>)> {
+        self.order.iter().map(move |&i| (i, &self.nodes[i]))
+    }
+
+    /// Returns all nodes, `BuildNode::deps` indexes into it
+    pub fn nodes(&self) -> &[BuildNode<'a>] {
+        &self.nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_config() -> BuildConfig {
+        toml::from_str("compiler = \"gcc\"").unwrap()
+    }
+
+    /// A target named lib* is a static lib, any other an exe
+    fn target(name: &str, deps: &[&str]) -> TargetConfig {
+        let typ = if name.starts_with("lib") { "static" } else { "exe" };
+        typed_target(name, typ, deps)
+    }
+
+    fn typed_target(name: &str, typ: &str, deps: &[&str]) -> TargetConfig {
+        toml::from_str(&format!("name = \"{}\"\ntype = \"{}\"\ndeps = {:?}", name, typ, deps)).unwrap()
+    }
+
+    fn package(name: &str, targets: Vec<TargetConfig>, sub_packages: Vec<Package>) -> Package {
+        Package::new(name.to_string(), format!("user/{}", name), "main".to_string(), build_config(), targets, sub_packages)
+    }
+
+    fn names(graph: &BuildGraph) -> Vec<String> {
+        graph.ordered().map(|(_, node)| node.target_config.name.clone()).collect()
+    }
+
+    fn config_error(result: Result<BuildGraph>) -> String {
+        match result {
+            Err(RuxgoError::Config(msg)) => msg,
+            Err(e) => panic!("expected a config error, got {}", e),
+            Ok(graph) => panic!("expected a config error, got {:?}", names(&graph)),
+        }
+    }
+
+    #[test]
+    fn dependencies_come_before_their_dependents() {
+        let build_config = build_config();
+        // main -> liba -> libb, main -> libb, tool -> libb
+        let targets = vec![
+            target("main", &["liba", "libb"]),
+            target("tool", &["libb"]),
+            target("liba", &["libb"]),
+            target("libb", &[]),
+        ];
+        let packages = Vec::new();
+        let graph = BuildGraph::new(&build_config, &targets, &packages).unwrap();
+        assert_eq!(names(&graph), ["libb", "liba", "main", "tool"]);
+    }
+
+    #[test]
+    fn package_targets_come_before_the_local_targets() {
+        let build_config = build_config();
+        let targets = vec![target("main", &["libpkg"]), target("libfree", &[])];
+        let packages = vec![package("pkg", vec![target("libpkg", &[])], Vec::new())];
+        let graph = BuildGraph::new(&build_config, &targets, &packages).unwrap();
+        assert_eq!(names(&graph), ["libpkg", "main", "libfree"]);
+    }
+
+    #[test]
+    fn cycle_is_reported_with_its_path() {
+        let build_config = build_config();
+        let targets = vec![
+            target("main", &["liba"]),
+            target("liba", &["libb"]),
+            target("libb", &["libc"]),
+            target("libc", &["liba"]),
+        ];
+        let packages = Vec::new();
+        // main leads into the cycle but is not part of it
+        assert_eq!(
+            config_error(BuildGraph::new(&build_config, &targets, &packages)),
+            "Circular dependency found: liba -> libb -> libc -> liba"
+        );
+        let targets = vec![target("liba", &["liba"])];
+        assert_eq!(
+            config_error(BuildGraph::new(&build_config, &targets, &packages)),
+            "Circular dependency found: liba -> liba"
+        );
+    }
+
+    #[test]
+    fn dangling_dependency_names_the_target_depending_on_it() {
+        let build_config = build_config();
+        let targets = vec![target("main", &["liba"]), target("liba", &["libmissing"])];
+        let packages = Vec::new();
+        assert_eq!(
+            config_error(BuildGraph::new(&build_config, &targets, &packages)),
+            "Dependency libmissing of target liba does not exist"
+        );
+    }
+
+    #[test]
+    fn selected_targets_are_kept_with_their_dependencies() {
+        let build_config = build_config();
+        let targets = vec![
+            target("main", &["liba"]),
+            target("tool", &["libb"]),
+            target("liba", &["libb"]),
+            target("libb", &[]),
+            target("libc", &[]),
+        ];
+        let packages = Vec::new();
+        let select = |selected: &[&str]| {
+            let selected = selected.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+            BuildGraph::new(&build_config, &targets, &packages).unwrap().select(&selected)
+        };
+        assert_eq!(names(&select(&[]).unwrap()), ["libb", "liba", "main", "tool", "libc"]);
+        assert_eq!(names(&select(&["libc"]).unwrap()), ["libc"]);
+        assert_eq!(names(&select(&["main"]).unwrap()), ["libb", "liba", "main"]);
+        assert_eq!(names(&select(&["tool", "liba", "tool"]).unwrap()), ["libb", "liba", "tool"]);
+
+        // the deps still point to the same targets once the nodes are renumbered
+        let graph = select(&["tool", "main"]).unwrap();
+        for node in graph.nodes() {
+            let deps = node.deps.iter().map(|&dep| &graph.nodes()[dep].target_config.name).collect::<Vec<_>>();
+            assert_eq!(deps, node.target_config.deps.iter().collect::<Vec<_>>());
+        }
+
+        assert_eq!(
+            config_error(select(&["main", "nope"])),
+            "Target nope does not exist, the targets are: main, tool, liba, libb, libc"
+        );
+    }
+
+    #[test]
+    fn nested_package_targets_are_added_once() {
+        let inner = package("inner", vec![target("libinner", &[])], Vec::new());
+        let outer = package("outer", vec![target("libouter", &["libinner"])], vec![inner.clone()]);
+        // `Package::parse_packages` lists the sub packages next to the packages depending on them
+        let packages = vec![inner, outer];
+        let targets = vec![target("main", &["libouter"])];
+        let build_config = build_config();
+        let graph = BuildGraph::new(&build_config, &targets, &packages).unwrap();
+        assert_eq!(names(&graph), ["libinner", "libouter", "main"]);
+    }
+
+    #[test]
+    fn dll_depends_on_the_libs_of_its_sub_packages() {
+        let inner = package("inner", vec![target("libinner", &[])], Vec::new());
+        let outer = package("outer", vec![typed_target("libouter", "dll", &[])], vec![inner.clone()]);
+        let packages = vec![inner, outer];
+        let targets = vec![target("main", &["libouter"])];
+        let build_config = build_config();
+        let graph = BuildGraph::new(&build_config, &targets, &packages).unwrap();
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(names(&graph), ["libinner", "libouter", "main"]);
+    }
+
+    #[test]
+    fn ulib_comes_first_and_every_exe_links_with_it() {
+        let build_config = build_config();
+        let targets = vec![target("main", &["liba"]), target("tool", &[]), target("liba", &[])];
+        let packages = Vec::new();
+        let ulib = target("libc", &[]);
+        let (no_targets, no_packages) = (Vec::new(), Vec::new());
+        let mut graph = BuildGraph::new(&build_config, &targets, &packages).unwrap();
+        graph.add_ulib(&build_config, &ulib, &no_targets, &no_packages);
+        assert_eq!(names(&graph), ["libc", "liba", "main", "tool"]);
+        for node in graph.nodes() {
+            let deps = node.deps.iter().map(|&dep| graph.nodes()[dep].target_config.name.as_str()).collect::<Vec<_>>();
+            match node.target_config.name.as_str() {
+                "main" => assert_eq!(deps, ["liba", "libc"]),
+                "tool" => assert_eq!(deps, ["libc"]),
+                _ => assert!(deps.is_empty()),
+            }
+        }
+    }
+}
@@ -1,127 +1,50 @@
-//! This module contains functions for hashing files and checking if they have changed.
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
-use std::cmp::min;
-use std::path::Path;
+//! This module contains functions for hashing files.
+use std::fs::File;
+use std::io::Read;
 use crate::utils::{log, LogLevel};
-use std::collections::HashMap;
-use sha1::{Sha1, Digest};
+use xxhash_rust::xxh3::Xxh3;
 
 /// Hashes a file and returns the hash as a string.
-fn hash_file(path: &str) -> Option<String> {
+///
+/// The hash is a 128 bit xxh3, fast rather than cryptographic, as it only detects changes.
+pub fn hash_file(path: &str) -> Option<String> {
     if let Ok(mut file) = File::open(path) {
         const CHUNK_SIZE: usize = 1024 * 1024;  // 1MB: read files in chunks 
-        let mut limit = file.metadata().unwrap_or_else(|why| {
-            log(LogLevel::Error, &format!("Failed to get length for file: {}", path));
-            log(LogLevel::Error, &format!("Error: {}", why));
-            std::process::exit(1);
-        }).len();
-        let mut buffer = [0; CHUNK_SIZE];
-        let mut hasher = Sha1::new();
-    
-        while limit > 0 {
-            let read_size = min(limit as usize, CHUNK_SIZE);
-            let read = file.read(&mut buffer[0..read_size]).unwrap();
-            if read == 0 {
-                break;
-            }
-            limit -= read as u64;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut hasher = Xxh3::new();
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(why) => {
+                    log(LogLevel::Warn, &format!("Failed to read file: {}: {}", path, why));
+                    return None;
+                }
+            };
             hasher.update(&buffer[0..read]);
         }
-        let result = hasher.finalize();
-        Some(result.iter().map(|byte| format!("{:02x}", byte)).collect())
+        Some(format!("{:032x}", hasher.digest128()))
     } else {
         log(LogLevel::Warn, &format!("Failed to open file '{}'", path));
         None
     }
 }
 
-/// Returns the hash of a file if it exists in the path_hash.
-/// Otherwise returns None.
-/// # Arguments
-/// * `path` - The path of the file to get the hash of.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn get_hash(path: &str, path_hash: &HashMap<String, String>) -> Option<String> {
-    if path_hash.contains_key(path) {
-        return Some(path_hash.get(path).unwrap().to_string());
-    }
-    return None;
-}
-
-/// Loads the hashes from a file and returns them as a hashmap.
-/// # Arguments
-/// * `path` - The path of the file to load the hashes from.
-pub fn load_hashes_from_file(path: &str) -> HashMap<String, String> {
-    let mut path_hash: HashMap<String, String> = HashMap::new();
-    let path = Path::new(path);
-    if !path.exists() {
-        return path_hash;
-    }
-    let mut file = OpenOptions::new().read(true).open(path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    for line in contents.lines() {
-        if line.is_empty() {
-            continue;
-        }
-        let mut split = line.split(" ");
-        let path = split.next().unwrap();
-        let hash = split.next().unwrap();
-        path_hash.insert(path.to_string(), hash.to_string());
-    }
-    return path_hash;
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Saves the hashes to a file.
-/// # Arguments
-/// * `path` - The path of the file to save the hashes to.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn save_hashes_to_file(path: &str, path_hash: &HashMap<String, String>) {
-    let mut file = OpenOptions::new().write(true).create(true).open(path).unwrap_or_else(|_| {
-        log(LogLevel::Error, &format!("Failed to open file: {}", path));
-        std::process::exit(1);
-    });
-    for (path, hash) in path_hash {
-        let line = format!("{} {}\n", path, hash);
-        file.write(line.as_bytes()).unwrap();
-    }
-}
-
-/// Checks if a file has changed.
-/// # Arguments
-/// * `path` - The path of the file to check.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn is_file_changed(path: &str, path_hash: &HashMap<String, String>) -> bool {
-    let hash = get_hash(path, path_hash);
-    if hash.is_none() {
-        return true;
-    }
-    let hash = hash.unwrap();
-    let new_hash = match hash_file(path) {
-        Some(h) => h,
-        None => String::new(),
-    };
-    hash != new_hash
-}
-
-/// Saves the hash of a file to the hashmap.
-/// # Arguments
-/// * `path` - The path of the file to save the hash of.
-/// * `path_hash` - The hashmap of paths and hashes.
-pub fn save_hash(path: &str, path_hash: &mut HashMap<String, String>) {
-    let new_hash = match hash_file(path) {
-        Some(h) => h,
-        None => String::new(),
-    };
-    let hash = get_hash(path, path_hash);
-    if hash.is_none() {
-        path_hash.insert(path.to_string(), new_hash);
-        return;
-    }
-    let hash = hash.unwrap();
-    if hash != new_hash {
-        log(LogLevel::Info, &format!("File changed, updating hash for file: {}", path));
-        path_hash.insert(path.to_string(), new_hash);
-        return;
+    #[test]
+    fn hash_is_a_128_bit_hex_digest_of_the_content() {
+        let path = std::env::temp_dir().join(format!("ruxgo-{}-hash.c", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "int a;\n").unwrap();
+        let hash = hash_file(path).unwrap();
+        assert_eq!(hash.len(), 32);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        std::fs::write(path, "int b;\n").unwrap();
+        assert_ne!(hash_file(path).unwrap(), hash);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(hash_file(path), None);
     }
 }
@@ -0,0 +1,211 @@
+//! This module contains the limit on the jobs run in parallel.
+//!
+//! The job count bounds the compilations of ruxgo, and is handed down to cargo and make. An optional
+//! throttle holds back new compilations while the load average is too high or the memory too low.
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use crate::utils::{log, LogLevel};
+use crate::error::{Result, RuxgoError};
+
+static JOBS: OnceLock<Jobs> = OnceLock::new();
+/// Environment variable overriding the job count of the global config
+pub const JOBS_ENV: &str = "RUXGO_JOBS";
+/// How long a held back job waits before looking at the load again
+const THROTTLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Represents the limits of the jobs of a build
+pub struct Jobs {
+    count: usize,
+    /// The load average above which no new job starts, 0 to ignore the load
+    max_load: f64,
+    /// The available memory in bytes below which no new job starts, 0 to ignore the memory
+    min_free_memory: u64,
+    running: AtomicUsize,
+}
+
+/// A running job, it frees its slot when dropped
+pub struct JobSlot;
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        if let Some(jobs) = JOBS.get() {
+            jobs.running.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Returns the job count to use: the command line first, then `RUXGO_JOBS`, then the global config,
+/// and the number of CPUs if none of them sets it, a count of 0 being not set
+/// # Arguments
+/// * `cli` - The `--jobs` option, if given
+/// * `config` - The `jobs` of the global config, 0 if not set
+pub fn resolve(cli: Option<usize>, config: usize) -> Result<usize> {
+    let env = std::env::var(JOBS_ENV).ok();
+    resolve_from(cli, env.as_deref(), config)
+}
+
+/// Returns the job count to use, as `resolve` does with the given value of `RUXGO_JOBS`
+/// # Arguments
+/// * `cli` - The `--jobs` option, if given
+/// * `env` - The value of `RUXGO_JOBS`, if set
+/// * `config` - The `jobs` of the global config, 0 if not set
+fn resolve_from(cli: Option<usize>, env: Option<&str>, config: usize) -> Result<usize> {
+    let env = match env {
+        Some(value) if !value.is_empty() => Some(value.parse::<usize>().map_err(|_| {
+            RuxgoError::Config(format!("Invalid {}: {}, expected a number of jobs", JOBS_ENV, value))
+        })?),
+        _ => None,
+    };
+    // a count of 0 sets nothing, the next source is used
+    let count = [cli, env, Some(config)]
+        .into_iter()
+        .flatten()
+        .find(|&count| count > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get()));
+    Ok(count)
+}
+
+/// Sets the limits of the jobs for this invocation, and sizes the compilation pool accordingly
+/// # Arguments
+/// * `count` - The number of jobs run in parallel
+/// * `max_load` - The load average above which no new job starts, 0 to ignore the load
+/// * `min_free_memory` - The available memory in MiB below which no new job starts, 0 to ignore the memory
+pub fn configure(count: usize, max_load: f64, min_free_memory: u64) -> Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(count)
+        .build_global()
+        .map_err(|why| RuxgoError::Config(format!("Failed to create a pool of {} jobs: {}", count, why)))?;
+    let _ = JOBS.set(Jobs {
+        count,
+        max_load,
+        min_free_memory: min_free_memory * 1024 * 1024,
+        running: AtomicUsize::new(0),
+    });
+    log(LogLevel::Info, &format!("Running up to {} jobs in parallel", count));
+    Ok(())
+}
+
+/// Returns the number of jobs run in parallel, the number of CPUs if it was not configured
+pub fn count() -> usize {
+    match JOBS.get() {
+        Some(jobs) => jobs.count,
+        None => thread::available_parallelism().map_or(1, |count| count.get()),
+    }
+}
+
+/// Waits until the machine has room for a new job, then returns its slot
+///
+/// A job always starts when no other one runs, so that a busy machine slows the build down without stalling it
+pub fn acquire() -> JobSlot {
+    let Some(jobs) = JOBS.get() else {
+        return JobSlot;
+    };
+    let mut logged = false;
+    loop {
+        if jobs.running.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            return JobSlot;
+        }
+        let Some(reason) = jobs.overloaded() else {
+            jobs.running.fetch_add(1, Ordering::AcqRel);
+            return JobSlot;
+        };
+        if !logged {
+            log(LogLevel::Debug, &format!("Holding back a job: {}", reason));
+            logged = true;
+        }
+        thread::sleep(THROTTLE_INTERVAL);
+    }
+}
+
+impl Jobs {
+    /// Returns why the machine has no room for a new job, None if it has
+    fn overloaded(&self) -> Option<String> {
+        if self.max_load > 0.0 {
+            if let Some(load) = load_average() {
+                if load > self.max_load {
+                    return Some(format!("load average {:.2} above {}", load, self.max_load));
+                }
+            }
+        }
+        if self.min_free_memory > 0 {
+            if let Some(free) = available_memory() {
+                if free < self.min_free_memory {
+                    return Some(format!(
+                        "{} MiB of memory available, below {} MiB",
+                        free / (1024 * 1024), self.min_free_memory / (1024 * 1024)
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Returns the load average of the last minute, None where it can't be read
+fn load_average() -> Option<f64> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Returns the memory available to new processes in bytes, None where it can't be read
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpus() -> usize {
+        thread::available_parallelism().map_or(1, |count| count.get())
+    }
+
+    #[test]
+    fn command_line_comes_first_then_the_environment_then_the_config() {
+        assert_eq!(resolve_from(Some(2), Some("4"), 3).unwrap(), 2);
+        assert_eq!(resolve_from(None, Some("4"), 3).unwrap(), 4);
+        assert_eq!(resolve_from(None, None, 3).unwrap(), 3);
+        // an empty variable is not set
+        assert_eq!(resolve_from(None, Some(""), 3).unwrap(), 3);
+    }
+
+    #[test]
+    fn job_count_of_0_falls_back_to_the_next_source() {
+        assert_eq!(resolve_from(Some(0), Some("4"), 3).unwrap(), 4);
+        assert_eq!(resolve_from(Some(0), None, 3).unwrap(), 3);
+        assert_eq!(resolve_from(None, Some("0"), 3).unwrap(), 3);
+        assert_eq!(resolve_from(Some(0), Some("0"), 0).unwrap(), cpus());
+    }
+
+    #[test]
+    fn no_job_count_gives_the_number_of_cpus() {
+        assert_eq!(resolve_from(None, None, 0).unwrap(), cpus());
+    }
+
+    #[test]
+    fn invalid_environment_is_a_config_error() {
+        for value in ["many", "-1", "2.5"] {
+            match resolve_from(Some(2), Some(value), 3) {
+                Err(RuxgoError::Config(msg)) => {
+                    assert_eq!(msg, format!("Invalid RUXGO_JOBS: {}, expected a number of jobs", value));
+                }
+                other => panic!("expected a config error for {}, got {:?}", value, other),
+            }
+        }
+    }
+
+    #[test]
+    fn throttle_only_holds_jobs_back_past_its_limits() {
+        let jobs = |max_load, min_free_memory| Jobs { count: 1, max_load, min_free_memory, running: AtomicUsize::new(0) };
+        assert_eq!(jobs(0.0, 0).overloaded(), None);
+        if available_memory().is_some() {
+            let reason = jobs(0.0, u64::MAX).overloaded().unwrap();
+            assert!(reason.ends_with(&format!(" MiB of memory available, below {} MiB", u64::MAX / (1024 * 1024))), "{}", reason);
+        }
+    }
+}
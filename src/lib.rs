@@ -12,9 +12,33 @@ pub mod builder;
 pub mod commands;
 /// Contains logger, config parser and environment config
 pub mod utils;
+/// Contains the dependency graph of the targets
+pub mod graph;
+/// Contains the scheduler building targets in parallel
+pub mod scheduler;
 /// Contains hashing related functions
 pub mod hasher;
+/// Contains the dry-run and explain modes of a build
+pub mod explain;
+/// Contains the timings of the steps of a build
+pub mod timings;
+/// Contains the limit on the jobs run in parallel
+pub mod jobs;
+/// Contains the build state recorded between builds
+pub mod build_state;
+/// Contains the local object cache
+pub mod cache;
+/// Contains the remote build cache client and server
+pub mod remote_cache;
+/// Contains the command model running the tools without a shell
+pub mod process;
+/// Contains the parser and the summary of the compiler diagnostics
+pub mod diagnostics;
+/// Contains compiler depfile related functions
+pub mod depfile;
 /// Contains features related functions
 pub mod features;
 /// Handles global config
 pub mod global_cfg;
+/// Contains the error type of the library
+pub mod error;
@@ -1,6 +1,9 @@
-use ruxgo::utils::OSConfig;
-use ruxgo::{utils, commands};
-use clap::{Parser, Subcommand};
+use ruxgo::utils::{BuildConfig, OSConfig, TargetConfig, Package};
+use ruxgo::{utils, commands, cache, remote_cache, diagnostics, explain, timings};
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use ruxgo::error::{Result, RuxgoError};
+use clap::{CommandFactory, Parser, Subcommand};
 use directories::ProjectDirs;
 use ruxgo::global_cfg::GlobalConfig;
 use dialoguer::MultiSelect;
@@ -8,40 +11,109 @@ use dialoguer::MultiSelect;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Build your project
+    #[command(subcommand)]
+    commands: Option<Commands>,
+    /// Path to the config file of the project, ruxgo works in its directory
+    #[arg(long, global = true, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+    /// Number of jobs run in parallel, defaults to RUXGO_JOBS, then to `jobs` of the global config, then to the number of CPUs
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
+    /// Log everything, down to the debug messages
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Only log the warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// When to color the output
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto", value_parser = ["auto", "always", "never"])]
+    color: String,
+    #[command(flatten, next_help_heading = "Deprecated options, use the subcommands instead")]
+    legacy: LegacyArgs,
+}
+
+/// The options of the command line before the subcommands, still accepted
+#[derive(clap::Args, Debug)]
+struct LegacyArgs {
+    /// Build your project, see `build`
     #[arg(short, long)]
     build: bool,
-    /// Clean the obj and bin intermediates
+    /// Clean the obj and bin intermediates, see `clean`
     #[arg(short, long)]
     clean: bool,
-    /// Choose which parts to delete
-    #[arg(conflicts_with("clean"))]
-    choices: Vec<String>,
-    /// Run the executable
+    /// Run the executable, see `run`
     #[arg(short, long)]
     run: bool,
-    /// Initialize a new project. See `init --help` for more info
-    #[command(subcommand)]
-    commands: Option<Commands>,
     /// Arguments to pass to the executable when running
     #[arg(long, num_args(1..), require_equals(true), value_delimiter(','))]
     bin_args: Option<Vec<String>>,
+    /// Update packages, see `update`
+    #[arg(long)]
+    update_packages: bool,
+    /// Restore packages, see `restore`
+    #[arg(long)]
+    restore_packages: bool,
+    #[command(flatten)]
+    build_args: BuildArgs,
+}
+
+/// The options of a build
+#[derive(clap::Args, Debug, Default)]
+struct BuildArgs {
+    /// Build only these targets, and the targets they depend on
+    #[arg(short = 'p', long = "target", value_name = "TARGET", num_args(1..))]
+    targets: Vec<String>,
     /// Generate compile_commands.json
     #[arg(long)]
     gen_cc: bool,
     /// Generate .vscode/c_cpp_properties.json
     #[arg(long)]
     gen_vsc: bool,
-    /// Update packages
+    /// List every step of the build with its command, without running anything
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Tell why each step of the build runs
     #[arg(long)]
-    update_packages: bool,
-    /// Restore packages
-    #[arg(long)]
-    restore_packages: bool,
+    explain: bool,
+    /// Write the timings of the build steps as a Chrome trace, to load in about://tracing or Perfetto
+    #[arg(long, value_name = "FILE")]
+    timings: Option<PathBuf>,
+    /// Keep building the targets that do not depend on a failure, and report every failure at the end
+    #[arg(short, long)]
+    keep_going: bool,
+    /// Format of the compiler diagnostics: a summary at the end of the build, or JSON lines streamed on stdout
+    #[arg(long, default_value = "human", value_parser = clap::builder::PossibleValuesParser::new(diagnostics::MESSAGE_FORMATS))]
+    message_format: String,
 }
 
+/// Parts of the intermediates `clean` can remove
+const CLEAN_PARTS: &[&str] = &["All", "App_libs", "Obj", "OS", "Ulib", "Packages"];
+
 #[derive(Subcommand, Debug)]
 enum Commands {
+    /// Build the targets of the project
+    Build(BuildArgs),
+    /// Build and run an executable target of the project
+    Run {
+        /// The executable target to run, only needed when there are several
+        target: Option<String>,
+        /// Arguments to pass to the executable
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Clean the obj and bin intermediates
+    Clean {
+        /// Clean only the binaries and objects of these targets
+        #[arg(short = 'p', long = "target", value_name = "TARGET", num_args(1..), conflicts_with = "parts")]
+        targets: Vec<String>,
+        /// Parts to clean, chosen interactively if none is given
+        #[arg(value_parser = clap::builder::PossibleValuesParser::new(CLEAN_PARTS))]
+        parts: Vec<String>,
+    },
+    /// Update the packages
+    Update,
+    /// Restore the packages
+    Restore,
     /// Initialize a new project
     /// Defaults to C++ if no language is specified
     Init {
@@ -60,23 +132,96 @@ enum Commands {
         ///     - `default_compiler`: Sets the default compiler to use
         ///     - `default_language`: Sets the default language to use
         ///     - `license`: Sets the license to use. Give the path to the license file
+        ///     - `cache`: Enables or disables the object cache
+        ///     - `cache_dir`: Sets the directory of the object cache
+        ///     - `cache_max_size`: Sets the size limit of the object cache in MiB
+        ///     - `remote_cache`: Sets the url of the remote cache server, empty to disable it
+        ///     - `remote_cache_mode`: Sets whether objects built locally are uploaded
+        ///     - `jobs`: Sets the number of jobs run in parallel, 0 for the number of CPUs
+        ///     - `max_load`: Holds back new jobs above this load average, 0 to disable
+        ///     - `min_free_memory`: Holds back new jobs below this available memory in MiB, 0 to disable
         #[clap(verbatim_doc_comment)]
         parameter: String,
         /// Value to set the parameter to currently supported values:
         ///     - `compiler`: `gcc`, `clang` Uses g++ or clang++ respectively
         ///     - `language`: `c`, `cpp`
         ///     - `license`: `path/to/license/file`
+        ///     - `cache`: `true`, `false`
+        ///     - `cache_dir`: `path/to/cache/dir`
+        ///     - `cache_max_size`: size in MiB, eg: `5120`
+        ///     - `remote_cache`: `http://host:port`
+        ///     - `remote_cache_mode`: `read-only`, `read-write`
+        ///     - `jobs`: number of jobs, eg: `8`
+        ///     - `max_load`: load average, eg: `6.5`
+        ///     - `min_free_memory`: memory in MiB, eg: `2048`
         #[clap(verbatim_doc_comment)]
         value: String,
     },
+    /// Inspect or empty the object cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Serve a directory as a remote cache over HTTP
+    CacheServer {
+        /// Directory holding the cached entries, defaults to the object cache directory
+        #[clap(long)]
+        dir: Option<String>,
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+    },
+    /// Validate the config file without building anything
+    CheckConfig {
+        /// Path to the config file, defaults to --manifest-path, then to the config file of the current directory
+        path: Option<String>,
+        /// Print the JSON Schema of the config file instead
+        #[clap(long, action)]
+        schema: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Show the hit and miss counts and the size of the cache
+    Stats,
+    /// Remove every cached object
+    Clear,
 }
 
 fn main() {
+    if let Err(e) = try_main() {
+        utils::log(utils::LogLevel::Error, &e.to_string());
+        std::process::exit(1);
+    }
+}
+
+/// The configs of the project a command works on
+struct Project {
+    build_config: BuildConfig,
+    os_config: OSConfig,
+    targets: Vec<TargetConfig>,
+    packages: Vec<Package>,
+}
+
+impl Project {
+    /// Parses the config file of the project, and sets up the environment of its os
+    fn load() -> Result<Self> {
+        let (build_config, os_config, targets, packages) = commands::parse_config(commands::CONFIG_FILE)?;
+        // Add environment config
+        utils::config_env(&os_config);
+        Ok(Project { build_config, os_config, targets, packages })
+    }
+}
+
+fn try_main() -> Result<()> {
     // Add global config
-    let project_dirs = ProjectDirs::from("com", "RuxosApps", "ruxos-c").unwrap();
+    let project_dirs = ProjectDirs::from("com", "RuxosApps", "ruxos-c")
+        .ok_or_else(|| RuxgoError::Config("Could not determine the home directory".to_string()))?;
     let config_dir = project_dirs.config_dir();
     if !config_dir.exists() {
-        std::fs::create_dir_all(config_dir).unwrap();
+        std::fs::create_dir_all(config_dir)
+            .map_err(|why| RuxgoError::io("Could not create global config directory", why))?;
     }
     let config = config_dir.join("config.toml");
     if !config.exists() {
@@ -88,117 +233,318 @@ default_language = "cpp"
 license = "NONE"
 "#,
         )
-        .unwrap();
+        .map_err(|why| RuxgoError::io("Could not write global config", why))?;
     }
-    let global_config = GlobalConfig::from_file(&config);
+    let global_config = GlobalConfig::from_file(&config)?;
 
     // Parse args
     let args = Args::parse();
-    if args.commands.is_some() {
-        match args.commands {
-            Some(Commands::Init { name, c, cpp }) => {
-                if c && cpp {
-                    utils::log(
-                        utils::LogLevel::Error,
-                        "Only one of --c or --cpp can be specified",
-                    );
-                    std::process::exit(1);
+    configure_output(&args);
+    let Some(command) = args.commands else {
+        return legacy(&global_config, args.manifest_path.as_deref(), args.jobs, args.legacy);
+    };
+    match command {
+        Commands::Build(build_args) => {
+            enter_project(args.manifest_path.as_deref())?;
+            let project = Project::load()?;
+            build(&global_config, &project, args.jobs, &build_args)?;
+        }
+        Commands::Run { target, args: bin_args } => {
+            enter_project(args.manifest_path.as_deref())?;
+            let project = Project::load()?;
+            let names = target.into_iter().collect::<Vec<String>>();
+            // only the exe to run and what it depends on are built
+            let exe_target = commands::exe_target(&project.targets, &names)?;
+            let build_args = BuildArgs { targets: vec![exe_target.name.clone()], ..Default::default() };
+            build(&global_config, &project, args.jobs, &build_args)?;
+            run(&project, &names, &bin_args)?;
+        }
+        Commands::Clean { targets, parts } => {
+            enter_project(args.manifest_path.as_deref())?;
+            let project = Project::load()?;
+            clean(&project, &targets, parts)?;
+        }
+        Commands::Update => {
+            enter_project(args.manifest_path.as_deref())?;
+            commands::update_packages(&Project::load()?.packages)?;
+        }
+        Commands::Restore => {
+            enter_project(args.manifest_path.as_deref())?;
+            commands::restore_packages(&Project::load()?.packages)?;
+        }
+        Commands::Init { name, c, cpp } => {
+            if c && cpp {
+                return Err(RuxgoError::Config("Only one of --c or --cpp can be specified".to_string()));
+            }
+            if !c && !cpp {
+                commands::init_project(&name, None, &global_config)?;
+            } else {
+                commands::init_project(&name, Some(c), &global_config)?;
+            }
+        }
+        Commands::Config { parameter, value } => {
+            let parameter = parameter.as_str();
+            let value = value.as_str();
+            GlobalConfig::set_defaults(&config, parameter, value)?;
+            utils::log(
+                utils::LogLevel::Log,
+                format!("Setting {} to {}", parameter, value).as_str(),
+            );
+        }
+        Commands::CheckConfig { path, schema } => {
+            if schema {
+                let schema = utils::config_schema()?;
+                // the schema is often piped, a reader closing the pipe early is not an error
+                return match writeln!(std::io::stdout().lock(), "{}", schema) {
+                    Err(why) if why.kind() != ErrorKind::BrokenPipe => {
+                        Err(RuxgoError::io("Couldn't write the schema", why))
+                    }
+                    _ => Ok(()),
+                };
+            }
+            let path = path
+                .or_else(|| args.manifest_path.map(|path| path.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| commands::CONFIG_FILE.to_string());
+            let problems = commands::check_config(&path)?;
+            if !problems.is_empty() {
+                for problem in &problems {
+                    utils::log(utils::LogLevel::Error, problem);
                 }
-                if !c && !cpp {
-                    commands::init_project(&name, None, &global_config);
+                return Err(RuxgoError::Config(format!("{}: {} problem(s) found", path, problems.len())));
+            }
+            utils::log(utils::LogLevel::Log, &format!("{} is valid", path));
+        }
+        Commands::Cache { action } => {
+            let cache = global_config.get_object_cache()?;
+            match action {
+                CacheAction::Stats => {
+                    let stats = cache.stats()?;
+                    let lookups = stats.hits + stats.misses;
+                    let hit_rate = if lookups > 0 { stats.hits as f64 * 100.0 / lookups as f64 } else { 0.0 };
+                    println!("Enabled:  {}", global_config.is_cache_enabled());
+                    println!("Hits:     {}", stats.hits);
+                    println!("Misses:   {}", stats.misses);
+                    println!("Hit rate: {:.1}%", hit_rate);
+                    println!("Objects:  {}", stats.entries);
+                    println!("Size:     {:.1} MiB", stats.size as f64 / (1024.0 * 1024.0));
                 }
-                if c {
-                    commands::init_project(&name, Some(true), &global_config);
-                } else {
-                    commands::init_project(&name, Some(false), &global_config);
+                CacheAction::Clear => {
+                    cache.clear()?;
+                    utils::log(utils::LogLevel::Log, "Object cache cleared");
                 }
             }
-            Some(Commands::Config { parameter, value }) => {
-                let parameter = parameter.as_str();
-                let value = value.as_str();
-                GlobalConfig::set_defaults(&config, parameter, value);
-                utils::log(
-                    utils::LogLevel::Log,
-                    format!("Setting {} to {}", parameter, value).as_str(),
-                );
-                std::process::exit(0);
-            }
-            None => {
-                utils::log(utils::LogLevel::Error, "Rust is broken");
-                std::process::exit(1);
-            }
+        }
+        Commands::CacheServer { dir, addr } => {
+            let dir = match dir {
+                Some(dir) => PathBuf::from(dir),
+                None => global_config.get_object_cache()?.dir().to_path_buf(),
+            };
+            remote_cache::serve(&dir, &addr)?;
         }
     }
+    Ok(())
+}
 
-    let mut gen_cc = false;
-    if args.gen_cc {
-        gen_cc = true;
-        commands::pre_gen_cc();
+/// Applies the global options on the log level and the colors
+fn configure_output(args: &Args) {
+    if args.verbose {
+        utils::set_log_level(utils::LogLevel::Debug);
+    } else if args.quiet {
+        utils::set_log_level(utils::LogLevel::Warn);
     }
-
-    let mut gen_vsc = false;
-    if args.gen_vsc {
-        gen_vsc = true;
-        commands::pre_gen_vsc();
+    match args.color.as_str() {
+        "always" => colored::control::set_override(true),
+        "never" => colored::control::set_override(false),
+        _ if !std::io::stdout().is_terminal() => colored::control::set_override(false),
+        _ => {}
     }
+}
 
-    let (build_config, os_config, targets, packages) = commands::parse_config();
-
-    // Add environment config
-    utils::config_env(&os_config);
-
-    if args.update_packages {
-        commands::update_packages(&packages);
-        std::process::exit(0);
+/// Moves to the directory of the config file given by `--manifest-path`, where ruxgo works
+fn enter_project(manifest_path: Option<&Path>) -> Result<()> {
+    let Some(manifest_path) = manifest_path else {
+        return Ok(());
+    };
+    let config_file = Path::new(commands::CONFIG_FILE).file_name().unwrap_or_default();
+    if manifest_path.file_name() != Some(config_file) {
+        return Err(RuxgoError::Config(format!(
+            "The manifest path must point to a {} file: {}", config_file.to_string_lossy(), manifest_path.display()
+        )));
     }
+    if let Some(dir) = manifest_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::env::set_current_dir(dir)
+            .map_err(|why| RuxgoError::io(format!("Could not enter {}", dir.display()), why))?;
+    }
+    Ok(())
+}
 
-    if args.restore_packages {
-        commands::restore_packages(&packages);
-        std::process::exit(0);
+/// Builds the project, then reports the diagnostics and timings of the build
+fn build(global_config: &GlobalConfig, project: &Project, jobs: Option<usize>, args: &BuildArgs) -> Result<()> {
+    if args.dry_run {
+        explain::enable_dry_run();
+    }
+    if args.explain {
+        explain::enable_explain();
+    }
+    if args.gen_cc {
+        commands::pre_gen_cc()?;
     }
+    if args.gen_vsc {
+        commands::pre_gen_vsc()?;
+    }
+    utils::log(utils::LogLevel::Log, "Building...");
+    if global_config.is_cache_enabled() {
+        cache::enable(global_config.get_object_cache()?);
+    }
+    if let Some(remote) = global_config.get_remote_cache()? {
+        remote_cache::enable(remote);
+    }
+    global_config.configure_jobs(jobs)?;
+    if args.message_format == "json" {
+        diagnostics::enable_json();
+    }
+    let options = commands::BuildOptions {
+        gen_cc: args.gen_cc,
+        gen_vsc: args.gen_vsc,
+        keep_going: args.keep_going,
+        targets: args.targets.clone(),
+    };
+    let result = commands::build(&project.build_config, &project.targets, &project.os_config, &project.packages, &options);
+    diagnostics::summary();
+    timings::summary();
+    if let Some(trace) = &args.timings {
+        timings::write_trace(trace)?;
+    }
+    cache::flush()?;
+    result
+}
 
-    // If clean flag is provided, prompt user for choices
-    if args.clean {
+/// Runs an exe target of the project
+/// # Arguments
+/// * `names` - The names of the targets selected, the exe target among them is run
+/// * `bin_args` - Arguments to pass to the executable
+fn run(project: &Project, names: &[String], bin_args: &[String]) -> Result<()> {
+    utils::log(utils::LogLevel::Log, "Running...");
+    let exe_target = commands::exe_target(&project.targets, names)?;
+    let bin_args = Some(bin_args.iter().map(String::as_str).collect()).filter(|args: &Vec<&str>| !args.is_empty());
+    commands::run(bin_args, &project.build_config, &project.os_config, exe_target, &project.targets, &project.packages)
+}
+
+/// Cleans the given targets, or the given parts of the intermediates, prompting for them if none is given
+fn clean(project: &Project, targets: &[String], mut parts: Vec<String>) -> Result<()> {
+    if !targets.is_empty() {
+        utils::log(utils::LogLevel::Log, "Cleaning...");
+        return commands::clean_targets(&project.targets, &project.packages, targets);
+    }
+    if parts.is_empty() {
         let mut items = vec!["All", "App_libs", "Obj"];
-        if os_config != OSConfig::default() {
+        if project.os_config != OSConfig::default() {
             items.push("OS");
-            if !os_config.ulib.is_empty() {
+            if !project.os_config.ulib.is_empty() {
                 items.push("Ulib");
             }
         }
-        if !packages.is_empty() {
+        if !project.packages.is_empty() {
             items.push("Packages");
         }
         let defaults = vec![false; items.len()];
-        let choices = MultiSelect::new()
+        parts = MultiSelect::new()
             .with_prompt("What parts do you want to clean?")
             .items(&items)
             .defaults(&defaults)
             .interact_opt()
-            .unwrap_or_else(|_| None)
-            .unwrap_or_else(|| Vec::new())
+            .unwrap_or(None)
+            .unwrap_or_default()
             .iter()
             .map(|&index| String::from(items[index]))
             .collect();
+    }
+    utils::log(utils::LogLevel::Log, "Cleaning...");
+    commands::clean(&project.targets, &project.os_config, &project.packages, parts)
+}
 
-        utils::log(utils::LogLevel::Log, "Cleaning...");
-        commands::clean(&targets, &os_config, &packages, choices);
+/// Runs the deprecated options given before the subcommands, in the order they always ran in
+fn legacy(global_config: &GlobalConfig, manifest_path: Option<&Path>, jobs: Option<usize>, args: LegacyArgs) -> Result<()> {
+    let used = [
+        (args.update_packages, "--update-packages", "ruxgo update"),
+        (args.restore_packages, "--restore-packages", "ruxgo restore"),
+        (args.clean, "-c/--clean", "ruxgo clean"),
+        (args.build, "-b/--build", "ruxgo build"),
+        (args.run, "-r/--run", "ruxgo run"),
+    ];
+    if used.iter().all(|&(used, _, _)| !used) {
+        Args::command()
+            .print_help()
+            .map_err(|why| RuxgoError::io("Could not print help", why))?;
+        return Ok(());
+    }
+    for (_, option, command) in used.iter().filter(|&&(used, _, _)| used) {
+        utils::log(utils::LogLevel::Warn, &format!("{} is deprecated, use `{}` instead", option, command));
     }
 
+    enter_project(manifest_path)?;
+    let project = Project::load()?;
+    if args.update_packages {
+        return commands::update_packages(&project.packages);
+    }
+    if args.restore_packages {
+        return commands::restore_packages(&project.packages);
+    }
+    if args.clean {
+        clean(&project, &args.build_args.targets, Vec::new())?;
+    }
     if args.build {
-        utils::log(utils::LogLevel::Log, "Building...");
-        commands::build(&build_config, &targets, &os_config, gen_cc, gen_vsc, &packages);
+        build(global_config, &project, jobs, &args.build_args)?;
     }
-
     if args.run {
-        let bin_args: Option<Vec<&str>> = args
-            .bin_args
-            .as_ref()
-            .map(|x| x.iter().map(|x| x.as_str()).collect());
-
-        utils::log(utils::LogLevel::Log, "Running...");
-        let exe_target = targets.iter().find(|x| x.typ == "exe").unwrap();
-        commands::run(bin_args, &build_config, &os_config, exe_target, &targets, &packages);
+        run(&project, &args.build_args.targets, &args.bin_args.unwrap_or_default())?;
     }
+    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_is_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn subcommands_take_their_options_and_the_global_ones() {
+        let args = Args::try_parse_from(["ruxgo", "build", "-p", "main", "tool", "-j", "4", "--explain"]).unwrap();
+        assert_eq!(args.jobs, Some(4));
+        match args.commands {
+            Some(Commands::Build(build)) => {
+                assert_eq!(build.targets, ["main", "tool"]);
+                assert!(build.explain && !build.dry_run);
+            }
+            commands => panic!("expected build, got {:?}", commands),
+        }
+
+        let args = Args::try_parse_from(["ruxgo", "-q", "run", "tool", "--", "-x", "y"]).unwrap();
+        assert!(args.quiet);
+        match args.commands {
+            Some(Commands::Run { target, args }) => {
+                assert_eq!(target.as_deref(), Some("tool"));
+                assert_eq!(args, ["-x", "y"]);
+            }
+            commands => panic!("expected run, got {:?}", commands),
+        }
+
+        let args = Args::try_parse_from(["ruxgo", "-b", "--bin-args=a,b", "-p", "main"]).unwrap();
+        assert!(args.commands.is_none() && args.legacy.build && !args.legacy.run);
+        assert_eq!(args.legacy.bin_args.unwrap(), ["a", "b"]);
+        assert_eq!(args.legacy.build_args.targets, ["main"]);
+
+        for wrong in [
+            &["ruxgo", "clean", "-p", "main", "--", "Obj"][..],
+            &["ruxgo", "clean", "Everything"],
+            &["ruxgo", "build", "--color", "sometimes"],
+            &["ruxgo", "-v", "-q"],
+        ] {
+            assert!(Args::try_parse_from(wrong).is_err(), "{:?}", wrong);
+        }
+    }
+}
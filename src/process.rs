@@ -0,0 +1,235 @@
+//! This module contains the command model used to run the tools of the toolchain.
+//!
+//! A command is a program and a list of arguments, run directly without a shell: paths holding
+//! spaces or quotes reach the tool as they are. Flag strings of the config are split shell-style
+//! once, and commands are logged quoted, so that they can be pasted into a shell.
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Mutex, OnceLock};
+use crate::error::{Result, RuxgoError};
+
+/// Represents a command run without a shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd {
+    program: OsString,
+    args: Vec<OsString>,
+    /// The directory the command runs in, the current one if None
+    dir: Option<PathBuf>,
+}
+
+impl Cmd {
+    /// Creates a command running a program without arguments
+    /// # Arguments
+    /// * `program` - The program, looked up in PATH if it is not a path
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Cmd {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            dir: None,
+        }
+    }
+
+    /// Creates a command from a string holding the program and its arguments, eg: `ar rcs`
+    /// # Arguments
+    /// * `cmd` - The command, split shell-style
+    pub fn parse(cmd: &str) -> Result<Self> {
+        let mut words = split_flags(cmd)?.into_iter();
+        let program = words
+            .next()
+            .ok_or_else(|| RuxgoError::Config(format!("Expected a command, found: \"{}\"", cmd)))?;
+        let mut parsed = Cmd::new(program);
+        parsed.args(words);
+        Ok(parsed)
+    }
+
+    /// Appends an argument
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    /// Appends several arguments
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self
+    }
+
+    /// Appends the arguments of a flag string of the config, split shell-style
+    /// # Arguments
+    /// * `flags` - The flags, eg: `-Wall -DNAME="a b"`
+    pub fn flags(&mut self, flags: &str) -> Result<&mut Self> {
+        let flags = split_flags(flags)?;
+        Ok(self.args(flags))
+    }
+
+    /// Sets the directory the command runs in
+    pub fn current_dir(&mut self, dir: impl AsRef<Path>) -> &mut Self {
+        self.dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Returns the program of the command
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+
+    /// Returns the arguments of the command
+    pub fn get_args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// Returns the process builder of the command, to adjust before spawning it
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// Runs the command to completion, capturing its stdout and stderr
+    pub fn output(&self) -> io::Result<Output> {
+        self.to_command().output()
+    }
+
+    /// Runs the command to completion, its stdin, stdout and stderr are those of ruxgo
+    pub fn status(&self) -> io::Result<ExitStatus> {
+        self.to_command()
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+    }
+}
+
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(dir) = &self.dir {
+            write!(f, "cd {} && ", quote(dir.as_os_str()))?;
+        }
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns an argument quoted for a POSIX shell, as is if it needs no quoting
+pub fn quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        return arg.into_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Splits a flag string of the config into arguments, shell-style
+///
+/// Quotes and backslashes are handled like a POSIX shell does. A command between backticks is
+/// run, and its output split on whitespace, eg: `pkg-config --cflags gtk+-3.0`.
+/// A flag string is only split once per invocation, its subcommands are only run once
+/// # Arguments
+/// * `flags` - The flag string
+pub fn split_flags(flags: &str) -> Result<Vec<String>> {
+    static SPLIT: OnceLock<Mutex<HashMap<String, Vec<String>>>> = OnceLock::new();
+    let split = SPLIT.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(args) = split.lock().unwrap().get(flags) {
+        return Ok(args.clone());
+    }
+    let parts = flags.split('`').collect::<Vec<&str>>();
+    if parts.len() % 2 == 0 {
+        return Err(RuxgoError::Config(format!("Unmatched backtick in flags: {}", flags)));
+    }
+    let mut args = Vec::new();
+    // even parts are plain flags, odd parts are subcommands
+    for (i, part) in parts.iter().enumerate() {
+        let words = shlex::split(part)
+            .ok_or_else(|| RuxgoError::Config(format!("Unmatched quote in flags: {}", flags)))?;
+        if i % 2 == 0 {
+            args.extend(words);
+            continue;
+        }
+        let Some((program, subcmd_args)) = words.split_first() else {
+            continue;
+        };
+        let mut subcmd = Cmd::new(program);
+        subcmd.args(subcmd_args);
+        let output = subcmd
+            .output()
+            .map_err(|why| RuxgoError::io(format!("Failed to execute subcmd: {}", subcmd), why))?;
+        if !output.status.success() {
+            return Err(RuxgoError::Toolchain(format!(
+                "Failed to execute subcmd: {}\n  Stderr: {}",
+                subcmd, String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+        args.extend(String::from_utf8_lossy(&output.stdout).split_whitespace().map(str::to_string));
+    }
+    split.lock().unwrap().insert(flags.to_string(), args.clone());
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_error(result: Result<Vec<String>>) -> String {
+        match result {
+            Err(RuxgoError::Config(msg)) => msg,
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flags_are_split_like_a_shell_does() {
+        assert_eq!(split_flags("  -O2\t-Wall  ").unwrap(), ["-O2", "-Wall"]);
+        assert_eq!(split_flags("-DNAME=\"a b\" -I'my inc' a\\ b").unwrap(), ["-DNAME=a b", "-Imy inc", "a b"]);
+        assert_eq!(split_flags("-DX='it'\\''s'").unwrap(), ["-DX=it's"]);
+        assert!(split_flags("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn backtick_subcommands_are_run_and_their_output_split() {
+        assert_eq!(split_flags("`echo -I/usr/inc  -lfoo` -O2").unwrap(), ["-I/usr/inc", "-lfoo", "-O2"]);
+        // the output is split on whitespace only, it is not parsed as flags again
+        assert_eq!(split_flags("`printf \"'a b'\"`").unwrap(), ["'a", "b'"]);
+        assert!(split_flags("`true` ``").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unbalanced_flags_are_config_errors() {
+        assert_eq!(config_error(split_flags("-O2 `echo")), "Unmatched backtick in flags: -O2 `echo");
+        assert_eq!(config_error(split_flags("-DX=\"a")), "Unmatched quote in flags: -DX=\"a");
+        assert_eq!(config_error(split_flags("`echo 'a`")), "Unmatched quote in flags: `echo 'a`");
+        assert!(matches!(Cmd::parse(" "), Err(RuxgoError::Config(_))));
+    }
+
+    #[test]
+    fn failing_subcommand_is_a_toolchain_error() {
+        match split_flags("-O2 `false`") {
+            Err(RuxgoError::Toolchain(msg)) => assert!(msg.starts_with("Failed to execute subcmd: false"), "{}", msg),
+            other => panic!("expected a toolchain error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn displayed_command_pastes_back_into_a_shell() {
+        let mut cmd = Cmd::parse("gcc -O2").unwrap();
+        cmd.arg("-DMSG=it's $HOME").arg("my src/main.c").arg("").current_dir("/tmp/my project");
+        let displayed = cmd.to_string();
+        assert_eq!(displayed, "cd '/tmp/my project' && gcc -O2 '-DMSG=it'\\''s $HOME' 'my src/main.c' ''");
+        let words = split_flags(displayed.split_once(" && ").unwrap().1).unwrap();
+        assert_eq!(words, ["gcc", "-O2", "-DMSG=it's $HOME", "my src/main.c", ""]);
+    }
+}
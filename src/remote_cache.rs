@@ -0,0 +1,293 @@
+//! This module contains the remote build cache, shared by a team over HTTP.
+//!
+//! The protocol is content addressed: `GET /<key>/<file>` answers the file or 404,
+//! and `PUT /<key>/<file>` stores it. The files of an object are `o`, `d` and `stderr`,
+//! a linked library is `bin`. `ruxgo cache-server` serves a directory over this protocol.
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tiny_http::{Method, Response, Server};
+use crate::utils::{log, LogLevel};
+use crate::error::{Result, RuxgoError};
+
+static REMOTE_CACHE: OnceLock<RemoteCache> = OnceLock::new();
+/// Modes of the remote cache accepted in the global config
+pub const REMOTE_CACHE_MODES: &[&str] = &["read-only", "read-write"];
+
+/// Represents a remote cache server
+pub struct RemoteCache {
+    url: String,
+    /// Whether the objects built locally are uploaded
+    writable: bool,
+    /// Set once the server can't be reached, the rest of the build goes without it
+    unreachable: AtomicBool,
+    agent: ureq::Agent,
+}
+
+/// Enables the remote cache for this invocation
+pub fn enable(remote: RemoteCache) {
+    let _ = REMOTE_CACHE.set(remote);
+}
+
+/// Returns the remote cache if it is enabled
+pub fn get() -> Option<&'static RemoteCache> {
+    REMOTE_CACHE.get()
+}
+
+impl RemoteCache {
+    /// Creates a client of a remote cache
+    /// # Arguments
+    /// * `url` - The base url of the server, eg: `http://cache.local:8080`
+    /// * `writable` - Whether objects built locally are uploaded
+    pub fn new(url: &str, writable: bool) -> Self {
+        RemoteCache {
+            url: url.trim_end_matches('/').to_string(),
+            writable,
+            unreachable: AtomicBool::new(false),
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(2))
+                .timeout(Duration::from_secs(30))
+                .build(),
+        }
+    }
+
+    /// Downloads a file of an entry, None if the server does not have it or can't be reached
+    /// # Arguments
+    /// * `key` - The key of the entry
+    /// * `file` - The name of the file in the entry
+    pub fn download(&self, key: &str, file: &str) -> Option<Vec<u8>> {
+        if self.unreachable.load(Ordering::Relaxed) {
+            return None;
+        }
+        let url = format!("{}/{}/{}", self.url, key, file);
+        match self.agent.get(&url).call() {
+            Ok(response) => {
+                let mut data = Vec::new();
+                match response.into_reader().read_to_end(&mut data) {
+                    Ok(_) => Some(data),
+                    Err(why) => {
+                        log(LogLevel::Warn, &format!("Failed to download {}: {}", url, why));
+                        None
+                    }
+                }
+            }
+            Err(ureq::Error::Status(404, _)) => None,
+            Err(why) => {
+                self.disable(why);
+                None
+            }
+        }
+    }
+
+    /// Uploads a file of an entry, does nothing if the cache is read-only
+    /// # Arguments
+    /// * `key` - The key of the entry
+    /// * `file` - The name of the file in the entry
+    /// * `data` - The content of the file
+    pub fn upload(&self, key: &str, file: &str, data: &[u8]) {
+        if !self.writable || self.unreachable.load(Ordering::Relaxed) {
+            return;
+        }
+        let url = format!("{}/{}/{}", self.url, key, file);
+        match self.agent.put(&url).send_bytes(data) {
+            Ok(_) => {}
+            Err(ureq::Error::Status(status, _)) => {
+                log(LogLevel::Warn, &format!("Failed to upload {}: status {}", url, status));
+            }
+            Err(why) => self.disable(why),
+        }
+    }
+
+    /// Stops using the server after a transport error, warning once
+    fn disable(&self, why: ureq::Error) {
+        if !self.unreachable.swap(true, Ordering::Relaxed) {
+            log(LogLevel::Warn, &format!("Remote cache {} unavailable, building without it: {}", self.url, why));
+        }
+    }
+
+    /// Downloads an object and its depfile into the build tree, returns the stderr of the
+    /// compiler or None on a miss
+    /// # Arguments
+    /// * `key` - The key of the object
+    /// * `obj_path` - Where the object is expected
+    /// * `dep_path` - Where the depfile of the object is expected
+    pub fn fetch(&self, key: &str, obj_path: &str, dep_path: &str) -> Result<Option<String>> {
+        let Some(obj) = self.download(key, "o") else {
+            return Ok(None);
+        };
+        let Some(dep) = self.download(key, "d") else {
+            return Ok(None);
+        };
+        let stderr = self.download(key, "stderr").unwrap_or_default();
+        fs::write(obj_path, obj)
+            .map_err(|why| RuxgoError::io(format!("Failed to write file: {}", obj_path), why))?;
+        fs::write(dep_path, dep)
+            .map_err(|why| RuxgoError::io(format!("Failed to write file: {}", dep_path), why))?;
+        Ok(Some(String::from_utf8_lossy(&stderr).to_string()))
+    }
+
+    /// Uploads a freshly compiled object and its depfile, does nothing if the cache is read-only
+    /// # Arguments
+    /// * `key` - The key of the object
+    /// * `obj_path` - The path of the object
+    /// * `dep_path` - The path of the depfile of the object
+    /// * `stderr` - What the compiler printed on stderr
+    pub fn store(&self, key: &str, obj_path: &str, dep_path: &str, stderr: &str) {
+        if !self.writable {
+            return;
+        }
+        let (Ok(obj), Ok(dep)) = (fs::read(obj_path), fs::read(dep_path)) else {
+            return;
+        };
+        if !stderr.is_empty() {
+            self.upload(key, "stderr", stderr.as_bytes());
+        }
+        self.upload(key, "d", &dep);
+        // the object is uploaded last, an entry is complete once it exists
+        self.upload(key, "o", &obj);
+    }
+}
+
+/// Serves a directory over the remote cache protocol, until the process is killed
+/// # Arguments
+/// * `dir` - The directory holding the entries, laid out like the local object cache
+/// * `addr` - The address to listen on, eg: `0.0.0.0:8080`
+pub fn serve(dir: &Path, addr: &str) -> Result<()> {
+    fs::create_dir_all(dir)
+        .map_err(|why| RuxgoError::io(format!("Failed to create {}", dir.display()), why))?;
+    let server = Server::http(addr)
+        .map_err(|why| RuxgoError::Config(format!("Failed to listen on {}: {}", addr, why)))?;
+    log(LogLevel::Log, &format!("Serving {} on http://{}", dir.display(), addr));
+    for mut request in server.incoming_requests() {
+        let dir = dir.to_path_buf();
+        std::thread::spawn(move || {
+            let path = entry_path(&dir, request.url());
+            let response = match (request.method(), path) {
+                (_, None) => Response::from_string("Bad entry").with_status_code(400),
+                (Method::Get, Some(path)) => match fs::read(&path) {
+                    Ok(data) => Response::from_data(data),
+                    Err(_) => Response::from_string("Not found").with_status_code(404),
+                },
+                (Method::Put, Some(path)) => {
+                    let mut data = Vec::new();
+                    match request.as_reader().read_to_end(&mut data) {
+                        Ok(_) => match write_entry(&path, &data) {
+                            Ok(_) => Response::from_string("Stored").with_status_code(201),
+                            Err(why) => {
+                                log(LogLevel::Warn, &format!("Failed to store {}: {}", path.display(), why));
+                                Response::from_string("Failed to store").with_status_code(500)
+                            }
+                        },
+                        Err(_) => Response::from_string("Bad body").with_status_code(400),
+                    }
+                }
+                _ => Response::from_string("Method not allowed").with_status_code(405),
+            };
+            log(LogLevel::Info, &format!("{} {} {}", request.method(), request.url(), response.status_code().0));
+            let _ = request.respond(response);
+        });
+    }
+    Ok(())
+}
+
+/// Returns the path of the file of an entry from a request url, None if the url is not `/<key>/<file>`
+fn entry_path(dir: &Path, url: &str) -> Option<PathBuf> {
+    let (key, file) = url.trim_start_matches('/').split_once('/')?;
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric());
+    if key.len() < 2 || !valid(key) || !valid(file) {
+        return None;
+    }
+    Some(dir.join(&key[..2]).join(format!("{}.{}", key, file)))
+}
+
+/// Writes the file of an entry through a temp file, so that readers never see it half written
+fn write_entry(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    static TMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+    let tmp_path = path.with_extension(format!("tmp{}", TMP_COUNT.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&tmp_path, data).and_then(|_| fs::rename(&tmp_path, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ruxgo-{}-remote-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns an address nothing listens on
+    fn free_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    /// Serves a directory in the background and returns its url
+    fn server(dir: &Path) -> String {
+        let addr = free_addr();
+        let (dir, serve_addr) = (dir.to_path_buf(), addr.clone());
+        std::thread::spawn(move || serve(&dir, &serve_addr));
+        // waits for the server to listen
+        let url = format!("http://{}", addr);
+        for _ in 0..100 {
+            if std::net::TcpStream::connect(&addr).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        url
+    }
+
+    #[test]
+    fn entry_path_rejects_what_is_not_a_key_and_a_file() {
+        let dir = Path::new("/srv/cache");
+        assert_eq!(entry_path(dir, "/ab12/o"), Some(dir.join("ab").join("ab12.o")));
+        assert_eq!(entry_path(dir, "/ab12/stderr"), Some(dir.join("ab").join("ab12.stderr")));
+        for url in ["/../../etc/passwd", "/ab12/../o", "/ab12/o/d", "/ab.12/o", "/a/o", "/ab12/", "//o", "/ab12"] {
+            assert_eq!(entry_path(dir, url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn stored_object_is_fetched_from_the_server() {
+        let root = temp_dir("round-trip");
+        let url = server(&root.join("server"));
+        let (obj_path, dep_path) = (root.join("main.o"), root.join("main.d"));
+        let (obj_path, dep_path) = (obj_path.to_str().unwrap(), dep_path.to_str().unwrap());
+        fs::write(obj_path, "object").unwrap();
+        fs::write(dep_path, "main.o: main.c").unwrap();
+
+        let remote = RemoteCache::new(&format!("{}/", url), true);
+        assert_eq!(remote.fetch("ab12", obj_path, dep_path).unwrap(), None);
+        remote.store("ab12", obj_path, dep_path, "warning: unused");
+        fs::remove_file(obj_path).unwrap();
+        fs::remove_file(dep_path).unwrap();
+        assert_eq!(remote.fetch("ab12", obj_path, dep_path).unwrap().as_deref(), Some("warning: unused"));
+        assert_eq!(fs::read_to_string(obj_path).unwrap(), "object");
+        assert_eq!(fs::read_to_string(dep_path).unwrap(), "main.o: main.c");
+
+        // a read-only client does not upload
+        RemoteCache::new(&url, false).store("cd34", obj_path, dep_path, "");
+        assert_eq!(remote.download("cd34", "o"), None);
+    }
+
+    #[test]
+    fn unreachable_server_is_a_miss_and_is_not_asked_again() {
+        let root = temp_dir("unreachable");
+        let (obj_path, dep_path) = (root.join("main.o"), root.join("main.d"));
+        let remote = RemoteCache::new(&format!("http://{}", free_addr()), true);
+        let fetched = remote.fetch("ab12", obj_path.to_str().unwrap(), dep_path.to_str().unwrap());
+        assert_eq!(fetched.unwrap(), None);
+        assert!(remote.unreachable.load(Ordering::Relaxed));
+        assert!(!obj_path.exists());
+    }
+}
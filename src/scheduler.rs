@@ -0,0 +1,302 @@
+//! This module contains the scheduler building the targets of a build graph in parallel
+
+use crate::builder::{Plan, Target, compile_progress_bar};
+use crate::build_state;
+use crate::explain;
+use crate::commands::BuildOptions;
+use crate::graph::{BuildGraph, BuildNode};
+use crate::utils::{log, LogLevel, OSConfig};
+use crate::error::{Result, RuxgoError};
+use indicatif::ProgressBar;
+use rayon::Scope;
+use rayon::prelude::*;
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Builds every target of the graph through one shared job pool
+///
+/// The source files of all targets are compiled as soon as the pool has room for them,
+/// and each target is linked as soon as its own objects and its dependencies are done.
+/// In keep-going mode, a failure only stops the targets depending on the failed one.
+/// # Arguments
+/// * `graph` - The build graph of the targets
+/// * `os_config` - The local os configuration
+/// * `options` - The options of the build
+/// * `failures` - The failures of the os and ulib builds in keep-going mode, the exe targets linked with them are skipped
+pub fn build_graph(
+    graph: &BuildGraph,
+    os_config: &OSConfig,
+    options: &BuildOptions,
+    failures: Vec<RuxgoError>,
+) -> Result<()> {
+    let nodes = graph.nodes();
+    let mut targets = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        targets.push(Target::new(node.build_config, os_config, node.target_config, node.targets, node.packages)?);
+    }
+
+    // the targets are prepared in parallel, a level of the graph once the levels it depends on are done
+    let mut levels = vec![0; nodes.len()];
+    for (i, node) in graph.ordered() {
+        levels[i] = node.deps.iter().map(|&dep| levels[dep] + 1).max().unwrap_or(0);
+    }
+    let mut plans = vec![None; nodes.len()];
+    for level in 0..=levels.iter().copied().max().unwrap_or(0) {
+        let level = graph.ordered().filter(|&(i, _)| levels[i] == level).collect::<Vec<_>>();
+        let prepare = |&(i, node): &(usize, &BuildNode)| {
+            // a dependency linked again may change what this target links
+            let linked_dep = node.deps.iter().find(|&&dep| plans[dep].is_some()).map(|&dep| targets[dep].bin_path.as_str());
+            targets[i].prepare(options.gen_cc, linked_dep).map(|plan| (i, plan))
+        };
+        // a dry run lists the steps in order
+        let prepared = if explain::is_dry_run() {
+            level.iter().map(prepare).collect::<Result<Vec<_>>>()?
+        } else {
+            level.par_iter().map(prepare).collect::<Result<Vec<_>>>()?
+        };
+        for (i, plan) in prepared {
+            plans[i] = plan;
+        }
+    }
+    if explain::is_dry_run() {
+        return Ok(());
+    }
+    let os_failed = !failures.is_empty() && os_config != &OSConfig::default();
+
+    let mut dependents = vec![Vec::new(); nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        for &dep in &node.deps {
+            dependents[dep].push(i);
+        }
+    }
+    let pending = nodes
+        .iter()
+        .zip(&plans)
+        .map(|(node, plan)| {
+            let jobs = plan.as_ref().map_or(0, |plan| plan.srcs.len() + usize::from(plan.pch));
+            AtomicUsize::new(node.deps.len() + jobs)
+        })
+        .collect();
+    let total_srcs = plans.iter().flatten().map(|plan| plan.srcs.len()).sum();
+    let progress_bar = if total_srcs > 0 { compile_progress_bar(total_srcs) } else { ProgressBar::hidden() };
+
+    let scheduler = Scheduler {
+        targets: targets.into_iter().map(RwLock::new).collect(),
+        built: plans.iter().map(|_| Mutex::new(Vec::new())).collect(),
+        failed_targets: plans.iter().map(|_| AtomicBool::new(false)).collect(),
+        broken: nodes.iter().map(|node| AtomicBool::new(os_failed && node.target_config.typ == "exe")).collect(),
+        plans,
+        dependents,
+        pending,
+        progress_bar,
+        keep_going: options.keep_going,
+        failed: AtomicBool::new(false),
+        errors: Mutex::new(failures),
+        skipped: Mutex::new(Vec::new()),
+    };
+    let result = scheduler.run();
+    // keeps the stamps of the files rehashed by up to date targets
+    build_state::lock().save()?;
+    result
+}
+
+/// Shared state of the jobs building a graph
+struct Scheduler<'a> {
+    targets: Vec<RwLock<Target<'a>>>,
+    /// What each target has to build, None if the target is up to date
+    plans: Vec<Option<Plan>>,
+    /// Indices of the targets depending on each target
+    dependents: Vec<Vec<usize>>,
+    /// Number of source files and dependencies each target is still waiting for
+    pending: Vec<AtomicUsize>,
+    progress_bar: ProgressBar,
+    keep_going: bool,
+    /// Indices of the source files each target compiled and did not record yet
+    built: Vec<Mutex<Vec<usize>>>,
+    /// Whether each target failed itself, in keep-going mode
+    failed_targets: Vec<AtomicBool>,
+    /// Whether each target failed or depends on a failed one, in keep-going mode
+    broken: Vec<AtomicBool>,
+    /// Set once a job fails and the build stops
+    failed: AtomicBool,
+    errors: Mutex<Vec<RuxgoError>>,
+    /// Names of the targets not linked because they depend on a failed one
+    skipped: Mutex<Vec<String>>,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Runs the jobs until every target is built, or one of them fails outside of keep-going mode
+    fn run(self) -> Result<()> {
+        // collect the ready targets first, as jobs may make other targets ready while spawning
+        let ready = (0..self.targets.len())
+            .filter(|&i| self.pending[i].load(Ordering::Acquire) == 0)
+            .collect::<Vec<usize>>();
+        let scheduler = &self;
+        rayon::scope(|s| {
+            for i in ready {
+                s.spawn(move |s| scheduler.link(s, i));
+            }
+            for (i, plan) in scheduler.plans.iter().enumerate() {
+                match plan {
+                    // the source files are compiled once the header is
+                    Some(plan) if plan.pch => s.spawn(move |s| scheduler.compile_pch(s, i)),
+                    Some(plan) => {
+                        for &src in &plan.srcs {
+                            s.spawn(move |s| scheduler.compile(s, i, src));
+                        }
+                    }
+                    None => {}
+                }
+            }
+        });
+        self.progress_bar.finish();
+        if self.failed.load(Ordering::Acquire) {
+            // keeps the objects compiled before the failure, they are not compiled again next time
+            for (target, built) in self.targets.iter().zip(&self.built) {
+                let built = std::mem::take(&mut *built.lock().unwrap());
+                if built.is_empty() {
+                    continue;
+                }
+                if let Err(e) = target.write().unwrap().record_objects(&built) {
+                    log(LogLevel::Warn, &e.to_string());
+                }
+            }
+        }
+        let mut errors = self.errors.into_inner().unwrap();
+        let skipped = self.skipped.into_inner().unwrap();
+        if !self.keep_going || (errors.len() == 1 && skipped.is_empty()) {
+            return errors.pop().map_or(Ok(()), Err);
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(RuxgoError::Build { failures: errors, skipped })
+    }
+
+    /// Compiles the precompiled header of a target, then queues its source files
+    fn compile_pch<'s>(&'s self, s: &Scope<'s>, target: usize) {
+        if self.failed.load(Ordering::Acquire) {
+            return;
+        }
+        let srcs = self.plans[target].as_ref().map_or(&[][..], |plan| &plan.srcs);
+        let result = if self.broken[target].load(Ordering::Acquire) {
+            // the source files skip themselves
+            Ok(())
+        } else {
+            self.targets[target].read().unwrap().compile_pch()
+        };
+        match result {
+            Ok(()) => {
+                for &src in srcs {
+                    s.spawn(move |s| self.compile(s, target, src));
+                }
+            }
+            Err(e) if self.keep_going => {
+                self.failed_targets[target].store(true, Ordering::Release);
+                self.broken[target].store(true, Ordering::Release);
+                self.errors.lock().unwrap().push(e);
+                // the source files include the header, they are not compiled without it
+                for _ in srcs {
+                    self.done(s, target);
+                }
+            }
+            Err(e) => {
+                self.fail(e);
+                return;
+            }
+        }
+        self.done(s, target);
+    }
+
+    /// Compiles a source file of a target
+    fn compile<'s>(&'s self, s: &Scope<'s>, target: usize, src: usize) {
+        if self.failed.load(Ordering::Acquire) {
+            return;
+        }
+        // a target depending on a failed one won't be linked, its sources are not worth compiling
+        if self.broken[target].load(Ordering::Acquire) && !self.failed_targets[target].load(Ordering::Acquire) {
+            self.done(s, target);
+            return;
+        }
+        let result = self.targets[target].read().unwrap().compile(src, &self.progress_bar);
+        match result {
+            Ok(()) => {
+                self.built[target].lock().unwrap().push(src);
+                self.done(s, target);
+            }
+            Err(e) if self.keep_going => {
+                self.failed_targets[target].store(true, Ordering::Release);
+                self.broken[target].store(true, Ordering::Release);
+                self.errors.lock().unwrap().push(e);
+                self.done(s, target);
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+
+    /// Links a target whose objects and dependencies are done, then releases its dependents
+    fn link<'s>(&'s self, s: &Scope<'s>, target: usize) {
+        if self.failed.load(Ordering::Acquire) {
+            return;
+        }
+        if self.broken[target].load(Ordering::Acquire) {
+            self.skip(s, target);
+            return;
+        }
+        if let Some(Plan { srcs: compiled, .. }) = &self.plans[target] {
+            // finish records the objects before linking them
+            self.built[target].lock().unwrap().clear();
+            if let Err(e) = self.targets[target].write().unwrap().finish(compiled) {
+                if !self.keep_going {
+                    self.fail(e);
+                    return;
+                }
+                self.errors.lock().unwrap().push(e);
+                self.failed_targets[target].store(true, Ordering::Release);
+                self.broken[target].store(true, Ordering::Release);
+                self.release_dependents(s, target);
+                return;
+            }
+        }
+        self.release_dependents(s, target);
+    }
+
+    /// Gives up linking a broken target, keeping the objects that did compile
+    fn skip<'s>(&'s self, s: &Scope<'s>, target: usize) {
+        if !self.failed_targets[target].load(Ordering::Acquire) {
+            self.skipped.lock().unwrap().push(self.targets[target].read().unwrap().name().to_string());
+        }
+        let built = std::mem::take(&mut *self.built[target].lock().unwrap());
+        if let Err(e) = self.targets[target].write().unwrap().record_objects(&built) {
+            self.errors.lock().unwrap().push(e);
+        }
+        self.release_dependents(s, target);
+    }
+
+    /// Releases the targets depending on a target, they are broken if it is
+    fn release_dependents<'s>(&'s self, s: &Scope<'s>, target: usize) {
+        let broken = self.broken[target].load(Ordering::Acquire);
+        for &dependent in &self.dependents[target] {
+            if broken {
+                self.broken[dependent].store(true, Ordering::Release);
+            }
+            self.done(s, dependent);
+        }
+    }
+
+    /// Marks one source file or dependency of a target as done, and links it if it was the last one
+    fn done<'s>(&'s self, s: &Scope<'s>, target: usize) {
+        if self.pending[target].fetch_sub(1, Ordering::AcqRel) == 1 {
+            s.spawn(move |s| self.link(s, target));
+        }
+    }
+
+    /// Records the first error, the jobs that did not start yet are skipped
+    fn fail(&self, e: RuxgoError) {
+        self.failed.store(true, Ordering::Release);
+        let mut errors = self.errors.lock().unwrap();
+        if errors.is_empty() {
+            errors.push(e);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! This module contains the timings of the steps of a build.
+//!
+//! Each compile, link, objcopy, cargo build of the OS and musl configure/make is timed while it runs.
+//! The slowest steps are listed once the build ends, and every step can be written as a Chrome trace,
+//! to load in about://tracing or Perfetto.
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use serde_json::json;
+use crate::utils::{log, LogLevel};
+use crate::error::{Result, RuxgoError};
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+/// Number of steps listed in the summary
+const SLOWEST: usize = 10;
+
+thread_local! {
+    /// The id of the thread in the trace, 0 until the thread times its first step
+    static THREAD: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A step of the build that ran
+struct Event {
+    /// What the step does, eg: "compile"
+    category: &'static str,
+    /// What the step works on, eg: the path of a source file
+    name: String,
+    /// When the step started, since the first step of the build
+    start: Duration,
+    duration: Duration,
+    thread: usize,
+}
+
+/// A running step, it is recorded with its duration when dropped
+pub struct Span {
+    category: &'static str,
+    name: String,
+    start: Instant,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let epoch = *EPOCH.get_or_init(Instant::now);
+        let thread = THREAD.with(|thread| {
+            if thread.get() == 0 {
+                thread.set(THREADS.fetch_add(1, Ordering::Relaxed) + 1);
+            }
+            thread.get()
+        });
+        EVENTS.lock().unwrap().push(Event {
+            category: self.category,
+            name: std::mem::take(&mut self.name),
+            start: self.start.saturating_duration_since(epoch),
+            duration: self.start.elapsed(),
+            thread,
+        });
+    }
+}
+
+/// Starts timing a step of the build, until the returned span is dropped
+/// # Arguments
+/// * `category` - What the step does, eg: "compile"
+/// * `name` - What the step works on, eg: the path of a source file
+pub fn span(category: &'static str, name: &str) -> Span {
+    EPOCH.get_or_init(Instant::now);
+    Span { category, name: name.to_string(), start: Instant::now() }
+}
+
+/// Lists the slowest steps of the build, nothing if no step ran
+pub fn summary() {
+    let events = EVENTS.lock().unwrap();
+    if events.is_empty() {
+        return;
+    }
+    let wall = events.iter().map(|event| event.start + event.duration).max().unwrap_or_default();
+    let mut slowest = events.iter().collect::<Vec<&Event>>();
+    slowest.sort_by_key(|event| std::cmp::Reverse(event.duration));
+    log(LogLevel::Info, &format!(
+        "Slowest {} of {} steps, over {:.2}s:",
+        slowest.len().min(SLOWEST), events.len(), wall.as_secs_f64()
+    ));
+    for event in slowest.into_iter().take(SLOWEST) {
+        log(LogLevel::Info, &format!(
+            "  {:>8.2}s  {:<9} {}",
+            event.duration.as_secs_f64(), event.category, event.name
+        ));
+    }
+}
+
+/// Writes the steps of the build as a Chrome trace
+/// # Arguments
+/// * `path` - The path of the trace, eg: `trace.json`
+pub fn write_trace(path: &Path) -> Result<()> {
+    let events = EVENTS.lock().unwrap();
+    let trace_events = events
+        .iter()
+        .map(|event| json!({
+            "name": event.name,
+            "cat": event.category,
+            "ph": "X",
+            "ts": event.start.as_micros() as u64,
+            "dur": event.duration.as_micros() as u64,
+            "pid": 1,
+            "tid": event.thread,
+        }))
+        .collect::<Vec<_>>();
+    let trace = json!({ "traceEvents": trace_events, "displayTimeUnit": "ms" });
+    fs::write(path, trace.to_string())
+        .map_err(|why| RuxgoError::io(format!("Couldn't write trace: {}", path.display()), why))?;
+    log(LogLevel::Log, &format!("Timings written to {}", path.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_holds_one_complete_event_per_step_and_a_row_per_thread() {
+        {
+            let _span = span("compile", "./src/a.c");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        std::thread::spawn(|| drop(span("link", "main"))).join().unwrap();
+        let path = std::env::temp_dir().join(format!("ruxgo-{}-trace.json", std::process::id()));
+        write_trace(&path).unwrap();
+        let trace: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(trace["displayTimeUnit"], "ms");
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        let (compile, link) = (&events[0], &events[1]);
+        assert_eq!(compile["name"], "./src/a.c");
+        assert_eq!(compile["cat"], "compile");
+        assert_eq!(compile["ph"], "X");
+        assert!(compile["dur"].as_u64().unwrap() >= 5000);
+        assert_eq!(link["name"], "main");
+        assert_eq!(link["cat"], "link");
+        // the link ran after the compile, on another row
+        assert!(link["ts"].as_u64().unwrap() >= compile["ts"].as_u64().unwrap() + compile["dur"].as_u64().unwrap());
+        assert_ne!(link["tid"], compile["tid"]);
+    }
+}
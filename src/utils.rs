@@ -1,20 +1,27 @@
 //! This file contains various logging and toml parsing functions
 //! used by the ruxgo library
-use std::sync::{Arc, RwLock};
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
 use std::{io::Read, path::Path};
 use std::fs::{self, File};
 use toml::{Table, Value};
+use serde::{Deserialize, Deserializer};
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{ArrayValidation, InstanceType, ObjectValidation, Schema, SchemaObject, StringValidation};
 use colored::Colorize;
 use std::default::Default;
 use crate::builder::Target;
+use crate::error::{Result, RuxgoError};
+use crate::features::KNOWN_SERVICES;
+use crate::process::Cmd;
 use std::process::{Command, Stdio};
 
-#[cfg(target_os = "windows")]
-static OBJ_DIR: &str = "ruxos_bld/obj_win32";
-#[cfg(target_os = "linux")]
+/// The log level set on the command line, if any
+static LOG_LEVEL: OnceLock<LogLevel> = OnceLock::new();
 
 /// This enum is used to represent the different log levels
-#[derive(PartialEq, PartialOrd, Debug)]
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
 pub enum LogLevel {
     Debug,
     Info,
@@ -29,6 +36,8 @@ pub enum LogLevel {
 /// * `message` - The message to log
 /// # Example
 /// ```
+/// use ruxgo::utils::{log, LogLevel};
+/// let error = "file not found";
 /// log(LogLevel::Info, "Hello World!");
 /// log(LogLevel::Error, &format!("Something went wrong! {}", error));
 /// ```
@@ -41,6 +50,7 @@ pub enum LogLevel {
 /// * `Log`
 /// * `Warn`
 /// * `Error`
+///
 /// If the environment variable is not set, the default log level is `Log`
 pub fn log(level: LogLevel, message: &str) {
     let level_str = match level {
@@ -50,7 +60,25 @@ pub fn log(level: LogLevel, message: &str) {
         LogLevel::Warn => "[WARN]".yellow(),
         LogLevel::Error => "[ERROR]".red(),
     };
-    let log_level = match std::env::var("RUXGO_LOG_LEVEL") {
+    let log_level = match LOG_LEVEL.get() {
+        Some(&log_level) => log_level,
+        None => env_log_level(),
+    };
+    if level >= log_level {
+        println!("{} {}", level_str, message);
+    }
+}
+
+/// Sets the log level of this invocation, instead of the one of the environment variable `RUXGO_LOG_LEVEL`
+/// # Arguments
+/// * `level` - The lowest level of the messages logged
+pub fn set_log_level(level: LogLevel) {
+    let _ = LOG_LEVEL.set(level);
+}
+
+/// Returns the log level set by the environment variable `RUXGO_LOG_LEVEL`, "Info" if not set
+fn env_log_level() -> LogLevel {
+    match std::env::var("RUXGO_LOG_LEVEL") {
         Ok(val) => {
             if val == "Debug" {
                 LogLevel::Debug
@@ -67,73 +95,131 @@ pub fn log(level: LogLevel, message: &str) {
             }
         }
         Err(_) => LogLevel::Info,
-    };
-    if level >= log_level {
-        println!("{} {}", level_str, message);
     }
 }
 
 /// Struct descibing the build config of the local project
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct BuildConfig {
+    /// Compiler used for the targets, prefixed with the cross compiler of the platform if any
+    #[serde(deserialize_with = "deserialize_compiler")]
+    #[schemars(with = "String")]
     pub compiler: Arc<RwLock<String>>,
+    /// Compiler of the C and assembly sources, derived from `compiler` if not set (eg: "g++" => "gcc")
+    #[serde(default, deserialize_with = "deserialize_compiler")]
+    #[schemars(with = "String")]
+    pub cc: Arc<RwLock<String>>,
+    /// Compiler of the C++ sources, and driver linking targets with C++ objects,
+    /// derived from `compiler` if not set (eg: "gcc" => "g++")
+    #[serde(default, deserialize_with = "deserialize_compiler")]
+    #[schemars(with = "String")]
+    pub cxx: Arc<RwLock<String>>,
+    /// Packages to fetch, in the form of "<git_repo> <branch>"
+    #[serde(default)]
     pub packages: Vec<String>,
 }
 
 /// Struct descibing the OS config of the local project
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct OSConfig {
+    /// Name of the OS, eg: ruxos
+    #[serde(default)]
     pub name: String,
+    /// Services of the OS to enable
+    #[serde(rename = "services", default)]
+    #[schemars(schema_with = "services_schema")]
     pub features: Vec<String>,
+    /// User library linked into the app
+    #[serde(default, deserialize_with = "deserialize_ulib")]
+    #[schemars(schema_with = "ulib_schema")]
     pub ulib: String,
+    #[serde(default = "default_platform")]
     pub platform: PlatformConfig,
 }
 
 /// Struct descibing the platform config of the local project
-#[derive(Debug, Default, PartialEq)]
+///
+/// `arch`, `cross_compile` and `target` are derived from `name` after parsing.
+#[derive(Debug, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct PlatformConfig {
+    /// Platform name in the form of "<arch>-<machine>", eg: x86_64-qemu-q35
+    #[serde(default = "default_platform_name", deserialize_with = "deserialize_platform_name")]
+    #[schemars(schema_with = "platform_name_schema")]
     pub name: String,
+    #[serde(skip)]
     pub arch: String,
+    #[serde(skip)]
     pub cross_compile: String,
+    #[serde(skip)]
     pub target: String,
+    /// Number of cpus
+    #[serde(default = "default_smp")]
     pub smp: String,
+    /// Build mode of the OS
+    #[serde(default = "default_mode", deserialize_with = "deserialize_mode")]
+    #[schemars(schema_with = "mode_schema")]
     pub mode: String,
+    /// Log level of the OS
+    #[serde(default = "default_log", deserialize_with = "deserialize_log")]
+    #[schemars(schema_with = "log_schema")]
     pub log: String,
+    /// Verbose level of cargo
+    #[serde(default)]
     pub v: String,
+    #[serde(default = "default_qemu")]
     pub qemu: QemuConfig,
 }
 
 /// Struct descibing the qemu config of the local project
-#[derive(Debug, Default, PartialEq)]
+///
+/// `bus` and `accel` are derived from the platform after parsing.
+#[derive(Debug, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct QemuConfig {
+    #[serde(default = "default_no")]
     pub blk: String,
+    #[serde(default = "default_no")]
     pub net: String,
+    #[serde(default = "default_no")]
     pub graphic: String,
+    #[serde(skip)]
     pub bus: String,
+    #[serde(default = "default_disk_img")]
     pub disk_img: String,
+    #[serde(default = "default_no")]
     pub v9p: String,
+    #[serde(default = "default_v9p_path")]
     pub v9p_path: String,
+    #[serde(skip)]
     pub accel: String,
+    #[serde(default = "default_no")]
     pub qemu_log: String,
+    #[serde(default = "default_no")]
     pub net_dump: String,
+    #[serde(default = "default_net_dev", deserialize_with = "deserialize_net_dev")]
+    #[schemars(schema_with = "net_dev_schema")]
     pub net_dev: String,
+    #[serde(default = "default_ip")]
     pub ip: String,
+    #[serde(default = "default_gw")]
     pub gw: String,
+    #[serde(default)]
     pub args: String,
+    #[serde(default)]
     pub envs: String,
 }
 
 impl QemuConfig {
     /// This function is used to config qemu parameters when running on qemu
-    pub fn config_qemu(&self, platform_config: &PlatformConfig, trgt: &Target) -> (Vec<String>, Vec<String>) {
+    pub fn config_qemu(&self, platform_config: &PlatformConfig, trgt: &Target) -> Result<(Vec<String>, Vec<String>)> {
         // vdev_suffix
         let vdev_suffix = match self.bus.as_str() {
             "mmio" => "device",
             "pci" => "pci",
-            _ => {
-                log(LogLevel::Error, "BUS must be one of 'mmio' or 'pci'");
-                std::process::exit(1);
-            }
+            _ => return Err(RuxgoError::Qemu("BUS must be one of 'mmio' or 'pci'".to_string())),
         };
         // config qemu
         let mut qemu_args = Vec::new();
@@ -147,26 +233,25 @@ impl QemuConfig {
         match platform_config.arch.as_str() {
             "x86_64" => {
                 qemu_args.extend(
-                    vec!["-machine", "q35", "-kernel", &trgt.elf_path].iter().map(|&arg| arg.to_string()));
+                    ["-machine", "q35", "-kernel", &trgt.elf_path].iter().map(|&arg| arg.to_string()));
             }
             "risc64" => {
                 qemu_args.extend(
-                    vec!["-machine", "virt", "-bios", "default", "-kernel", &trgt.bin_path]
+                    ["-machine", "virt", "-bios", "default", "-kernel", &trgt.bin_path]
                     .iter().map(|&arg| arg.to_string()));
             }
             "aarch64" => {
                 qemu_args.extend(
-                    vec!["-cpu", "cortex-a72", "-machine", "virt", "-kernel", &trgt.bin_path]
+                    ["-cpu", "cortex-a72", "-machine", "virt", "-kernel", &trgt.bin_path]
                     .iter().map(|&arg| arg.to_string()));
             }
             _ => {
-                log(LogLevel::Error, "Unsupported architecture");
-                std::process::exit(1);
+                return Err(RuxgoError::Qemu(format!("Unsupported architecture: {}", platform_config.arch)));
             }
         };
         // args and envs
         qemu_args.push("-append".to_string());
-        qemu_args.push(format!("\";{};{}\"", self.args, self.envs));
+        qemu_args.push(format!(";{};{}", self.args, self.envs));
         // blk
         if self.blk == "y" {
             qemu_args.push("-device".to_string());
@@ -193,8 +278,7 @@ impl QemuConfig {
                 qemu_args.push("-netdev".to_string());
                 qemu_args.push("tap,id=net0,ifname=tap0,script=no,downscript=no".to_string());
             } else {
-                log(LogLevel::Error, "NET_DEV must be one of 'user' or 'tap'");
-                std::process::exit(1);
+                return Err(RuxgoError::Qemu("NET_DEV must be one of 'user' or 'tap'".to_string()));
             }
             // net_dump
             if self.net_dump == "y" {
@@ -227,7 +311,7 @@ impl QemuConfig {
         qemu_args_debug.push("-S".to_string());
         // acceel
         if self.accel == "y" {
-            if cfg!(target_os = "darwin") {
+            if cfg!(target_os = "macos") {
                 qemu_args.push("-cpu".to_string());
                 qemu_args.push("host".to_string());
                 qemu_args.push("-accel".to_string());
@@ -239,342 +323,476 @@ impl QemuConfig {
                 qemu_args.push("kvm".to_string());
             }
         }
-    
-        (qemu_args, qemu_args_debug)
+
+        Ok((qemu_args, qemu_args_debug))
     }
 }
 
 /// Struct describing the target config of the local project
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct TargetConfig {
+    /// Name of the target, libraries must start with `lib`
     pub name: String,
+    /// Directory holding the sources of the target
+    #[serde(default)]
     pub src: String,
+    /// Sources or directories to leave out of the target
+    #[serde(default)]
     pub src_excluded: Vec<String>,
+    /// Include directory of the target
+    #[serde(default = "default_include_dir")]
     pub include_dir: String,
+    /// Kind of binary produced by the target
+    #[serde(rename = "type", deserialize_with = "deserialize_target_type")]
+    #[schemars(schema_with = "target_type_schema")]
     pub typ: String,
+    /// Flags passed to the compiler for C sources
+    #[serde(default)]
     pub cflags: String,
+    /// Flags passed to the compiler for C++ sources, `cflags` if not set
+    #[serde(default)]
+    pub cxxflags: Option<String>,
+    /// Flags passed to the compiler for assembly sources, `cflags` if not set
+    #[serde(default)]
+    pub asflags: Option<String>,
+    /// Archiver used for static libraries
+    #[serde(default)]
     pub archive: String,
+    /// Flags passed to the linker or archiver
+    #[serde(default)]
     pub ldflags: String,
+    /// Names of the library targets this target depends on
+    #[serde(default)]
     pub deps: Vec<String>,
+    /// Header precompiled once and included first by every source of the target, eg: "src/pch.h"
+    #[serde(default)]
+    pub pch: String,
+    /// Whether the C and C++ sources are merged into generated unity files, compiled in batches
+    #[serde(default)]
+    pub unity: bool,
+    /// Number of sources merged into each unity file, 0 to merge all the sources of a language
+    #[serde(default = "default_unity_batch")]
+    pub unity_batch: usize,
+    /// Sources compiled on their own in unity mode, as they break when merged
+    #[serde(default)]
+    pub unity_excluded: Vec<String>,
+    /// Extensions of the source files (without the dot) and their language,
+    /// defaults to `c`, `cpp`, `cc`, `cxx`, `c++`, `S` and `s`
+    #[serde(default = "default_src_extensions", deserialize_with = "deserialize_src_extensions")]
+    #[schemars(schema_with = "src_extensions_schema")]
+    pub src_extensions: BTreeMap<String, String>,
 }
 
 impl TargetConfig {
-    /// Returns a vec of all filenames ending in .cpp or .c in the src directory
+    /// Returns the flags of the sources of a language
+    /// # Arguments
+    /// * `language` - The language of the sources, as returned by `src_language`
+    pub fn flags(&self, language: &str) -> &str {
+        let flags = match language {
+            "c++" => self.cxxflags.as_ref(),
+            "assembler" | "assembler-with-cpp" => self.asflags.as_ref(),
+            _ => None,
+        };
+        flags.unwrap_or(&self.cflags)
+    }
+
+    /// Returns the language of a source file of the target, None if it is not a source file
+    /// # Arguments
+    /// * `path` - The path of the file
+    pub fn src_language(&self, path: &str) -> Option<&str> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        self.src_extensions.get(ext).map(String::as_str)
+    }
+
+    /// Returns a vec of all filenames with a source extension of the target in the src directory
     /// # Arguments
     /// * `path` - The path to the src directory
-    fn get_src_names(path: &str) -> Vec<String> {
+    fn get_src_names(&self, path: &str) -> Result<Vec<String>> {
         if path.is_empty() {
-            return Vec::new();
+            return Ok(Vec::new());
         }
         let mut src_names = Vec::new();
         let src_path = Path::new(&path);
-        let src_entries = std::fs::read_dir(src_path).unwrap_or_else(|_| {
-            log(LogLevel::Error, &format!("Could not read src dir: {}", path));
-            std::process::exit(1);
-        });
+        let src_entries = std::fs::read_dir(src_path)
+            .map_err(|why| RuxgoError::io(format!("Could not read src dir: {}", path), why))?;
         for entry in src_entries {
-            let entry = entry.unwrap();
+            let entry = entry.map_err(|why| RuxgoError::io(format!("Could not read src dir: {}", path), why))?;
             let path = entry.path();
             if path.is_file() {
                 let file_name = path.file_name().unwrap().to_str().unwrap();
-                if file_name.ends_with(".cpp") || file_name.ends_with(".c") {
+                if self.src_language(file_name).is_some() {
                     src_names.push(file_name.to_string());
                 }
             } else if path.is_dir() {
-                let dir_name = path.to_str().unwrap().replace("\\", "/");
-                let mut dir_src_names = TargetConfig::get_src_names(&dir_name);
+                let dir_name = path.to_str().unwrap().replace('\\', "/");
+                let mut dir_src_names = self.get_src_names(&dir_name)?;
                 src_names.append(&mut dir_src_names);
             }
         }
-        src_names
-    }
-    
-    /// Rearrange the input targets
-    fn arrange_targets(targets: Vec<TargetConfig>) -> Vec<TargetConfig> {
-        let mut targets = targets.clone();
-        let mut i = 0;
-        while i < targets.len() {
-            let mut j = i + 1;
-            while j < targets.len() {
-                if targets[i].deps.contains(&targets[j].name) {
-                    //Check for circular dependencies
-                    if targets[j].deps.contains(&targets[i].name) {
-                        log(
-                            LogLevel::Error,
-                            &format!(
-                                "Circular dependency found between {} and {}",
-                                targets[i].name, targets[j].name
-                            ),
-                        );
-                        std::process::exit(1);
-                    }
-                    let temp = targets[i].clone();
-                    targets[i] = targets[j].clone();
-                    targets[j] = temp;
-                    i = 0;
-                    break;
-                }
-                j += 1;
-            }
-            i += 1;
-        }
-        targets
+        Ok(src_names)
     }
 }
 
+/// Struct describing a whole config file of the local project
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    build: BuildConfig,
+    os: Option<OSConfig>,
+    targets: Vec<TargetConfig>,
+}
+
+/// Returns the JSON Schema of the config file, used by editors for validation and completion
+pub fn config_schema() -> Result<String> {
+    let schema = schemars::schema_for!(ConfigFile);
+    serde_json::to_string_pretty(&schema)
+        .map_err(|e| RuxgoError::Config(format!("Could not serialize config schema: {}", e)))
+}
+
 /// This function is used to parse the config file of local project
 /// # Arguments
 /// * `path` - The path to the config file
-/// * `check_dup_src` - If true, the function will check for duplicately named source files
-pub fn parse_config(path: &str, check_dup_src: bool) -> (BuildConfig, OSConfig, Vec<TargetConfig>) {
+/// * `warn_no_src` - If true, the function will warn about targets without source files
+pub fn parse_config(path: &str, warn_no_src: bool) -> Result<(BuildConfig, OSConfig, Vec<TargetConfig>)> {
     // Open toml file and parse it into a string
-    let mut file = File::open(path).unwrap_or_else(|_| {
-        log(LogLevel::Error, &format!("Could not open config file: {}", path));
-        std::process::exit(1);
-    });
+    let mut file = File::open(path)
+        .map_err(|why| RuxgoError::io(format!("Could not open config file: {}", path), why))?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap_or_else(|_| {
-        log(LogLevel::Error, &format!("Could not read config file: {}", path));
-        std::process::exit(1);
-    });
-    let config = contents.parse::<Table>().unwrap_or_else(|e| {
-        log(LogLevel::Error, &format!("Could not parse config file: {}", path));
-        log(LogLevel::Error, &format!("Error: {}", e));
-        std::process::exit(1);
-    });
-
-    // Parse build
-    let build = config["build"].as_table().unwrap_or_else(|| {
-        log(LogLevel::Error, "Could not find build in config file");
-        std::process::exit(1);
-    });
-    let compiler= Arc::new(
-        RwLock::new(
-            build.get("compiler").unwrap_or_else(|| {
-                log(LogLevel::Error, "Could not find compiler in config file");
-                std::process::exit(1);
-            }).as_str().unwrap_or_else(|| {
-                log(LogLevel::Error, "Compiler is not a string");
-                std::process::exit(1);
-            }).to_string()
-        )
-    );
-
-    let packages = parse_cfg_vector(build, "packages");
-    let build_config = BuildConfig {compiler, packages};
-
-    // Parse os (optional)
-    let empty_os = Value::Table(toml::map::Map::default());
-    let os = config.get("os").unwrap_or(&empty_os);
-    let os_config: OSConfig;
-    if os != &empty_os {
-        if let Some(os_table) = os.as_table() {
-            let name = parse_cfg_string(&os_table, "name", "");
-            let ulib = parse_cfg_string(&os_table, "ulib", "");
-            let mut features = parse_cfg_vector(&os_table, "services");
-            if features.iter().any(|feat| {
+    file.read_to_string(&mut contents)
+        .map_err(|why| RuxgoError::io(format!("Could not read config file: {}", path), why))?;
+    let config: ConfigFile = toml::from_str(&contents)
+        .map_err(|e| config_error(path, &contents, &e))?;
+    let build_config = config.build;
+    {
+        let compiler = build_config.compiler.read().unwrap().clone();
+        let mut cc = build_config.cc.write().unwrap();
+        if cc.is_empty() {
+            *cc = c_driver(&compiler);
+        }
+        let mut cxx = build_config.cxx.write().unwrap();
+        if cxx.is_empty() {
+            *cxx = cxx_driver(&compiler);
+        }
+    }
+
+    // Complete os (optional)
+    let os_config = match config.os {
+        Some(mut os_config) => {
+            if os_config.features.iter().any(|feat| {
                 feat == "fs" || feat == "net" || feat == "pipe" || feat == "select" || feat == "poll" || feat == "epoll"
             }) {
-                features.push("fd".to_string());
+                os_config.features.push("fd".to_string());
             }
-            if ulib == "ruxmusl" {
-                features.push("musl".to_string());
-                features.push("fp_simd".to_string());
-                features.push("fd".to_string());
-                features.push("tls".to_string());
+            if os_config.ulib == "ruxmusl" {
+                os_config.features.push("musl".to_string());
+                os_config.features.push("fp_simd".to_string());
+                os_config.features.push("fd".to_string());
+                os_config.features.push("tls".to_string());
             }
-            // Parse platform (if empty, it is the default value)
-            let platform = parse_platform(&os_table);
-            *build_config.compiler.write().unwrap() = format!("{}{}", platform.cross_compile, *build_config.compiler.read().unwrap());
-            os_config = OSConfig {name, features, ulib, platform};
-        } else {
-            log(LogLevel::Error, "OS is not a table");
-            std::process::exit(1);
-        }
-    } else {
-        os_config = OSConfig::default();
-    }
-
-    // Parse multiple targets
-    let mut tgt = Vec::new();
-    let targets = config["targets"].as_array().unwrap_or_else(|| {
-        log(LogLevel::Error, "Could not find targets in config file");
-        std::process::exit(1);
-    });
-    for target in targets {
-        let target_tb = target.as_table().unwrap_or_else(|| {
-            log(LogLevel::Error, "Target is not a table");
-            std::process::exit(1);
-        });
-        let target_config = TargetConfig {
-            name: parse_cfg_string(target_tb, "name", ""),
-            src: parse_cfg_string(target_tb, "src", ""),
-            src_excluded: parse_cfg_vector(target_tb, "src_excluded"),
-            include_dir: parse_cfg_string(target_tb, "include_dir", "./"),
-            typ: parse_cfg_string(target_tb, "type", ""),
-            cflags: parse_cfg_string(target_tb, "cflags", ""),
-            archive: parse_cfg_string(target_tb, "archive", ""),
-            ldflags: parse_cfg_string(target_tb, "ldflags", ""),
-            deps: parse_cfg_vector(target_tb, "deps"),
-        };
-        if target_config.typ != "exe" && target_config.typ != "dll" 
-        && target_config.typ != "static" && target_config.typ != "object" {
-            log(LogLevel::Error, "Type must be exe, dll, object or static");
-            std::process::exit(1);
+            complete_platform(&mut os_config.platform)?;
+            for compiler in [&build_config.compiler, &build_config.cc, &build_config.cxx] {
+                let prefixed = format!("{}{}", os_config.platform.cross_compile, *compiler.read().unwrap());
+                *compiler.write().unwrap() = prefixed;
+            }
+            os_config
         }
-        tgt.push(target_config);
-    }
+        None => OSConfig::default(),
+    };
 
+    // Check multiple targets
+    let tgt = config.targets;
     if tgt.is_empty() {
-        log(LogLevel::Error, "No targets found");
-        std::process::exit(1);
+        return Err(RuxgoError::Config("No targets found".to_string()));
     }
     // Check for duplicate target names
     for i in 0..tgt.len() - 1 {
         for j in i + 1..tgt.len() {
             if tgt[i].name == tgt[j].name {
-                log(LogLevel::Error, &format!("Duplicate target names found: {}", tgt[i].name));
-                std::process::exit(1);
+                return Err(RuxgoError::Config(format!("Duplicate target names found: {}", tgt[i].name)));
             }
         }
     }
-    // Check duplicate srcs in target(no remove)
-    if check_dup_src {
+    // Source files may share names, their objects mirror the source tree
+    if warn_no_src {
         for target in &tgt {
-            let mut src_file_names = TargetConfig::get_src_names(&target.src);
-            src_file_names.sort();
-            if !src_file_names.is_empty() {
-                for i in 0..src_file_names.len() - 1 {
-                    if src_file_names[i] == src_file_names[i + 1] {
-                        log(LogLevel::Error, &format!("Duplicate source files found for target: {}", target.name));
-                        log(LogLevel::Error, "Source files must be unique");
-                        log(LogLevel::Error, &format!("Duplicate file: {}", src_file_names[i]));
-                        std::process::exit(1);
-                    }
-                }
-            } else {
+            if target.get_src_names(&target.src)?.is_empty() {
                 log(LogLevel::Warn, &format!("No source files found for target: {}", target.name));
             }
         }
     }
-    let tgt_arranged = TargetConfig::arrange_targets(tgt);
 
-    (build_config, os_config, tgt_arranged)
+    Ok((build_config, os_config, tgt))
 }
 
-/// Parse platform config
-fn parse_platform(config: &Table) -> PlatformConfig {
-    let empty_platform = Value::Table(toml::map::Map::default());
-    let platform = config.get("platform").unwrap_or(&empty_platform);
-    if let Some(platform_table) = platform.as_table() {
-        let name = parse_cfg_string(&platform_table, "name", "x86_64-qemu-q35");
-        let arch = name.split("-").next().unwrap_or("x86_64").to_string();
-        let cross_compile = format!("{}-linux-musl-", arch);
-        let target = match &arch[..] {
-            "x86_64" => "x86_64-unknown-none".to_string(),
-            "riscv64" => "riscv64gc-unknown-none-elf".to_string(),
-            "aarch64" => "aarch64-unknown-none-softfloat".to_string(),
-            _ => {
-                log(LogLevel::Error, "\"ARCH\" must be one of \"x86_64\", \"riscv64\", or \"aarch64\"");
-                std::process::exit(1);
-            }
-        };
-        let smp = parse_cfg_string(&platform_table, "smp", "1");
-        let mode = parse_cfg_string(&platform_table, "mode", "release");
-        let log = parse_cfg_string(&platform_table, "log", "warn");
-        let v = parse_cfg_string(&platform_table, "v", "");
-        // determine whether enable qemu
-        let qemu: QemuConfig;
-        if name.split("-").any(|s| s == "qemu") {
-            // parse qemu (if empty, it is the default value)
-            qemu = parse_qemu(&arch, &platform_table);
-        } else {
-            qemu = QemuConfig::default();
-        }
-        PlatformConfig {name, arch, cross_compile, target, smp, mode, log, v, qemu}
+/// Returns the C counterpart of a compiler, eg: "g++" => "gcc", "clang++" => "clang"
+fn c_driver(compiler: &str) -> String {
+    if let Some(prefix) = compiler.strip_suffix("g++") {
+        format!("{}gcc", prefix)
+    } else if let Some(prefix) = compiler.strip_suffix("clang++") {
+        format!("{}clang", prefix)
+    } else {
+        compiler.to_string()
+    }
+}
+
+/// Returns the C++ counterpart of a compiler, eg: "gcc" => "g++", "clang" => "clang++"
+fn cxx_driver(compiler: &str) -> String {
+    if let Some(prefix) = compiler.strip_suffix("gcc") {
+        format!("{}g++", prefix)
+    } else if let Some(prefix) = compiler.strip_suffix("clang") {
+        format!("{}clang++", prefix)
+    } else if let Some(prefix) = compiler.strip_suffix("cc") {
+        format!("{}c++", prefix)
     } else {
-        log(LogLevel::Error, "Platform is not a table");
-        std::process::exit(1);
-    } 
-}
-
-/// Parse qemu config
-fn parse_qemu(arch: &str, config: &Table) -> QemuConfig {
-    let empty_qemu = Value::Table(toml::map::Map::default());
-    let qemu = config.get("qemu").unwrap_or(&empty_qemu);
-    if let Some(qemu_table) = qemu.as_table() {
-        let blk = parse_cfg_string(&qemu_table, "blk", "n");
-        let net = parse_cfg_string(&qemu_table, "net", "n");
-        let graphic = parse_cfg_string(&qemu_table, "graphic", "n");
-        let bus = match &arch[..] {
+        compiler.to_string()
+    }
+}
+
+/// Fills in the platform fields that are derived from the platform name
+fn complete_platform(platform: &mut PlatformConfig) -> Result<()> {
+    platform.arch = platform.name.split('-').next().unwrap_or("x86_64").to_string();
+    platform.cross_compile = format!("{}-linux-musl-", platform.arch);
+    platform.target = match platform.arch.as_str() {
+        "x86_64" => "x86_64-unknown-none".to_string(),
+        "riscv64" => "riscv64gc-unknown-none-elf".to_string(),
+        _ => "aarch64-unknown-none-softfloat".to_string(),
+    };
+    // determine whether enable qemu
+    if platform.name.split('-').any(|s| s == "qemu") {
+        platform.qemu.bus = match platform.arch.as_str() {
             "x86_64" => "pci".to_string(),
             _ => "mmio".to_string()
         };
-        let disk_img = parse_cfg_string(&qemu_table, "disk_img", "disk.img");
-        let v9p = parse_cfg_string(&qemu_table, "v9p", "n");
-        let v9p_path = parse_cfg_string(&qemu_table, "v9p_path", "./");
-        let accel_pre = match Command::new("uname").arg("-r").output() {
-            Ok(output) => {
-                let kernel_version = String::from_utf8_lossy(&output.stdout).to_lowercase();
-                if kernel_version.contains("-microsoft") { "n" } else { "y" }
-            },
-            Err(_) => {
-                log(LogLevel::Error, "Failed to execute command");
-                std::process::exit(1);
-            }
-        };
-        let accel = match &arch[..] {
+        let output = Command::new("uname").arg("-r").output()
+            .map_err(|why| RuxgoError::io("Failed to execute uname", why))?;
+        let kernel_version = String::from_utf8_lossy(&output.stdout).to_lowercase();
+        let accel_pre = if kernel_version.contains("-microsoft") { "n" } else { "y" };
+        platform.qemu.accel = match platform.arch.as_str() {
             "x86_64" => accel_pre.to_string(),
             _ => "n".to_string()
         };
-        let qemu_log = parse_cfg_string(&qemu_table, "qemu_log", "n");
-        let net_dump = parse_cfg_string(&qemu_table, "net_dump", "n");
-        let net_dev = parse_cfg_string(&qemu_table, "net_dev", "user");
-        let ip = parse_cfg_string(&qemu_table, "ip",  "10.0.2.15");
-        let gw = parse_cfg_string(&qemu_table, "gw", "10.0.2.2");
-        let args = parse_cfg_string(&qemu_table, "args", "");
-        let envs = parse_cfg_string(&qemu_table, "envs", "");
-        QemuConfig {blk, net, graphic, bus, disk_img, v9p, v9p_path, accel, qemu_log, net_dump, net_dev, ip, gw, args, envs}
     } else {
-        log(LogLevel::Error, "Qemu is not a table");
-        std::process::exit(1);
-    }
-}
-
-fn parse_cfg_string(config: &Table, field: &str, default: &str) -> String {
-    let default_string = Value::String(default.to_string());
-    config.get(field)
-        .unwrap_or_else(|| &default_string)
-        .as_str()
-        .unwrap_or_else(|| {
-            log(LogLevel::Error, &format!("{} is not a string", field));
-            std::process::exit(1);
-        })
-        .to_string()
-}
-
-fn parse_cfg_vector(config: &Table, field: &str) -> Vec<String> {
-    let empty_vector = Value::Array(Vec::new());
-    config.get(field)
-        .unwrap_or_else(|| &empty_vector)
-        .as_array()
-        .unwrap_or_else(|| {
-            log(LogLevel::Error, &format!("{} is not an array", field));
-            std::process::exit(1);
-        })
+        platform.qemu = QemuConfig::default();
+    }
+    Ok(())
+}
+
+/// Turns a toml error into a config error pointing at `path:line:column` of the offending key or value
+fn config_error(path: &str, contents: &str, err: &toml::de::Error) -> RuxgoError {
+    let mut message = err.message().trim_end().to_string();
+    if let Some(suggestion) = suggest_key(&message) {
+        message.push_str(&format!(", did you mean `{}`?", suggestion));
+    }
+    let span = match err.span() {
+        Some(span) => span,
+        None => return RuxgoError::Config(format!("{}: {}", path, message)),
+    };
+    let line_start = contents[..span.start].rfind('\n').map_or(0, |pos| pos + 1);
+    let line = contents[..span.start].matches('\n').count() + 1;
+    let column = contents[line_start..span.start].chars().count() + 1;
+    let line_text = contents[line_start..].lines().next().unwrap_or("");
+    let marker_len = contents[span.clone()].lines().next().map_or(1, |s| s.chars().count().max(1));
+    let gutter = " ".repeat(line.to_string().len());
+    RuxgoError::Config(format!(
+        "{}:{}:{}: {}\n{} |\n{} | {}\n{} | {}{}",
+        path, line, column, message,
+        gutter,
+        line, line_text,
+        gutter, " ".repeat(column - 1), "^".repeat(marker_len)
+    ))
+}
+
+/// Returns the closest expected name for an unknown key or invalid value in a serde error message
+fn suggest_key(message: &str) -> Option<String> {
+    // eg: unknown field `IP`, expected one of `blk`, `net`, ...
+    //     invalid value: string "stati", expected one of `exe`, `dll`, ...
+    let (given, expected) = if let Some(rest) = message.strip_prefix("unknown field `") {
+        let end = rest.find('`')?;
+        (&rest[..end], &rest[end..])
+    } else if let Some(rest) = message.strip_prefix("invalid value: string \"") {
+        let end = rest.find('"')?;
+        (&rest[..end], &rest[end..])
+    } else {
+        return None;
+    };
+    let expected = &expected[expected.find("expected")?..];
+    let given = given.to_lowercase();
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (strsim::levenshtein(&given, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Allowed values of `type` in a target
+pub const TARGET_TYPES: &[&str] = &["exe", "dll", "static", "object"];
+/// Allowed values of `ulib` in the `[os]` section
+pub const ULIBS: &[&str] = &["ruxlibc", "ruxmusl"];
+/// Allowed values of the "ARCH" part of the platform name
+pub const ARCHS: &[&str] = &["x86_64", "riscv64", "aarch64"];
+const MODES: &[&str] = &["release", "debug"];
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+const NET_DEVS: &[&str] = &["user", "tap"];
+/// Languages of the source files, named like the `-x` option of gcc and clang
+pub const LANGUAGES: &[&str] = &["c", "c++", "assembler", "assembler-with-cpp"];
+
+/// Deserializes a string and checks that it is one of `allowed`
+fn deserialize_one_of<'de, D>(deserializer: D, allowed: &[&str]) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    if allowed.contains(&value.as_str()) {
+        return Ok(value);
+    }
+    let expected = allowed.iter().map(|v| format!("`{}`", v)).collect::<Vec<String>>().join(", ");
+    Err(serde::de::Error::invalid_value(
+        serde::de::Unexpected::Str(&value),
+        &format!("one of {}", expected).as_str(),
+    ))
+}
+
+fn deserialize_compiler<'de, D>(deserializer: D) -> std::result::Result<Arc<RwLock<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Arc::new(RwLock::new(String::deserialize(deserializer)?)))
+}
+
+fn deserialize_ulib<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    deserialize_one_of(deserializer, ULIBS)
+}
+
+fn deserialize_platform_name<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    match name.split('-').next() {
+        Some(arch) if ARCHS.contains(&arch) => Ok(name),
+        _ => Err(serde::de::Error::custom(
+            "\"ARCH\" of the platform name must be one of \"x86_64\", \"riscv64\", or \"aarch64\""
+        )),
+    }
+}
+
+fn deserialize_mode<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    deserialize_one_of(deserializer, MODES)
+}
+
+fn deserialize_log<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    deserialize_one_of(deserializer, LOG_LEVELS)
+}
+
+fn deserialize_net_dev<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    deserialize_one_of(deserializer, NET_DEVS)
+}
+
+fn deserialize_src_extensions<'de, D>(deserializer: D) -> std::result::Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let extensions = BTreeMap::<String, String>::deserialize(deserializer)?;
+    let expected = LANGUAGES.iter().map(|v| format!("`{}`", v)).collect::<Vec<String>>().join(", ");
+    let mut src_extensions = BTreeMap::new();
+    for (ext, lang) in extensions {
+        if !LANGUAGES.contains(&lang.as_str()) {
+            return Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&lang),
+                &format!("one of {}", expected).as_str(),
+            ));
+        }
+        src_extensions.insert(ext.trim_start_matches('.').to_string(), lang);
+    }
+    Ok(src_extensions)
+}
+
+fn deserialize_target_type<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    deserialize_one_of(deserializer, TARGET_TYPES)
+}
+
+/// Returns the schema of a string that must be one of `allowed`
+fn one_of_schema(allowed: &[&str]) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(allowed.iter().map(|v| (*v).into()).collect()),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn ulib_schema(_: &mut SchemaGenerator) -> Schema { one_of_schema(ULIBS) }
+fn mode_schema(_: &mut SchemaGenerator) -> Schema { one_of_schema(MODES) }
+fn log_schema(_: &mut SchemaGenerator) -> Schema { one_of_schema(LOG_LEVELS) }
+fn net_dev_schema(_: &mut SchemaGenerator) -> Schema { one_of_schema(NET_DEVS) }
+fn target_type_schema(_: &mut SchemaGenerator) -> Schema { one_of_schema(TARGET_TYPES) }
+
+fn src_extensions_schema(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(ObjectValidation {
+            additional_properties: Some(Box::new(one_of_schema(LANGUAGES))),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn services_schema(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Array.into()),
+        array: Some(Box::new(ArrayValidation {
+            items: Some(one_of_schema(KNOWN_SERVICES).into()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn platform_name_schema(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(format!("^({})(-|$)", ARCHS.join("|"))),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn default_platform() -> PlatformConfig {
+    PlatformConfig::deserialize(Value::Table(Table::new())).expect("default platform config")
+}
+
+fn default_qemu() -> QemuConfig {
+    QemuConfig::deserialize(Value::Table(Table::new())).expect("default qemu config")
+}
+
+/// Returns the source extensions of a target that does not set them, and their language
+pub fn default_src_extensions() -> BTreeMap<String, String> {
+    [("c", "c"), ("cpp", "c++"), ("cc", "c++"), ("cxx", "c++"), ("c++", "c++"), ("S", "assembler-with-cpp"), ("s", "assembler")]
         .iter()
-        .map(|value| {
-            value
-                .as_str()
-                .unwrap_or_else(|| {
-                    log(LogLevel::Error, &format!("{} elements are strings", field));
-                    std::process::exit(1);
-                })
-                .to_string()
-        })
+        .map(|(ext, lang)| (ext.to_string(), lang.to_string()))
         .collect()
 }
 
+fn default_unity_batch() -> usize { 16 }
+fn default_platform_name() -> String { "x86_64-qemu-q35".to_string() }
+fn default_smp() -> String { "1".to_string() }
+fn default_mode() -> String { "release".to_string() }
+fn default_log() -> String { "warn".to_string() }
+fn default_no() -> String { "n".to_string() }
+fn default_disk_img() -> String { "disk.img".to_string() }
+fn default_v9p_path() -> String { "./".to_string() }
+fn default_net_dev() -> String { "user".to_string() }
+fn default_ip() -> String { "10.0.2.15".to_string() }
+fn default_gw() -> String { "10.0.2.2".to_string() }
+fn default_include_dir() -> String { "./".to_string() }
+
 // This function is used to configure environment variables
 pub fn config_env(os_config: &OSConfig,) {
     if os_config != &OSConfig::default() && os_config.platform != PlatformConfig::default() {
@@ -634,56 +852,42 @@ impl Package {
     }
     
     /// Updates the package to latest commit
-    pub fn update(&self) {
-        let mut cmd = String::from("cd");
-        cmd.push_str(&format!(" ./ruxos_bld/packages/{}", self.name));
+    pub fn update(&self) -> Result<()> {
+        let mut cmd = Cmd::new("git");
+        cmd.args(["pull", "origin", &self.branch]);
+        cmd.current_dir(format!("./ruxos_bld/packages/{}", self.name));
         log(LogLevel::Log, &format!("Updating package: {}", self.name));
-        cmd.push_str(" &&");
-        cmd.push_str(" git");
-        cmd.push_str(" pull");
-        cmd.push_str(" origin");
-        cmd.push_str(&format!(" {}", self.branch));
-        let com = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
+        let com = cmd
             .output()
-            .unwrap_or_else(|e| {
-                log(LogLevel::Error, &format!("Failed to update package: {}", e));
-                std::process::exit(1);
-            });
+            .map_err(|e| RuxgoError::Package(format!("Failed to update package {}: {}", self.name, e)))?;
         if com.status.success() {
             log(LogLevel::Log, &format!("Successfully updated package: {}", self.name));
-            log(LogLevel::Log, &format!("Output: {}", String::from_utf8_lossy(&com.stdout)).replace("\r", "").replace("\n", ""));
+            log(LogLevel::Log, &format!("Output: {}", String::from_utf8_lossy(&com.stdout)).replace(['\r', '\n'], ""));
+            Ok(())
         } else {
-            log(LogLevel::Error, &format!("Failed to update package: {}", String::from_utf8_lossy(&com.stderr)));
-            std::process::exit(1);
+            Err(RuxgoError::Package(format!(
+                "Failed to update package {}: {}", self.name, String::from_utf8_lossy(&com.stderr)
+            )))
         }
     }
 
     /// Restores package to last offline commit
-    pub fn restore(&self) {
-        let mut cmd = String::from("cd");
-        cmd.push_str(&format!(" ./ruxos_bld/packages/{}", self.name));
+    pub fn restore(&self) -> Result<()> {
+        let mut cmd = Cmd::new("git");
+        cmd.args(["reset", "--hard", &self.branch]);
+        cmd.current_dir(format!("./ruxos_bld/packages/{}", self.name));
         log(LogLevel::Log, &format!("Updating package: {}", self.name));
-        cmd.push_str(" &&");
-        cmd.push_str(" git");
-        cmd.push_str(" reset");
-        cmd.push_str(" --hard");
-        cmd.push_str(&format!(" {}", self.branch));
-        let com = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
+        let com = cmd
             .output()
-            .unwrap_or_else(|e| {
-                log(LogLevel::Error, &format!("Failed to restore package: {}", e));
-                std::process::exit(1);
-            });
+            .map_err(|e| RuxgoError::Package(format!("Failed to restore package {}: {}", self.name, e)))?;
         if com.status.success() {
             log(LogLevel::Log, &format!("Successfully restored package: {}", self.name));
-            log(LogLevel::Log, &format!("Output: {}", String::from_utf8_lossy(&com.stdout)).replace("\r", "").replace("\n", ""));
+            log(LogLevel::Log, &format!("Output: {}", String::from_utf8_lossy(&com.stdout)).replace(['\r', '\n'], ""));
+            Ok(())
         } else {
-            log(LogLevel::Error, &format!("Failed to restore package: {}", String::from_utf8_lossy(&com.stderr)));
-            std::process::exit(1);
+            Err(RuxgoError::Package(format!(
+                "Failed to restore package {}: {}", self.name, String::from_utf8_lossy(&com.stderr)
+            )))
         }
     }
 
@@ -691,28 +895,28 @@ impl Package {
     /// The folder must contain a config toml file
     /// # Arguments
     /// * `path` - The path to the folder containing the package
-    pub fn parse_packages(path: &str) -> Vec<Package> {
+    pub fn parse_packages(path: &str) -> Result<Vec<Package>> {
         let mut packages: Vec<Package> = Vec::new();
         // parse the root toml file, eg: packages = ["Ybeichen/redis, redis-7.0.12"]
-        let (build_config_toml, _ , _) = parse_config(path, false);
+        let (build_config_toml, _ , _) = parse_config(path, false)?;
         for package in build_config_toml.packages {
             let deets = package.split_whitespace().collect::<Vec<&str>>();
             if deets.len() != 2 {
-                log(LogLevel::Error, "Packages must be in the form of \"<git_repo> <branch>\"");
-                std::process::exit(1);
+                return Err(RuxgoError::Package(
+                    "Packages must be in the form of \"<git_repo> <branch>\"".to_string()
+                ));
             }
-            let repo = deets[0].to_string().replace(",", "");
+            let repo = deets[0].to_string().replace(',', "");
             let branch = deets[1].to_string();
-            let name = repo.split("/").collect::<Vec<&str>>()[1].to_string();
+            let name = repo.split('/').nth(1)
+                .ok_or_else(|| RuxgoError::Package(format!("Invalid package repo: {}", repo)))?
+                .to_string();
             let source_dir = format!("./ruxos_bld/packages/{}/", name);
             let mut sub_packages: Vec<Package> = Vec::new();
             // git clone packages
             if !Path::new(&source_dir).exists() {
                 fs::create_dir_all(&source_dir)
-                    .unwrap_or_else(|err| {
-                        log(LogLevel::Error, &format!("Failed to create {}: {}", source_dir, err));
-                        std::process::exit(1);
-                    });
+                    .map_err(|err| RuxgoError::io(format!("Failed to create {}", source_dir), err))?;
                 log(LogLevel::Info, &format!("Created {}", source_dir));
                 log(LogLevel::Log, &format!("Cloning {} into {}...", repo, source_dir));
                 let repo_https = format!("https://mirror.ghproxy.com/https://github.com/{}", repo);
@@ -725,10 +929,12 @@ impl Package {
                     .stdin(Stdio::inherit())
                     .stdout(Stdio::inherit())
                     .stderr(Stdio::inherit());
-                let output = cmd.output().expect("Failed to execute git clone");
+                let output = cmd.output()
+                    .map_err(|err| RuxgoError::Package(format!("Failed to execute git clone: {}", err)))?;
                 if !output.status.success() {
-                    log(LogLevel::Error, &format!("Failed to clone {} branch {} into {}", repo, branch, source_dir));
-                    std::process::exit(1);
+                    return Err(RuxgoError::Package(format!(
+                        "Failed to clone {} branch {} into {}", repo, branch, source_dir
+                    )));
                 }
             } else {
                 log(LogLevel::Log, &format!("{} already exists!", source_dir));
@@ -737,12 +943,12 @@ impl Package {
             let pkg_toml = format!("{}/config_linux.toml", source_dir).replace("//", "/");
             #[cfg(target_os = "windows")]
             let pkg_toml = format!("{}/config_win32.toml", source_dir).replace("//", "/");
-            let (pkg_bld_config_toml, _, pkg_targets_toml) = parse_config(&pkg_toml, false);
+            let (pkg_bld_config_toml, _, pkg_targets_toml) = parse_config(&pkg_toml, false)?;
             log(LogLevel::Info, &format!("Parsed {}", pkg_toml));
 
             // recursive parse all of the packages
             if !pkg_bld_config_toml.packages.is_empty() {
-                sub_packages = Package::parse_packages(&pkg_toml);
+                sub_packages = Package::parse_packages(&pkg_toml)?;
                 for foreign_package in sub_packages.clone() {
                     packages.push(foreign_package);
                 }
@@ -750,7 +956,9 @@ impl Package {
 
             // get build_config
             let mut build_config = pkg_bld_config_toml;
-            build_config.compiler = build_config_toml.compiler.clone(); // use current compiler
+            build_config.compiler = build_config_toml.compiler.clone(); // use current compilers
+            build_config.cc = build_config_toml.cc.clone();
+            build_config.cxx = build_config_toml.cxx.clone();
 
             // get tgt_config
             let mut target_configs = Vec::new();
@@ -761,13 +969,19 @@ impl Package {
                 }
                 // concatenate to generate a new src path and include path
                 tgt.src = format!("{}/{}", source_dir, tgt.src)
-                    .replace("\\", "/")
+                    .replace('\\', "/")
                     .replace("/./", "/")
                     .replace("//", "/");
                 tgt.include_dir = format!("{}/{}", source_dir, tgt.include_dir)
-                    .replace("\\", "/")
+                    .replace('\\', "/")
                     .replace("/./", "/")
                     .replace("//", "/");
+                if !tgt.pch.is_empty() {
+                    tgt.pch = format!("{}/{}", source_dir, tgt.pch)
+                        .replace('\\', "/")
+                        .replace("/./", "/")
+                        .replace("//", "/");
+                }
                 target_configs.push(tgt);
             }
             packages.push(Package::new(name, repo, branch, build_config, target_configs, sub_packages));
@@ -776,6 +990,163 @@ impl Package {
         // sort and remove duplicate packages
         packages.sort_by_key(|a| a.name.clone());
         packages.dedup_by_key(|a| a.name.clone());
-        packages
+        Ok(packages)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a config file for a test and returns its path
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ruxgo-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn config_message(result: Result<(BuildConfig, OSConfig, Vec<TargetConfig>)>) -> String {
+        match result {
+            Err(RuxgoError::Config(msg)) => msg,
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn defaults_fill_in_the_missing_keys() {
+        let path = write_config("defaults", concat!(
+            "[build]\ncompiler = \"gcc\"\n",
+            "[os]\nname = \"ruxos\"\nulib = \"ruxlibc\"\nservices = [\"fs\"]\n",
+            "[[targets]]\nname = \"main\"\ntype = \"exe\"\n",
+        ));
+        let (build_config, os_config, targets) = parse_config(&path, false).unwrap();
+        assert!(build_config.packages.is_empty());
+        assert_eq!(os_config.features, ["fs", "fd"]);
+        assert_eq!(os_config.platform.name, "x86_64-qemu-q35");
+        assert_eq!(os_config.platform.mode, "release");
+        assert_eq!(os_config.platform.cross_compile, "x86_64-linux-musl-");
+        // the compiler of an os build is the cross compiler of its platform
+        assert_eq!(*build_config.compiler.read().unwrap(), "x86_64-linux-musl-gcc");
+        assert_eq!(targets[0].include_dir, "./");
+        assert!(targets[0].deps.is_empty());
+    }
+
+    #[test]
+    fn c_and_cxx_compilers_are_derived_from_the_compiler() {
+        assert_eq!((c_driver("g++"), cxx_driver("g++")), ("gcc".to_string(), "g++".to_string()));
+        assert_eq!((c_driver("clang"), cxx_driver("clang")), ("clang".to_string(), "clang++".to_string()));
+        assert_eq!(cxx_driver("x86_64-linux-musl-gcc"), "x86_64-linux-musl-g++");
+        assert_eq!(cxx_driver("cc"), "c++");
+        // unknown compilers are used for every language
+        assert_eq!((c_driver("tcc-wrapper"), cxx_driver("icx")), ("tcc-wrapper".to_string(), "icx".to_string()));
+
+        let path = write_config("drivers", concat!(
+            "[build]\ncompiler = \"g++\"\ncc = \"clang\"\n",
+            "[os]\nname = \"ruxos\"\nulib = \"ruxlibc\"\n",
+            "[[targets]]\nname = \"main\"\ntype = \"exe\"\n",
+        ));
+        let (build_config, _, _) = parse_config(&path, false).unwrap();
+        // the cc set in the config is kept, and every compiler gets the cross compile prefix
+        assert_eq!(*build_config.cc.read().unwrap(), "x86_64-linux-musl-clang");
+        assert_eq!(*build_config.cxx.read().unwrap(), "x86_64-linux-musl-g++");
+    }
+
+    #[test]
+    fn flags_fall_back_to_cflags_for_every_language() {
+        let target: TargetConfig = toml::from_str("name = \"a\"\ntype = \"exe\"\ncflags = \"-std=c11\"").unwrap();
+        assert_eq!(target.flags("c++"), "-std=c11");
+        assert_eq!(target.flags("assembler"), "-std=c11");
+
+        let target: TargetConfig = toml::from_str(
+            "name = \"a\"\ntype = \"exe\"\ncflags = \"-std=c11\"\ncxxflags = \"-std=c++17\"\nasflags = \"\"",
+        ).unwrap();
+        assert_eq!(target.flags("c"), "-std=c11");
+        assert_eq!(target.flags("c++"), "-std=c++17");
+        assert_eq!(target.flags("assembler-with-cpp"), "");
+    }
+
+    #[test]
+    fn unknown_key_points_at_its_line_and_suggests_the_closest_one() {
+        let path = write_config("unknown-key", "[build]\ncompiler = \"gcc\"\n\n[[targets]]\nname = \"a\"\nsrcs = \"src\"\ntype = \"exe\"\n");
+        let msg = config_message(parse_config(&path, false));
+        assert!(msg.starts_with(&format!("{}:6:1: unknown field `srcs`, expected one of ", path)), "{}", msg);
+        assert!(msg.ends_with(", did you mean `src`?\n  |\n6 | srcs = \"src\"\n  | ^^^^"), "{}", msg);
+    }
+
+    #[test]
+    fn invalid_value_is_underlined_with_the_closest_allowed_one() {
+        let path = write_config("invalid-value", "[build]\ncompiler = \"gcc\"\n[[targets]]\nname = \"a\"\ntype = \"stati\"\n");
+        let msg = config_message(parse_config(&path, false));
+        assert_eq!(msg, format!(
+            "{}:5:8: invalid value: string \"stati\", expected one of `exe`, `dll`, `static`, `object`, \
+             did you mean `static`?\n  |\n5 | type = \"stati\"\n  |        ^^^^^^^",
+            path
+        ));
+    }
+
+    #[test]
+    fn error_column_counts_characters() {
+        let contents = "name = \"a\"\ntype = \"exe\"\ndeps = [\"\u{e9}\", 3]\n";
+        let err = toml::from_str::<TargetConfig>(contents).unwrap_err();
+        match config_error("config_linux.toml", contents, &err) {
+            RuxgoError::Config(msg) => assert!(msg.starts_with("config_linux.toml:3:14: invalid type: integer `3`"), "{}", msg),
+            other => panic!("expected a config error, got {:?}", other),
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn src_language_follows_the_extension_map_of_the_target() {
+        let target: TargetConfig = toml::from_str("name = \"a\"\ntype = \"exe\"").unwrap();
+        assert_eq!(target.src_language("./src/main.cc"), Some("c++"));
+        assert_eq!(target.src_language("./src/entry.S"), Some("assembler-with-cpp"));
+        assert_eq!(target.src_language("./src/entry.s"), Some("assembler"));
+        assert_eq!(target.src_language("./src/main.h"), None);
+        assert_eq!(target.src_language("./src/Makefile"), None);
+
+        // a map set by the target replaces the default one
+        let target: TargetConfig =
+            toml::from_str("name = \"a\"\ntype = \"exe\"\nsrc_extensions = { \".asm\" = \"assembler-with-cpp\", c = \"c\" }").unwrap();
+        assert_eq!(target.src_language("./src/entry.asm"), Some("assembler-with-cpp"));
+        assert_eq!(target.src_language("./src/main.cpp"), None);
+    }
+
+    #[test]
+    fn unknown_src_language_is_rejected() {
+        let path = write_config("src-language", concat!(
+            "[build]\ncompiler = \"gcc\"\n",
+            "[[targets]]\nname = \"a\"\ntype = \"exe\"\nsrc_extensions = { f90 = \"fortran\" }\n",
+        ));
+        let msg = config_message(parse_config(&path, false));
+        assert!(msg.contains("invalid value: string \"fortran\", expected one of `c`, `c++`, `assembler`, `assembler-with-cpp`"), "{}", msg);
+    }
+
+    #[test]
+    fn schema_lists_the_allowed_values_and_rejects_unknown_keys() {
+        let schema: serde_json::Value = serde_json::from_str(&config_schema().unwrap()).unwrap();
+        assert_eq!(schema["required"], serde_json::json!(["build", "targets"]));
+        assert_eq!(schema["additionalProperties"], false);
+        let definitions = &schema["definitions"];
+        assert_eq!(definitions["TargetConfig"]["properties"]["type"]["enum"], serde_json::json!(TARGET_TYPES));
+        assert_eq!(definitions["TargetConfig"]["additionalProperties"], false);
+        assert_eq!(definitions["OSConfig"]["properties"]["services"]["items"]["enum"], serde_json::json!(KNOWN_SERVICES));
+    }
+
+    #[test]
+    fn suggest_key_only_suggests_close_names() {
+        let unknown = "unknown field `IP`, expected one of `blk`, `net`, `ip`";
+        assert_eq!(suggest_key(unknown).as_deref(), Some("ip"));
+        let invalid = "invalid value: string \"Exe\", expected one of `exe`, `dll`";
+        assert_eq!(suggest_key(invalid).as_deref(), Some("exe"));
+        assert_eq!(suggest_key("unknown field `foo`, expected one of `name`, `src`"), None);
+        assert_eq!(suggest_key("missing field `type`"), None);
+        assert_eq!(suggest_key("invalid type: integer `3`, expected a string"), None);
+    }
+
+    #[test]
+    fn missing_config_file_is_an_io_error() {
+        let path = std::env::temp_dir().join(format!("ruxgo-{}-missing.toml", std::process::id()));
+        match parse_config(path.to_str().unwrap(), false) {
+            Err(RuxgoError::Io { source, .. }) => assert_eq!(source.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("expected an io error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
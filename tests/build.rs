@@ -0,0 +1,764 @@
+//! Builds small projects with the ruxgo binary and checks what it did
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A project in a temporary directory, built by the ruxgo binary
+struct Project {
+    root: PathBuf,
+}
+
+impl Project {
+    /// Creates an empty project holding only its config
+    fn new(name: &str, config: &str) -> Project {
+        let root = std::env::temp_dir().join(format!("ruxgo-it-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let project = Project { root };
+        project.file("config_linux.toml", config);
+        project
+    }
+
+    /// Writes a file of the project, creating its directory
+    fn file(&self, path: &str, contents: &str) -> &Project {
+        let path = self.root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        self
+    }
+
+    /// Runs ruxgo in the project, returns whether it succeeded and what it printed
+    fn ruxgo(&self, args: &[&str]) -> (bool, String) {
+        self.ruxgo_env(args, &[])
+    }
+
+    /// Runs ruxgo in the project with additional environment variables
+    fn ruxgo_env(&self, args: &[&str], envs: &[(&str, &str)]) -> (bool, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_ruxgo"))
+            .args(args)
+            .current_dir(&self.root)
+            // keeps the global config of the user untouched
+            .env("XDG_CONFIG_HOME", self.root.join(".config"))
+            .env("NO_COLOR", "1")
+            .env_remove("RUXGO_LOG_LEVEL")
+            .env_remove("RUXGO_JOBS")
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        let printed = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        (output.status.success(), printed)
+    }
+
+    /// Runs a binary built in the project and returns what it printed
+    fn run(&self, bin: &str) -> String {
+        let output = Command::new(self.root.join(bin)).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.root.join(path).exists()
+    }
+}
+
+/// Returns the first command printed by ruxgo containing `part`
+fn command<'a>(printed: &'a str, part: &str) -> &'a str {
+    printed
+        .lines()
+        .filter_map(|line| line.split_once("Command: ").map(|(_, cmd)| cmd))
+        .find(|cmd| cmd.contains(part))
+        .unwrap_or_else(|| panic!("no command with {}:\n{}", part, printed))
+}
+
+/// The config of a project with an exe linking one of its two libs
+const LIBS_CONFIG: &str = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "main"
+src = "./src"
+include_dir = "./inc"
+type = "exe"
+deps = ["libfoo"]
+
+[[targets]]
+name = "libfoo"
+src = "./foo"
+include_dir = "./inc"
+type = "static"
+archive = "ar"
+ldflags = "rcs"
+
+[[targets]]
+name = "libbar"
+src = "./bar"
+include_dir = "./inc"
+type = "static"
+archive = "ar"
+ldflags = "rcs"
+"#;
+
+fn libs_project(name: &str) -> Project {
+    let project = Project::new(name, LIBS_CONFIG);
+    project
+        .file("inc/libs.h", "const char *foo(void);\nconst char *bar(void);\n")
+        .file("foo/foo.c", "#include \"libs.h\"\nconst char *foo(void) { return \"foo\"; }\n")
+        .file("bar/bar.c", "#include \"libs.h\"\nconst char *bar(void) { return \"bar\"; }\n")
+        .file("src/main.c", "#include <stdio.h>\n#include \"libs.h\"\nint main(void) { puts(foo()); return 0; }\n");
+    project
+}
+
+#[test]
+fn exe_is_linked_with_the_lib_built_before_it() {
+    let project = libs_project("link-order");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "foo\n");
+    assert!(project.exists("ruxos_bld/bin/libbar.a"));
+}
+
+#[test]
+fn independent_targets_are_prepared_together() {
+    let project = libs_project("parallel-prepare");
+    let (ok, printed) = project.ruxgo(&["build", "-j", "4", "--gen-cc"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "foo\n");
+    let compile_commands = fs::read_to_string(project.root.join("compile_commands.json")).unwrap();
+    for src in ["src/main.c", "foo/foo.c", "bar/bar.c"] {
+        let file = format!("\"file\": \"{}\"\n", project.root.join(src).display());
+        assert_eq!(compile_commands.matches(&file).count(), 1, "{}", compile_commands);
+    }
+}
+
+#[test]
+fn failing_source_fails_the_build_before_its_dependents_link() {
+    let project = libs_project("link-failure");
+    project.file("foo/foo.c", "const char *foo(void) { return }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Failed to compile: ./foo/foo.c"), "{}", printed);
+    assert!(!project.exists("ruxos_bld/bin/main.bin"));
+}
+
+/// The config of a project with a single exe
+const EXE_CONFIG: &str = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "main"
+src = "./src"
+include_dir = "./inc"
+type = "exe"
+"#;
+
+#[test]
+fn changed_header_recompiles_only_the_sources_including_it() {
+    let project = Project::new("header-change", EXE_CONFIG);
+    project
+        .file("inc/answer.h", "#define ANSWER 1\n")
+        .file("src/main.c", "#include \"answer.h\"\nint main(void) { return ANSWER; }\n")
+        .file("src/util.c", "int util(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+
+    project.file("inc/answer.h", "#define ANSWER 42\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("1 of 2 source files have to be compiled"), "{}", printed);
+    assert!(printed.contains("Compiled: ./src/main.c"), "{}", printed);
+
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Target: main is up to date"), "{}", printed);
+}
+
+#[test]
+fn changed_flags_rebuild_the_objects_and_changed_ldflags_only_relink() {
+    let project = Project::new("flags-change", EXE_CONFIG);
+    project.file("src/main.c", "int main(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+
+    project.file("config_linux.toml", &format!("{}cflags = \"-O2\"\n", EXE_CONFIG));
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Compiled: ./src/main.c"), "{}", printed);
+
+    project.file("config_linux.toml", &format!("{}cflags = \"-O2\"\nldflags = \"-s\"\n", EXE_CONFIG));
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(!printed.contains("Compiled:"), "{}", printed);
+    assert!(printed.contains("Linking: main"), "{}", printed);
+
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Target: main is up to date"), "{}", printed);
+}
+
+#[test]
+fn sources_sharing_a_name_are_all_linked() {
+    let project = Project::new("same-names", EXE_CONFIG);
+    project
+        .file("src/main.c", "#include <stdio.h>\nint net(void);\nint fs(void);\nint main(void) { printf(\"%d\\n\", net() + fs()); return 0; }\n")
+        .file("src/net/util.c", "int net(void) { return 1; }\n")
+        .file("src/fs/util.c", "int fs(void) { return 2; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "3\n");
+}
+
+#[test]
+fn clean_rebuild_takes_the_objects_from_the_cache() {
+    let project = Project::new("object-cache", EXE_CONFIG);
+    project.file("src/main.c", "int main(void) { return 0; }\n");
+    let cache_dir = project.root.join("objcache");
+    assert!(project.ruxgo(&["config", "cache", "true"]).0);
+    assert!(project.ruxgo(&["config", "cache_dir", cache_dir.to_str().unwrap()]).0);
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(!printed.contains("Cache hit"), "{}", printed);
+
+    fs::remove_dir_all(project.root.join("ruxos_bld")).unwrap();
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Cache hit: main"), "{}", printed);
+    assert!(project.exists("ruxos_bld/bin/main.bin"));
+
+    let (ok, printed) = project.ruxgo(&["cache", "stats"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Hits:     1\nMisses:   1\n"), "{}", printed);
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn assembly_and_mapped_extensions_are_compiled_as_their_language() {
+    let config = format!("{}src_extensions = {{ c = \"c\", S = \"assembler-with-cpp\", inc = \"c\" }}\n", EXE_CONFIG);
+    let project = Project::new("languages", &config);
+    project
+        .file("src/main.c", "#include <stdio.h>\nint answer(void);\nint twice(int);\nint main(void) { printf(\"%d\\n\", twice(answer())); return 0; }\n")
+        .file("src/answer.S", "#define ANSWER 21\n.globl answer\nanswer:\n    mov $ANSWER, %eax\n    ret\n.section .note.GNU-stack,\"\",@progbits\n")
+        .file("src/twice.inc", "int twice(int x) { return 2 * x; }\n")
+        .file("src/notes.txt", "not a source\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    // gcc only has to be told the language of the extensions it does not know
+    assert!(printed.contains("-x c -c ./src/twice.inc"), "{}", printed);
+    assert!(printed.contains(" -c ./src/answer.S"), "{}", printed);
+    assert!(!printed.contains("notes.txt"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "42\n");
+}
+
+#[test]
+fn exe_linking_a_cxx_lib_is_linked_by_the_cxx_compiler() {
+    let config = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "main"
+src = "./src"
+include_dir = "./inc"
+type = "exe"
+cflags = "-DLANG=c"
+deps = ["libgreet"]
+
+[[targets]]
+name = "libgreet"
+src = "./greet"
+include_dir = "./inc"
+type = "static"
+cflags = "-DLANG=c"
+cxxflags = "-DLANG=cxx"
+archive = "ar"
+ldflags = "rcs"
+"#;
+    let project = Project::new("cxx-link", config);
+    project
+        .file("inc/greet.h", "#ifdef __cplusplus\nextern \"C\"\n#endif\nconst char *greet(void);\n")
+        .file("greet/greet.cpp", "#include <string>\n#include \"greet.h\"\nstatic std::string msg(\"hello\");\nconst char *greet(void) { return msg.c_str(); }\n")
+        .file("src/main.c", "#include <stdio.h>\n#include \"greet.h\"\nint main(void) { puts(greet()); return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    let greet = command(&printed, "-c ./greet/greet.cpp");
+    assert!(greet.starts_with("g++ ") && greet.contains(" -DLANG=cxx "), "{}", greet);
+    let main = command(&printed, "-c ./src/main.c");
+    assert!(main.starts_with("gcc ") && main.contains(" -DLANG=c "), "{}", main);
+    assert!(command(&printed, "-o ruxos_bld/bin/main.bin").starts_with("g++ "), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello\n");
+}
+
+#[test]
+fn compile_commands_name_the_compiler_of_each_language() {
+    let project = Project::new("gen-cc", &EXE_CONFIG.replace("compiler = \"gcc\"", "compiler = \"x86_64-linux-gnu-gcc\""));
+    project
+        .file("src/main.c", "int greet(void);\nint main(void) { return greet(); }\n")
+        .file("src/greet.cpp", "extern \"C\" int greet(void) { return 0; }\n");
+    // written before anything is compiled, whether the build succeeds or not
+    project.ruxgo(&["build", "--gen-cc"]);
+    let compile_commands = fs::read_to_string(project.root.join("compile_commands.json")).unwrap();
+    assert!(compile_commands.contains("\"command\": \"x86_64-linux-gnu-gcc -c -o ruxos_bld/obj_linux/main/src/main.c.o"), "{}", compile_commands);
+    assert!(compile_commands.contains("\"command\": \"x86_64-linux-gnu-g++ -c -o ruxos_bld/obj_linux/main/src/greet.cpp.o"), "{}", compile_commands);
+}
+
+#[test]
+fn pch_is_included_first_and_rebuilt_with_its_header() {
+    let project = Project::new("pch", &format!("{}pch = \"src/common.h\"\n", EXE_CONFIG));
+    project
+        .file("src/common.h", "#include <stdio.h>\n#define GREETING \"hello\"\n")
+        .file("src/main.c", "void greet(void);\nint main(void) { greet(); return 0; }\n")
+        .file("src/greet.c", "void greet(void) { puts(GREETING); }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Precompiling header: src/common.h"), "{}", printed);
+    let greet = command(&printed, "-c ./src/greet.c");
+    assert!(greet.contains(" -include ruxos_bld/obj_linux/main/pch/common.h "), "{}", greet);
+    assert!(project.exists("ruxos_bld/obj_linux/main/pch/common.h.gch"));
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello\n");
+
+    project.file("src/common.h", "#include <stdio.h>\n#define GREETING \"hello again\"\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Precompiling header: src/common.h"), "{}", printed);
+    assert!(printed.contains("2 of 2 source files have to be compiled"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello again\n");
+
+    // the objects recorded the header they were compiled with
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Target: main is up to date"), "{}", printed);
+}
+
+#[test]
+fn unity_merges_the_sources_in_batches_but_the_excluded_ones() {
+    let config = format!("{}unity = true\nunity_batch = 2\nunity_excluded = [\"clash.c\"]\n", EXE_CONFIG);
+    let project = Project::new("unity", &config);
+    project
+        .file("src/main.c", "#include <stdio.h>\nint a(void);\nint b(void);\nint c(void);\nint main(void) { printf(\"%d\\n\", a() + b() + c()); return 0; }\n")
+        .file("src/a.c", "static int helper(void) { return 1; }\nint a(void) { return helper(); }\n")
+        .file("src/b.c", "int b(void) { return 20; }\n")
+        // its static helper clashes with the one of a.c once merged
+        .file("src/clash.c", "static int helper(void) { return 300; }\nint c(void) { return helper(); }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "321\n");
+    let unity = |name: &str| fs::read_to_string(project.root.join("ruxos_bld/obj_linux/main/unity").join(name)).unwrap();
+    let (first, second) = (unity("unity_0.c"), unity("unity_1.c"));
+    assert!(first.contains("/src/a.c\"\n") && first.contains("/src/b.c\"\n"), "{}", first);
+    assert!(second.contains("/src/main.c\"\n") && !second.contains("clash.c"), "{}", second);
+    assert!(!project.exists("ruxos_bld/obj_linux/main/unity/unity_2.c"));
+    assert!(command(&printed, "-c ./src/clash.c").contains("clash.c.o"), "{}", printed);
+
+    project.file("src/b.c", "int b(void) { return 40; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("1 of 3 source files have to be compiled"), "{}", printed);
+    assert!(printed.contains("unity_0.c"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "341\n");
+}
+
+#[test]
+fn paths_and_flags_with_spaces_reach_the_compiler_as_they_are() {
+    let config = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "main"
+src = "./my src"
+include_dir = "./my inc"
+type = "exe"
+cflags = "-DGREETING='\"hello world\"'"
+"#;
+    let project = Project::new("with spaces", config);
+    project
+        .file("my inc/greeting.h", "#include <stdio.h>\n")
+        .file("my src/main.c", "#include \"greeting.h\"\nint main(void) { puts(GREETING); return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    let main = command(&printed, "main.c");
+    assert!(main.contains(" '-DGREETING=\"hello world\"' ") && main.contains(" '-I./my inc' "), "{}", main);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello world\n");
+}
+
+#[test]
+fn warning_of_a_shared_header_is_reported_once() {
+    let project = Project::new("diagnostics", &format!("{}cflags = \"-Wall\"\n", EXE_CONFIG));
+    project
+        .file("inc/shared.h", "static int unused_in_header;\n")
+        .file("src/main.c", "#include \"shared.h\"\nint util(void);\nint main(void) { return util(); }\n")
+        .file("src/util.c", "#include \"shared.h\"\nint util(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("1 warning(s) and 0 error(s) emitted by the compiler in 1 file(s):"), "{}", printed);
+    assert!(printed.contains("  ./inc/shared.h: 1 diagnostic(s)\n"), "{}", printed);
+    assert!(printed.contains("    -Wunused-variable (1)\n"), "{}", printed);
+}
+
+#[test]
+fn keep_going_builds_what_does_not_depend_on_a_failure() {
+    let config = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "main"
+src = "./src"
+type = "exe"
+deps = ["libfoo"]
+
+[[targets]]
+name = "libfoo"
+src = "./foo"
+type = "static"
+archive = "ar"
+ldflags = "rcs"
+
+[[targets]]
+name = "libbar"
+src = "./bar"
+type = "static"
+archive = "ar"
+ldflags = "rcs"
+
+[[targets]]
+name = "libok"
+src = "./ok"
+type = "static"
+archive = "ar"
+ldflags = "rcs"
+"#;
+    let project = Project::new("keep-going", config);
+    project
+        .file("src/main.c", "int foo(void);\nint main(void) { return foo(); }\n")
+        .file("foo/foo.c", "int foo(void) { return }\n")
+        .file("foo/good.c", "int good(void) { return 0; }\n")
+        .file("bar/bar.c", "int bar(void) { return }\n")
+        .file("ok/ok.c", "int ok(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--keep-going"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Build failed with 2 error(s)"), "{}", printed);
+    assert!(printed.contains("Failed to compile: ./foo/foo.c"), "{}", printed);
+    assert!(printed.contains("Failed to compile: ./bar/bar.c"), "{}", printed);
+    assert!(printed.contains("Skipped targets depending on a failed one: main"), "{}", printed);
+    assert!(project.exists("ruxos_bld/bin/libok.a"));
+    assert!(!project.exists("ruxos_bld/bin/main.bin"));
+
+    // the sources that did compile are not compiled again
+    project.file("foo/foo.c", "int foo(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--keep-going"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("1 of 2 source files have to be compiled"), "{}", printed);
+    assert!(!printed.contains("Compiled: ./foo/good.c"), "{}", printed);
+    // a single failure is reported as is
+    assert!(!printed.contains("Build failed with"), "{}", printed);
+    assert!(printed.contains("Failed to compile: ./bar/bar.c"), "{}", printed);
+    assert!(project.exists("ruxos_bld/bin/main.bin"));
+}
+
+#[test]
+fn objects_compiled_before_a_failure_are_not_compiled_again() {
+    let project = Project::new("failure-objects", EXE_CONFIG);
+    project
+        .file("src/main.c", "int util(void);\nint main(void) { return util(); }\n")
+        .file("src/util.c", "int util(void) { return }\n");
+    let (ok, printed) = project.ruxgo(&["build", "-j", "1"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Compiled: ./src/main.c"), "{}", printed);
+
+    project.file("src/util.c", "int util(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("1 of 2 source files have to be compiled"), "{}", printed);
+    assert!(!printed.contains("Compiled: ./src/main.c"), "{}", printed);
+}
+
+#[test]
+fn job_count_comes_from_the_command_line_or_the_environment() {
+    let project = Project::new("jobs", EXE_CONFIG);
+    project.file("src/main.c", "int main(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo_env(&["build", "-j", "3"], &[("RUXGO_JOBS", "5")]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Running up to 3 jobs in parallel"), "{}", printed);
+
+    let (ok, printed) = project.ruxgo_env(&["build"], &[("RUXGO_JOBS", "5")]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Running up to 5 jobs in parallel"), "{}", printed);
+
+    let (ok, printed) = project.ruxgo_env(&["build"], &[("RUXGO_JOBS", "many")]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Invalid RUXGO_JOBS: many, expected a number of jobs"), "{}", printed);
+}
+
+#[test]
+fn dry_run_lists_the_steps_without_running_them() {
+    let project = Project::new("dry-run", EXE_CONFIG);
+    project.file("src/main.c", "int main(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--dry-run", "--explain"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Would compile ./src/main.c (missing output ruxos_bld/obj_linux/main/src/main.c.o)\n    gcc "), "{}", printed);
+    assert!(printed.contains("Would link ruxos_bld/bin/main.bin (missing output ruxos_bld/bin/main.bin)\n    gcc "), "{}", printed);
+    assert!(!project.exists("ruxos_bld/obj_linux/main/src/main.c.o"));
+    assert!(!project.exists("ruxos_bld/bin/main.bin"));
+}
+
+#[test]
+fn dry_run_lists_the_files_it_would_generate_without_writing_them() {
+    let config = format!("{}unity = true\npch = \"src/common.h\"\n", EXE_CONFIG);
+    let project = Project::new("dry-run-generated", &config);
+    project
+        .file("src/common.h", "#define ANSWER 0\n")
+        .file("src/main.c", "int main(void) { return ANSWER; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--dry-run", "--gen-cc", "--gen-vsc"]);
+    assert!(ok, "{}", printed);
+    for generated in [
+        "compile_commands.json",
+        ".vscode/c_cpp_properties.json",
+        "ruxos_bld/obj_linux/main/pch/common.h",
+        "ruxos_bld/obj_linux/main/unity/unity_0.c",
+    ] {
+        assert!(printed.contains(&format!("Would write {}\n", generated)), "{}", printed);
+        assert!(!project.exists(generated), "{}", generated);
+    }
+}
+
+#[test]
+fn explain_tells_why_each_step_runs() {
+    let project = Project::new("explain", EXE_CONFIG);
+    project
+        .file("inc/answer.h", "#define ANSWER 1\n")
+        .file("src/main.c", "#include \"answer.h\"\nint main(void) { return ANSWER - 1; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+
+    project.file("inc/answer.h", "#define ANSWER 2\n");
+    let (ok, printed) = project.ruxgo(&["build", "--explain"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Explain: compile ./src/main.c: changed input inc/answer.h"), "{}", printed);
+    assert!(printed.contains("Explain: link ruxos_bld/bin/main.bin: changed input ruxos_bld/obj_linux/main/src/main.c.o"), "{}", printed);
+
+    project.file("config_linux.toml", &format!("{}cflags = \"-O1\"\n", EXE_CONFIG));
+    let (ok, printed) = project.ruxgo(&["build", "--explain"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Explain: compile ./src/main.c: changed flags"), "{}", printed);
+}
+
+#[test]
+fn timings_list_the_slowest_steps_and_write_a_trace() {
+    let project = Project::new("timings", EXE_CONFIG);
+    project
+        .file("src/main.c", "int util(void);\nint main(void) { return util(); }\n")
+        .file("src/util.c", "int util(void) { return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--timings", "trace.json"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Slowest 3 of 3 steps, over "), "{}", printed);
+    let trace = fs::read_to_string(project.root.join("trace.json")).unwrap();
+    for step in ["\"cat\":\"compile\",", "\"name\":\"./src/util.c\"", "\"cat\":\"link\","] {
+        assert!(trace.contains(step), "{}", trace);
+    }
+
+    // an up to date build has no step to time
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(!printed.contains("Slowest"), "{}", printed);
+}
+
+#[test]
+fn targets_are_relinked_only_when_what_they_link_changed() {
+    let project = libs_project("relink");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+
+    // compiled again to the same object, nothing is linked
+    project.file("foo/foo.c", "#include \"libs.h\"\nconst char *foo(void) { return \"foo\"; } /* same code */\n");
+    let (ok, printed) = project.ruxgo(&["build", "--explain"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Compiled: ./foo/foo.c"), "{}", printed);
+    assert!(printed.contains("Linking skipped, the inputs of libfoo did not change"), "{}", printed);
+    assert!(!printed.contains("Explain: link ruxos_bld/bin/main.bin"), "{}", printed);
+
+    project.file("foo/foo.c", "#include \"libs.h\"\nconst char *foo(void) { return \"FOO\"; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "--explain"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Explain: link ruxos_bld/bin/main.bin: changed dependency ruxos_bld/bin/libfoo.a"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "FOO\n");
+
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Target: main is up to date"), "{}", printed);
+}
+
+#[test]
+fn selected_targets_are_built_run_and_cleaned_alone() {
+    let config = format!("{}\n[[targets]]\nname = \"tool\"\nsrc = \"./tool\"\ninclude_dir = \"./inc\"\ntype = \"exe\"\ndeps = [\"libbar\"]\n", LIBS_CONFIG);
+    let project = libs_project("select");
+    project
+        .file("config_linux.toml", &config)
+        .file("tool/tool.c", "#include <stdio.h>\n#include \"libs.h\"\nint main(void) { puts(bar()); return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["build", "-p", "tool"]);
+    assert!(ok, "{}", printed);
+    assert!(project.exists("ruxos_bld/bin/libbar.a") && project.exists("ruxos_bld/bin/tool.bin"));
+    assert!(!project.exists("ruxos_bld/bin/libfoo.a") && !project.exists("ruxos_bld/bin/main.bin"));
+
+    let (ok, printed) = project.ruxgo(&["run", "tool"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("bar\n"), "{}", printed);
+    let (ok, printed) = project.ruxgo(&["run"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Several executable targets found: main, tool"), "{}", printed);
+
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    let (ok, printed) = project.ruxgo(&["clean", "-p", "tool"]);
+    assert!(ok, "{}", printed);
+    assert!(!project.exists("ruxos_bld/bin/tool.bin") && !project.exists("ruxos_bld/obj_linux/tool"));
+    assert!(project.exists("ruxos_bld/bin/libbar.a") && project.exists("ruxos_bld/bin/main.bin"));
+
+    let (ok, printed) = project.ruxgo(&["build", "-p", "nope"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("Target nope does not exist"), "{}", printed);
+}
+
+#[test]
+fn deprecated_options_still_work_and_global_options_apply_anywhere() {
+    let project = Project::new("command-line", EXE_CONFIG);
+    project.file("src/main.c", "#include <stdio.h>\nint main(int argc, char **argv) { puts(argc > 1 ? argv[1] : \"none\"); return 0; }\n");
+    let (ok, printed) = project.ruxgo(&["-b", "-r", "--bin-args=hi"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("[WARN] -b/--build is deprecated, use `ruxgo build` instead"), "{}", printed);
+    assert!(printed.contains("[WARN] -r/--run is deprecated, use `ruxgo run` instead"), "{}", printed);
+    assert!(printed.contains("hi\n"), "{}", printed);
+
+    // the project is found from another directory, and the global options follow the subcommand
+    fs::remove_dir_all(project.root.join("ruxos_bld")).unwrap();
+    let manifest = project.root.join("config_linux.toml");
+    let (ok, printed) = project.ruxgo(&["--manifest-path", "src/../config_linux.toml", "run", "--quiet", "--", "there"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(printed, "there\n");
+    let (ok, printed) = project.ruxgo(&["clean", "--manifest-path", manifest.to_str().unwrap(), "All"]);
+    assert!(ok, "{}", printed);
+    assert!(!project.exists("ruxos_bld/bin/main.bin"));
+
+    let (ok, printed) = project.ruxgo(&["--manifest-path", "src/main.c", "build"]);
+    assert!(!ok, "{}", printed);
+    assert!(printed.contains("The manifest path must point to a config_linux.toml file: src/main.c"), "{}", printed);
+    let (ok, printed) = project.ruxgo(&["build", "--verbose", "--quiet"]);
+    assert!(!ok, "{}", printed);
+}
+
+#[test]
+fn schema_can_be_piped_into_a_reader_that_stops_early() {
+    let project = Project::new("schema", EXE_CONFIG);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ruxgo"))
+        .args(["check-config", "--schema"])
+        .current_dir(&project.root)
+        .env("XDG_CONFIG_HOME", project.root.join(".config"))
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    // closes the pipe before reading anything, like `| head -0`
+    drop(child.stdout.take());
+    assert!(child.wait().unwrap().success());
+
+    let (ok, printed) = project.ruxgo(&["check-config", "--schema"]);
+    assert!(ok, "{}", printed);
+    let schema: serde_json::Value = serde_json::from_str(&printed).unwrap();
+    assert!(schema["properties"]["targets"].is_object(), "{}", printed);
+}
+
+/// A `ruxgo cache-server` serving a directory of the project, killed when dropped
+struct CacheServer {
+    child: std::process::Child,
+    url: String,
+}
+
+impl CacheServer {
+    fn start(project: &Project) -> CacheServer {
+        // the port is free once the listener picking it is dropped
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_ruxgo"))
+            .args(["cache-server", "--dir", project.root.join("remote").to_str().unwrap(), "--addr", &addr.to_string()])
+            .env("XDG_CONFIG_HOME", project.root.join(".config"))
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        for _ in 0..100 {
+            if std::net::TcpStream::connect(addr).is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        CacheServer { child, url: format!("http://{}", addr) }
+    }
+}
+
+impl Drop for CacheServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn dll_is_taken_from_the_remote_cache_only_if_its_packages_did_not_change() {
+    let config = r#"
+[build]
+compiler = "gcc"
+packages = ["user/pkg main"]
+
+[[targets]]
+name = "main"
+src = "./src"
+include_dir = "./inc"
+type = "exe"
+deps = ["libgreet"]
+
+[[targets]]
+name = "libgreet"
+src = "./greet"
+include_dir = "./inc"
+type = "dll"
+cflags = "-fPIC"
+"#;
+    let package_config = r#"
+[build]
+compiler = "gcc"
+
+[[targets]]
+name = "libword"
+src = "./src"
+include_dir = "./inc"
+type = "static"
+cflags = "-fPIC"
+archive = "ar"
+ldflags = "rcs"
+"#;
+    let project = Project::new("remote-dll", config);
+    // a package already in place is not cloned
+    project
+        .file("ruxos_bld/packages/pkg/config_linux.toml", package_config)
+        .file("ruxos_bld/packages/pkg/src/word.c", "const char *word(void) { return \"hello\"; }\n")
+        .file("inc/greet.h", "const char *word(void);\nconst char *greet(void);\n")
+        .file("greet/greet.c", "#include \"greet.h\"\nconst char *greet(void) { return word(); }\n")
+        .file("src/main.c", "#include <stdio.h>\n#include \"greet.h\"\nint main(void) { puts(greet()); return 0; }\n");
+    let server = CacheServer::start(&project);
+    assert!(project.ruxgo(&["config", "remote_cache", &server.url]).0);
+    assert!(project.ruxgo(&["config", "remote_cache_mode", "read-write"]).0);
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello\n");
+
+    // the dll is linked again with the new package lib, not taken from the cache
+    project.file("ruxos_bld/packages/pkg/src/word.c", "const char *word(void) { return \"bye\"; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(!printed.contains("ruxos_bld/bin/libgreet.so found in the remote cache"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "bye\n");
+
+    // the same inputs again
+    project.file("ruxos_bld/packages/pkg/src/word.c", "const char *word(void) { return \"hello\"; }\n");
+    let (ok, printed) = project.ruxgo(&["build"]);
+    assert!(ok, "{}", printed);
+    assert!(printed.contains("Linking skipped, ruxos_bld/bin/libgreet.so found in the remote cache"), "{}", printed);
+    assert_eq!(project.run("ruxos_bld/bin/main.bin"), "hello\n");
+}